@@ -4,3 +4,4 @@ set -e
 RUSTFLAGS='-C link-arg=-s' cargo build --all --target wasm32-unknown-unknown --release
 cp target/wasm32-unknown-unknown/release/usdnear.wasm res/
 
+cp target/wasm32-unknown-unknown/release/mock_oracle.wasm res/
@@ -0,0 +1,162 @@
+//! Mock price-oracle contract, to test USDNEAR pull_oracle_price locally
+//! implements `get_price_data` with the same interface as the NEAR DeFi price oracle
+//! prices are set by anyone calling `set_price`
+
+use near_sdk::{env, near_bindgen};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOC: near_sdk::wee_alloc::WeeAlloc = near_sdk::wee_alloc::WeeAlloc::INIT;
+
+/// price of the smallest unit of the asset in USD: multiplier / 10^decimals
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetOptionalPrice {
+    pub asset_id: String,
+    pub price: Option<Price>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    pub timestamp: U64,
+    pub recency_duration_sec: u32,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct MockOracle {
+    pub prices: UnorderedMap<String, Price>,
+    /// when the last price was set (nanoseconds)
+    pub timestamp: u64,
+    pub recency_duration_sec: u32,
+}
+
+impl Default for MockOracle {
+    fn default() -> Self {
+        env::panic(b"The contract is not initialized.");
+    }
+}
+
+#[near_bindgen]
+impl MockOracle {
+    #[init]
+    #[allow(clippy::needless_return)]
+    pub fn new(recency_duration_sec: u32) -> Self {
+        assert!(!env::state_exists(), "The contract is already initialized");
+        return Self {
+            prices: UnorderedMap::new(b"p".to_vec()),
+            timestamp: 0,
+            recency_duration_sec,
+        };
+    }
+
+    /// sets the price of an asset, e.g. for stNEAR at USD 4.10 with decimals 28: multiplier 41000
+    pub fn set_price(&mut self, asset_id: String, price: Price) {
+        self.prices.insert(&asset_id, &price);
+        self.timestamp = env::block_timestamp();
+    }
+
+    /// to test stale data, sets the timestamp of the prices
+    pub fn set_timestamp(&mut self, timestamp: U64) {
+        self.timestamp = timestamp.0;
+    }
+
+    #[allow(clippy::needless_return)]
+    pub fn get_price_data(&self, asset_ids: Option<Vec<String>>) -> PriceData {
+        let asset_ids = asset_ids.unwrap_or_else(|| self.prices.keys().collect());
+        return PriceData {
+            timestamp: self.timestamp.into(),
+            recency_duration_sec: self.recency_duration_sec,
+            prices: asset_ids
+                .into_iter()
+                .map(|asset_id| AssetOptionalPrice { price: self.prices.get(&asset_id), asset_id })
+                .collect(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{testing_env, MockedBlockchain, VMConfig, VMContext, RuntimeFeesConfig};
+
+    const NANOS_PER_SECOND: u64 = 1_000_000_000;
+    const STNEAR: &str = "meta-pool.near";
+
+    fn set_context(block_timestamp: u64) {
+        testing_env!(VMContext {
+            current_account_id: "oracle.near".into(),
+            signer_account_id: "alice.near".into(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id: "alice.near".into(),
+            input: vec![],
+            block_index: block_timestamp / NANOS_PER_SECOND,
+            block_timestamp,
+            epoch_height: 0,
+            account_balance: 0,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+        }, VMConfig::free(), RuntimeFeesConfig::free());
+    }
+
+    /// stNEAR at USD 4.10 set at 10 seconds
+    #[allow(clippy::needless_return)]
+    fn oracle_with_price() -> MockOracle {
+        set_context(NANOS_PER_SECOND);
+        let mut oracle = MockOracle::new(90);
+        set_context(10 * NANOS_PER_SECOND);
+        oracle.set_price(STNEAR.into(), Price { multiplier: 41000.into(), decimals: 28 });
+        return oracle;
+    }
+
+    #[test]
+    fn price_data_has_the_requested_prices() {
+        let oracle = oracle_with_price();
+        let data = oracle.get_price_data(Some(vec![STNEAR.into(), "wrap.near".into()]));
+        assert_eq!(data.timestamp.0, 10 * NANOS_PER_SECOND);
+        assert_eq!(data.recency_duration_sec, 90);
+        assert_eq!(data.prices.len(), 2);
+        let price = data.prices[0].price.as_ref().unwrap();
+        assert_eq!((data.prices[0].asset_id.as_str(), price.multiplier.0, price.decimals), (STNEAR, 41000, 28));
+        // assets without a price are returned with None
+        assert_eq!(data.prices[1].asset_id, "wrap.near");
+        assert!(data.prices[1].price.is_none());
+    }
+
+    #[test]
+    fn price_data_defaults_to_every_price() {
+        let oracle = oracle_with_price();
+        let data = oracle.get_price_data(None);
+        assert_eq!(data.prices.len(), 1);
+        assert_eq!(data.prices[0].asset_id, STNEAR);
+    }
+
+    #[test]
+    fn timestamp_can_be_set_to_test_stale_data() {
+        let mut oracle = oracle_with_price();
+        oracle.set_timestamp(NANOS_PER_SECOND.into());
+        assert_eq!(oracle.get_price_data(None).timestamp.0, NANOS_PER_SECOND);
+        // a new price refreshes it
+        set_context(20 * NANOS_PER_SECOND);
+        oracle.set_price(STNEAR.into(), Price { multiplier: 42000.into(), decimals: 28 });
+        assert_eq!(oracle.get_price_data(None).timestamp.0, 20 * NANOS_PER_SECOND);
+    }
+}
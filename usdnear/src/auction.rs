@@ -0,0 +1,463 @@
+//
+// DUTCH-AUCTION LIQUIDATIONS
+// the owner selects the liquidation mode:
+// - fixed_discount: `liquidate` & `liquidate_with_stability_pool` sell collateral at liquidaton_fee_basis_points discount
+// - auction: anyone calls `start_auction` for an account below min_collateral_basis_points. The account's locked stNEAR is put up
+//   at `start_premium_bp` of the liquidation price, decaying linearly to `floor_bp` in `duration`. Bidders buy stNEAR at the
+//   current auction price paying USDNEAR, which repays the loan (partial fills). The auction ends when the USDNEAR needed
+//   to restore collateral_basis_points is raised or the collateral is sold. An auction older than `duration` takes no bids
+//   and can be restarted. A bid on an account back at min_collateral_basis_points removes the auction instead
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+/// default start price: 110% of the liquidation price (no profit for early bidders)
+pub const DEFAULT_AUCTION_START_PREMIUM_BP: u32 = 110 * PERCENT_BP;
+/// default floor: 80% of the liquidation price at start
+pub const DEFAULT_AUCTION_FLOOR_BP: u32 = 80 * PERCENT_BP;
+/// default auction duration: 2 hours
+pub const DEFAULT_AUCTION_DURATION: Duration = 2 * ONE_HOUR;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidationMode {
+    FixedDiscount,
+    Auction,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionConfig {
+    /// start price, basis points of the liquidation price
+    pub start_premium_bp: u32,
+    /// floor price, basis points of the liquidation price at start
+    pub floor_bp: u32,
+    /// time to decay from start to floor
+    pub duration_seconds: U64String,
+}
+
+impl Default for AuctionConfig {
+    fn default() -> Self {
+        Self {
+            start_premium_bp: DEFAULT_AUCTION_START_PREMIUM_BP,
+            floor_bp: DEFAULT_AUCTION_FLOOR_BP,
+            duration_seconds: (DEFAULT_AUCTION_DURATION / NANOS_PER_SECOND).into(),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Auction {
+    pub started_at: Timestamp,
+    /// USD per stNEAR (24 decimals)
+    pub start_price: u128,
+    pub floor_price: u128,
+    pub duration: Duration,
+    /// USDNEAR still to raise to restore collateral_basis_points
+    pub usdnear_to_raise: u128,
+    /// stNEAR still for sale
+    pub stnear_for_sale: u128,
+}
+
+impl Auction {
+    /// linear decay from start_price to floor_price
+    pub fn price_at(&self, now: Timestamp) -> u128 {
+        let elapsed = now.saturating_sub(self.started_at);
+        if elapsed >= self.duration {
+            return self.floor_price;
+        }
+        return self.start_price - proportional(self.start_price - self.floor_price, elapsed as u128, self.duration as u128);
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        return now.saturating_sub(self.started_at) >= self.duration;
+    }
+
+    /// a bid at `now` for at most `max_stnear` and `max_usdnear`, on a loan owing `owed_usdnear` with `locked_stnear`
+    pub fn bid_at(&self, now: Timestamp, owed_usdnear: u128, locked_stnear: u128, max_stnear: u128, max_usdnear: u128) -> AuctionBid {
+        let price = self.price_at(now);
+        let usdnear_to_raise = std::cmp::min(self.usdnear_to_raise, owed_usdnear);
+        let stnear_available = std::cmp::min(self.stnear_for_sale, locked_stnear);
+        let usdnear_wanted = std::cmp::min(usdnear_to_raise, max_usdnear);
+        let stnear_for_tab = proportional(usdnear_wanted, NEAR, price);
+        let stnear = std::cmp::min(max_stnear, std::cmp::min(stnear_available, stnear_for_tab));
+        let usdnear_cost = if stnear == stnear_for_tab { usdnear_wanted } else { proportional(stnear, price, NEAR) };
+        return AuctionBid { price, usdnear_to_raise, stnear_available, stnear, usdnear_cost };
+    }
+}
+
+/// amounts of a bid, used by bid_auction and by the keeper views (see liquidation.rs)
+pub struct AuctionBid {
+    pub price: u128,
+    /// min(auction.usdnear_to_raise, owed)
+    pub usdnear_to_raise: u128,
+    /// min(auction.stnear_for_sale, locked)
+    pub stnear_available: u128,
+    /// stNEAR bought
+    pub stnear: u128,
+    /// USDNEAR paid, repays the loan
+    pub usdnear_cost: u128,
+}
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn assert_liquidation_mode(&self, mode: LiquidationMode) {
+        assert!(self.liquidation_mode == mode, "liquidation mode is {:?}", self.liquidation_mode);
+    }
+
+    pub(crate) fn auction_json(&self, loan_account_id: AccountId, auction: &Auction) -> AuctionJSON {
+        let now = env::block_timestamp();
+        return AuctionJSON {
+            loan_account_id,
+            started_at: auction.started_at.into(),
+            start_price: auction.start_price.into(),
+            floor_price: auction.floor_price.into(),
+            current_price: auction.price_at(now).into(),
+            expired: auction.is_expired(now),
+            usdnear_to_raise: auction.usdnear_to_raise.into(),
+            stnear_for_sale: auction.stnear_for_sale.into(),
+        };
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Owner's method.
+    /// Selects fixed-discount or auction liquidations and sets the auction params. Running auctions keep their prices
+    pub fn set_liquidation_mode(&mut self, mode: LiquidationMode, auction_config: AuctionConfig) {
+        self.assert_owner_calling();
+        assert!(auction_config.floor_bp < auction_config.start_premium_bp, "floor_bp must be < start_premium_bp");
+        assert!(auction_config.floor_bp > 0, "floor_bp must be positive");
+        assert!(auction_config.duration_seconds.0 > 0, "duration must be positive");
+        self.liquidation_mode = mode;
+        self.auction_config = auction_config;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "liquidation_mode",
+            value: format!("{:?} start_premium_bp:{} floor_bp:{} duration_seconds:{}", mode,
+                self.auction_config.start_premium_bp, self.auction_config.floor_bp, self.auction_config.duration_seconds.0),
+        }.emit();
+    }
+
+    /// Starts (or restarts, if expired) the auction of an account below min_collateral_basis_points. Open to anyone
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn start_auction(&mut self, loan_account_id: AccountId) {
+        self.assert_one_yocto_for("start_auction");
+        self.assert_liquidation_mode(LiquidationMode::Auction);
+        self.assert_not_paused("liquidations");
+        self.assert_not_busy();
+        let now = env::block_timestamp();
+        if let Some(auction) = self.auctions.get(&loan_account_id) {
+            assert!(auction.is_expired(now), "the auction is running");
+        }
+        self.use_price_for(PriceUseCase::Liquidate);
+
+        let loan_acc = self.internal_get_account(&loan_account_id);
+        assert!(loan_acc.shares_usdnear_owed>0,"no USDNEAR owed");
+        let price = self.stnear_valuation_price();
+        let quote = self.liquidation_quote(&loan_acc, price, u128::MAX);
+        assert!(self.is_liquidatable(&quote), "coll.rate.BP is {}. Can't liquidate", quote.collateralization_ratio);
+
+        let auction = Auction {
+            started_at: now,
+            start_price: apply_pct(self.auction_config.start_premium_bp, price),
+            floor_price: apply_pct(self.auction_config.floor_bp, price),
+            duration: self.auction_config.duration_seconds.0 * NANOS_PER_SECOND,
+            usdnear_to_raise: quote.max_usdnear_repay,
+            stnear_for_sale: quote.locked_collateral_stnear,
+        };
+        self.auctions.insert(&loan_account_id, &auction);
+        events::AuctionUpdate {
+            loan_account_id: &loan_account_id,
+            account_id: &env::predecessor_account_id(),
+            action: "started",
+            price: auction.start_price.into(),
+            usdnear_amount: auction.usdnear_to_raise.into(),
+            stnear_amount: auction.stnear_for_sale.into(),
+        }.emit();
+    }
+
+    /// Buys up to `max_stnear` of the auctioned collateral at the current auction price, if it's not above `max_price`.
+    /// The USDNEAR paid repays the loan, the stNEAR goes to the caller's free stNEAR. Returns the stNEAR bought.
+    /// Expired auctions must be restarted. If the account is back at min_collateral_basis_points, the auction is removed and nothing is bought
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn bid_auction(&mut self, loan_account_id: AccountId, max_stnear: U128String, max_price: U128String) -> U128String {
+        self.assert_one_yocto_for("bid_auction");
+        self.assert_liquidation_mode(LiquidationMode::Auction);
+        self.assert_not_paused("liquidations");
+        self.assert_not_busy();
+        let bidder_id = env::predecessor_account_id();
+        self.assert_registered(&bidder_id);
+
+        let now = env::block_timestamp();
+        let mut auction = self.auctions.get(&loan_account_id).expect("no auction for this account");
+        // the floor price was set at start, a stale auction must restart at the current price
+        assert!(!auction.is_expired(now), "auction expired, restart it");
+
+        let mut loan_acc = self.internal_get_account(&loan_account_id);
+        self.use_price_for(PriceUseCase::Liquidate);
+        let rate = loan_acc.get_current_collateralization_ratio(self);
+        if rate >= self.min_collateral_basis_points {
+            // the price recovered or the borrower repaid, the collateral is not for sale anymore
+            log!("coll.rate.BP is {}. The account is not liquidatable, auction removed", rate);
+            self.auctions.remove(&loan_account_id);
+            events::AuctionUpdate {
+                loan_account_id: &loan_account_id,
+                account_id: &bidder_id,
+                action: "cancelled",
+                price: 0.into(),
+                usdnear_amount: 0.into(),
+                stnear_amount: 0.into(),
+            }.emit();
+            return 0.into();
+        }
+
+        let AuctionBid { price, usdnear_to_raise, stnear_available, stnear, usdnear_cost } =
+            auction.bid_at(now, loan_acc.outstanding_loans_usdnear(self), loan_acc.locked_stnear(self), max_stnear.0, u128::MAX);
+        assert!(price <= max_price.0, "auction price {} is above max_price", price);
+        assert!(stnear > 0, "nothing to buy");
+
+        let bidder_usdnear_balance = self.get_usdnear_balance(&bidder_id);
+        assert!(bidder_usdnear_balance >= usdnear_cost, "not enough USDNEAR. you need {}", usdnear_cost);
+
+        //from the bidder, take usdnear amount, use it to repay loan (burn it, remove from circulation)
+        self.set_usdnear_balance(&bidder_id, bidder_usdnear_balance - usdnear_cost);
+        loan_acc.remove_owed_usdnear_preserve_share_price(usdnear_cost, self);
+        // remove stnear from user's collateral, and add it to the bidder's account
+        let mut bidder_acc = self.internal_get_account(&bidder_id);
+        loan_acc.remove_locked_amount_preserve_share_price(stnear, self);
+        bidder_acc.add_free_amount_preserve_share_price(stnear, self);
+        self.internal_update_account(&loan_account_id, &loan_acc);
+        self.internal_update_account(&bidder_id, &bidder_acc);
+        self.update_mining_account(&loan_account_id);
+        self.update_mining_account(&bidder_id);
+
+        auction.usdnear_to_raise = usdnear_to_raise - usdnear_cost;
+        auction.stnear_for_sale = stnear_available - stnear;
+        let completed = auction.usdnear_to_raise < ONE_NEAR_CENT || auction.stnear_for_sale == 0 || loan_acc.shares_usdnear_owed == 0;
+        if completed {
+            self.auctions.remove(&loan_account_id);
+        } else {
+            self.auctions.insert(&loan_account_id, &auction);
+        }
+
+        events::FtBurn { owner_id: &bidder_id, amount: usdnear_cost.into(), memo: Some("bid_auction") }.emit();
+        events::Liquidate {
+            loan_account_id: &loan_account_id,
+            liquidator_id: &bidder_id,
+            usdnear_repaid: usdnear_cost.into(),
+            stnear_seized: stnear.into(),
+            collateralization_ratio: rate,
+        }.emit();
+        events::AuctionUpdate {
+            loan_account_id: &loan_account_id,
+            account_id: &bidder_id,
+            action: if completed { "completed" } else { "bid" },
+            price: price.into(),
+            usdnear_amount: usdnear_cost.into(),
+            stnear_amount: stnear.into(),
+        }.emit();
+        return stnear.into();
+    }
+
+    /// Cancels the auction of an account that is back at collateral_basis_points (e.g. the borrower repaid). Open to anyone
+    pub fn cancel_auction(&mut self, loan_account_id: AccountId) {
+        assert!(self.auctions.get(&loan_account_id).is_some(), "no auction for this account");
+        self.use_price_for(PriceUseCase::Liquidate);
+        let loan_acc = self.internal_get_account(&loan_account_id);
+        let rate = loan_acc.get_current_collateralization_ratio(self);
+        assert!(rate >= self.collateral_basis_points, "coll.rate.BP is {}. Can't cancel the auction", rate);
+        self.auctions.remove(&loan_account_id);
+        events::AuctionUpdate {
+            loan_account_id: &loan_account_id,
+            account_id: &env::predecessor_account_id(),
+            action: "cancelled",
+            price: 0.into(),
+            usdnear_amount: 0.into(),
+            stnear_amount: 0.into(),
+        }.emit();
+    }
+
+    pub fn get_liquidation_mode(&self) -> LiquidationMode {
+        return self.liquidation_mode;
+    }
+
+    pub fn get_auction_config(&self) -> AuctionConfig {
+        return self.auction_config.clone();
+    }
+
+    /// Returns the auction of `loan_account_id` with its current price, if any
+    pub fn get_auction(&self, loan_account_id: AccountId) -> Option<AuctionJSON> {
+        return self.auctions.get(&loan_account_id).map(|auction| self.auction_json(loan_account_id, &auction));
+    }
+
+    /// Returns a partial list of running auctions
+    pub fn get_auctions(&self, from_index: u64, limit: u32) -> Vec<AuctionJSON> {
+        let keys = self.auctions.keys_as_vector();
+        let values = self.auctions.values_as_vector();
+        return (from_index..std::cmp::min(from_index + limit as u64, keys.len()))
+            .map(|index| self.auction_json(keys.get(index).unwrap(), &values.get(index).unwrap()))
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// ALICE borrows at 200% and BOB holds USDNEAR, then the price drops to USD 7 (ALICE at 140%) and the auction starts
+    fn start_alice_auction(contract: &mut UsdNearStableCoin) -> Timestamp {
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(contract, ALICE, 100 * NEAR, 450 * NEAR, t0);
+        open_loan(contract, BOB, 1_000 * NEAR, 310 * NEAR, t0);
+        set_context(OWNER, 0, t0);
+        contract.set_liquidation_mode(LiquidationMode::Auction, AuctionConfig::default());
+        set_price(contract, 8 * NEAR, t0 + ONE_HOUR);
+        set_price(contract, 7 * NEAR, t0 + 2 * ONE_HOUR);
+        let t1 = t0 + 2 * ONE_HOUR;
+        set_context(CAROL, 1, t1);
+        contract.start_auction(ALICE.into());
+        return t1;
+    }
+
+    #[test]
+    fn auction_price_decays_to_the_floor() {
+        let auction = Auction { started_at: 100, start_price: 110, floor_price: 80, duration: 60, usdnear_to_raise: 0, stnear_for_sale: 0 };
+        assert_eq!(auction.price_at(100), 110);
+        assert_eq!(auction.price_at(130), 95);
+        assert_eq!(auction.price_at(160), 80);
+        assert_eq!(auction.price_at(1_000), 80);
+        assert!(!auction.is_expired(159));
+        assert!(auction.is_expired(160));
+    }
+
+    #[test]
+    fn bids_repay_the_loan_until_the_account_recovers() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        let auction = contract.get_auction(ALICE.into()).unwrap();
+        assert_eq!(auction.start_price.0, 77 * NEAR / 10);
+        assert_eq!(auction.floor_price.0, 56 * NEAR / 10);
+        assert_close(auction.usdnear_to_raise.0, 300 * NEAR, NEAR / 100);
+
+        // half way the price is USD 6.65
+        let t2 = t1 + DEFAULT_AUCTION_DURATION / 2;
+        set_context(BOB, 1, t2);
+        let owed_before = contract.get_account_info(ALICE.into()).outstanding_loans_usdnear.0;
+        let bob_stnear = contract.get_account_info(BOB.into()).stnear.0;
+        let bought = contract.bid_auction(ALICE.into(), (20 * NEAR).into(), (7 * NEAR).into()).0;
+        assert_eq!(bought, 20 * NEAR);
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 310 * NEAR - 133 * NEAR);
+        assert_close(contract.get_account_info(ALICE.into()).outstanding_loans_usdnear.0, owed_before - 133 * NEAR, 10);
+        assert_close(contract.get_account_info(BOB.into()).stnear.0, bob_stnear + 20 * NEAR, 10);
+        let auction = contract.get_auction(ALICE.into()).unwrap();
+        assert_close(auction.usdnear_to_raise.0, 167 * NEAR, NEAR / 100);
+
+        // ALICE is back above 150%, the next bid removes the auction and buys nothing
+        assert!(contract.get_account_info(ALICE.into()).collateralization_ratio >= contract.min_collateral_basis_points);
+        assert_eq!(contract.bid_auction(ALICE.into(), (100 * NEAR).into(), (7 * NEAR).into()).0, 0);
+        assert!(contract.get_auction(ALICE.into()).is_none());
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 310 * NEAR - 133 * NEAR);
+    }
+
+    #[test]
+    fn liquidation_views_quote_the_running_auction() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        let t2 = t1 + DEFAULT_AUCTION_DURATION / 2;
+        set_context(BOB, 1, t2);
+        // BOB's locked stNEAR is at 140% too, but no auction was started for BOB
+        let accounts = contract.get_liquidatable_accounts(0, 10);
+        assert_eq!(accounts.len(), 2);
+        let (alice, bob) = (&accounts[0], &accounts[1]);
+        assert_eq!(alice.liquidation_mode, LiquidationMode::Auction);
+        assert_eq!(alice.auction.as_ref().unwrap().current_price.0, 665 * NEAR / 100);
+        assert_eq!(alice.stnear_to_reserve.0, 0);
+        assert!(bob.liquidatable && bob.auction.is_none());
+        assert_eq!(bob.usdnear_repay.0, 0);
+
+        // a bid for 133 USDNEAR buys 20 stNEAR at USD 6.65, same as bid_auction
+        let preview = contract.preview_liquidation(ALICE.into(), (133 * NEAR).into());
+        assert_eq!(preview.usdnear_repay.0, 133 * NEAR);
+        assert_eq!(preview.stnear_to_receive.0, 20 * NEAR);
+        assert_eq!(contract.bid_auction(ALICE.into(), (20 * NEAR).into(), (7 * NEAR).into()).0, 20 * NEAR);
+
+        // an expired auction must be restarted: nothing to buy until then
+        set_context(BOB, 1, t1 + DEFAULT_AUCTION_DURATION);
+        let preview = contract.preview_liquidation(ALICE.into(), (100 * NEAR).into());
+        assert!(preview.auction.is_none() && preview.usdnear_repay.0 == 0);
+    }
+
+    #[test]
+    fn liquidation_views_need_a_started_auction() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, t0);
+        set_context(OWNER, 0, t0);
+        contract.set_liquidation_mode(LiquidationMode::Auction, AuctionConfig::default());
+        set_price(&mut contract, 8 * NEAR, t0 + ONE_HOUR);
+        set_price(&mut contract, 7 * NEAR, t0 + 2 * ONE_HOUR);
+        let preview = contract.preview_liquidation(ALICE.into(), (100 * NEAR).into());
+        assert!(preview.liquidatable && preview.auction.is_none());
+        assert_eq!(preview.usdnear_repay.0, 0);
+        assert_eq!(preview.stnear_to_receive.0, 0);
+    }
+
+    #[test]
+    fn a_big_bid_raises_the_whole_tab() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        set_context(BOB, 1, t1 + DEFAULT_AUCTION_DURATION / 2);
+        contract.bid_auction(ALICE.into(), (100 * NEAR).into(), (7 * NEAR).into());
+        assert!(contract.get_auction(ALICE.into()).is_none());
+        assert!(contract.get_account_info(ALICE.into()).collateralization_ratio >= contract.collateral_basis_points);
+    }
+
+    #[test]
+    #[should_panic(expected = "auction expired, restart it")]
+    fn expired_auctions_take_no_bids() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        set_context(BOB, 1, t1 + DEFAULT_AUCTION_DURATION);
+        contract.bid_auction(ALICE.into(), (20 * NEAR).into(), (7 * NEAR).into());
+    }
+
+    #[test]
+    fn bids_after_the_price_recovers_remove_the_auction() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        // ALICE is at 160%
+        set_price(&mut contract, 8 * NEAR, t1 + ONE_HOUR);
+        set_context(BOB, 1, t1 + ONE_HOUR);
+        let bob_usdnear = contract.ft_balance_of(BOB.into()).0;
+        assert_eq!(contract.bid_auction(ALICE.into(), (20 * NEAR).into(), (7 * NEAR).into()).0, 0);
+        assert!(contract.get_auction(ALICE.into()).is_none());
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, bob_usdnear);
+    }
+
+    #[test]
+    #[should_panic(expected = "is above max_price")]
+    fn bids_respect_max_price() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        set_context(BOB, 1, t1);
+        contract.bid_auction(ALICE.into(), (20 * NEAR).into(), (7 * NEAR).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "the auction is running")]
+    fn running_auctions_can_not_restart() {
+        let mut contract = new_contract();
+        let t1 = start_alice_auction(&mut contract);
+        set_context(CAROL, 1, t1 + DEFAULT_AUCTION_DURATION - 1);
+        contract.start_auction(ALICE.into());
+    }
+}
@@ -0,0 +1,238 @@
+//
+// BAD DEBT
+// a position is underwater when its collateral (at the liquidation price) is worth less than its debt.
+// `write_off_bad_debt` (open to anyone) closes it: its debt is removed and all its collateral is seized into the protocol reserve.
+// Its share of the unpaid interest is written off, and the USDNEAR in circulation that was backed by the rest of the debt
+// is then covered by this waterfall:
+// 1. the reserve (the seized collateral first, then up to `reserve_cover_bp` of the previous reserve) moves stNEAR to the
+//    collateral pool, and the same value is added to the remaining borrowers' debt (net zero for them)
+// 2. if `socialize` is on, the rest is added to the remaining borrowers' debt (borrow index, proportional to debt shares)
+// 3. what's left is `uncovered_bad_debt`, to be covered later with `cover_bad_debt` (e.g. after the reserve is funded)
+// every step is logged as a `bad_debt` event
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BadDebtConfig {
+    /// max share of the reserve (excluding the seized collateral) usable to cover bad debt
+    pub reserve_cover_bp: u32,
+    /// socialize the bad debt not covered by the reserve across the remaining borrowers
+    pub socialize: bool,
+}
+
+impl Default for BadDebtConfig {
+    fn default() -> Self {
+        Self {
+            reserve_cover_bp: 100 * PERCENT_BP,
+            socialize: true,
+        }
+    }
+}
+
+impl UsdNearStableCoin {
+
+    /// covers `debt` USDNEAR not backed by borrowers' debt with the waterfall. `seized_stnear` (already in the reserve)
+    /// is used first. Returns the USDNEAR not covered. Must not panic
+    fn cover_with_waterfall(&mut self, loan_account_id: Option<&str>, debt: u128, seized_stnear: u128, price: u128) -> u128 {
+        // without borrowers there's no debt nor collateral to move the bad debt to
+        if self.total_usdnear_shares == 0 || self.total_collateral_shares == 0 {
+            events::BadDebt { loan_account_id, step: "uncovered", usdnear_amount: debt.into(), stnear_amount: 0.into() }.emit();
+            return debt;
+        }
+
+        // 1. reserve
+        let reserve_before = self.reserve_stnear().saturating_sub(seized_stnear);
+        let reserve_available = std::cmp::min(self.reserve_stnear(), seized_stnear + apply_pct(self.bad_debt_config.reserve_cover_bp, reserve_before));
+        let reserve_available_usd = proportional(reserve_available, price, NEAR);
+        let covered_by_reserve = std::cmp::min(debt, reserve_available_usd);
+        if covered_by_reserve > 0 {
+            let reserve_stnear_used = std::cmp::min(reserve_available, proportional(covered_by_reserve, NEAR, price));
+            self.reserve_to_collateral_pool("bad_debt_cover", reserve_stnear_used);
+            self.increase_total_debt(covered_by_reserve);
+            self.total_bad_debt_covered_by_reserve += covered_by_reserve;
+            events::BadDebt { loan_account_id, step: "reserve_cover", usdnear_amount: covered_by_reserve.into(), stnear_amount: reserve_stnear_used.into() }.emit();
+        }
+
+        // 2. socialization
+        let mut rest = debt - covered_by_reserve;
+        if rest > 0 && self.bad_debt_config.socialize {
+            self.increase_total_debt(rest);
+            self.total_bad_debt_socialized += rest;
+            events::BadDebt { loan_account_id, step: "socialized", usdnear_amount: rest.into(), stnear_amount: 0.into() }.emit();
+            rest = 0;
+        }
+
+        // 3. uncovered
+        if rest > 0 {
+            events::BadDebt { loan_account_id, step: "uncovered", usdnear_amount: rest.into(), stnear_amount: 0.into() }.emit();
+        }
+        return rest;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Closes an underwater position (collateral worth less than the debt at the liquidation price):
+    /// removes its debt, seizes all its collateral into the reserve and covers the debt with the waterfall. Open to anyone
+    pub fn write_off_bad_debt(&mut self, loan_account_id: AccountId) {
+        self.assert_not_paused("liquidations");
+        self.assert_not_busy();
+        self.use_price_for(PriceUseCase::Liquidate);
+        let price = self.stnear_valuation_price();
+
+        let mut loan_acc = self.internal_get_account(&loan_account_id);
+        assert!(loan_acc.shares_usdnear_owed>0,"no USDNEAR owed");
+        let quote = self.liquidation_quote(&loan_acc, price, u128::MAX);
+        assert!(quote.underwater, "the position is not underwater, coll.rate.BP is {}", quote.collateralization_ratio);
+
+        // remove the debt, the USDNEAR in circulation remains. The unpaid interest of the loan is not backing USDNEAR, write it off
+        self.accrue_borrow_interest();
+        let written_off_interest = self.settle_accrued_interest(loan_acc.shares_usdnear_owed, quote.owed_usdnear);
+        self.total_usdnear_shares -= loan_acc.shares_usdnear_owed;
+        loan_acc.shares_usdnear_owed = 0;
+        // seize all the collateral into the reserve
+        let seized_stnear = quote.locked_collateral_stnear;
+        loan_acc.remove_locked_amount_preserve_share_price(seized_stnear, self);
+        self.internal_update_account(&loan_account_id, &loan_acc);
+        self.update_mining_account(&loan_account_id);
+        self.add_to_reserve(&loan_account_id, "seized_collateral", seized_stnear);
+        self.auctions.remove(&loan_account_id);
+
+        let shortfall = quote.owed_usdnear - quote.valued_collateral_usd;
+        self.total_bad_debt += shortfall;
+        events::BadDebt { loan_account_id: Some(&loan_account_id), step: "write_off", usdnear_amount: quote.owed_usdnear.into(), stnear_amount: seized_stnear.into() }.emit();
+
+        let uncovered = self.cover_with_waterfall(Some(&loan_account_id), quote.owed_usdnear - written_off_interest, seized_stnear, price);
+        self.uncovered_bad_debt += uncovered;
+    }
+
+    /// Covers the uncovered bad debt with the waterfall (reserve, then socialization if enabled). Open to anyone
+    pub fn cover_bad_debt(&mut self) {
+        assert!(self.uncovered_bad_debt > 0, "no uncovered bad debt");
+        self.assert_not_busy();
+        self.use_price_for(PriceUseCase::Liquidate);
+        let price = self.stnear_valuation_price();
+        let debt = self.uncovered_bad_debt;
+        self.uncovered_bad_debt = self.cover_with_waterfall(None, debt, 0, price);
+    }
+
+    /// Owner's method.
+    /// Sets the share of the reserve usable to cover bad debt and whether the rest is socialized
+    pub fn set_bad_debt_config(&mut self, config: BadDebtConfig) {
+        self.assert_owner_calling();
+        assert!(config.reserve_cover_bp <= 10000, "reserve_cover_bp must be <= 10000");
+        self.bad_debt_config = config;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "bad_debt_config",
+            value: format!("reserve_cover_bp:{} socialize:{}", self.bad_debt_config.reserve_cover_bp, self.bad_debt_config.socialize),
+        }.emit();
+    }
+
+    /// Returns bad debt totals, the waterfall config and the reserve
+    pub fn get_bad_debt_info(&self) -> BadDebtInfoJSON {
+        return BadDebtInfoJSON {
+            total_bad_debt: self.total_bad_debt.into(),
+            uncovered_bad_debt: self.uncovered_bad_debt.into(),
+            total_bad_debt_covered_by_reserve: self.total_bad_debt_covered_by_reserve.into(),
+            total_bad_debt_socialized: self.total_bad_debt_socialized.into(),
+            reserve_stnear: self.reserve_stnear().into(),
+            config: self.bad_debt_config.clone(),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// ALICE borrows USDNEAR 450 locking 90 of ALICE's 100 stNEAR, BOB borrows USDNEAR 300 with 1000 stNEAR,
+    /// CAROL funds the reserve with `reserve_stnear`, then the price drops to USD 4 (ALICE underwater). Returns the timestamp
+    fn setup(contract: &mut UsdNearStableCoin, reserve_stnear: u128) -> Timestamp {
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(contract, ALICE, 100 * NEAR, 450 * NEAR, t0);
+        open_loan(contract, BOB, 1_000 * NEAR, 300 * NEAR, t0);
+        if reserve_stnear > 0 {
+            register(contract, CAROL, t0);
+            deposit_stnear(contract, CAROL, reserve_stnear, t0);
+            set_context(CAROL, 1, t0);
+            contract.fund_reserve(reserve_stnear.into());
+        }
+        set_context(OWNER, 0, t0);
+        contract.set_circuit_breaker_limits(90 * PERCENT_BP, 90 * PERCENT_BP);
+        let t1 = t0 + ONE_HOUR;
+        set_price(contract, 4 * NEAR, t1);
+        return t1;
+    }
+
+    #[test]
+    #[should_panic(expected = "the position is not underwater")]
+    fn only_underwater_positions_are_written_off() {
+        let mut contract = new_contract();
+        let t1 = setup(&mut contract, 0);
+        set_price(&mut contract, 5 * NEAR, t1);
+        contract.write_off_bad_debt(ALICE.into());
+    }
+
+    #[test]
+    fn reserve_covers_the_bad_debt() {
+        let mut contract = new_contract();
+        let t1 = setup(&mut contract, 100 * NEAR);
+        let alice_owed = contract.get_account_info(ALICE.into()).outstanding_loans_usdnear.0;
+        let bob_locked = contract.get_account_info(BOB.into()).locked_stnear.0;
+        let bob_owed = contract.get_account_info(BOB.into()).outstanding_loans_usdnear.0;
+        set_context(CAROL, 0, t1);
+        contract.write_off_bad_debt(ALICE.into());
+
+        assert_eq!(contract.get_account_info(ALICE.into()).outstanding_loans_usdnear.0, 0);
+        assert_eq!(contract.get_account_info(ALICE.into()).locked_stnear.0, 0);
+        let info = contract.get_bad_debt_info();
+        assert_close(info.total_bad_debt.0, alice_owed - 360 * NEAR, NEAR / 100);
+        assert_eq!(info.uncovered_bad_debt.0, 0);
+        assert_eq!(info.total_bad_debt_socialized.0, 0);
+        // the seized 90 stNEAR + 22.5 stNEAR of the reserve cover USDNEAR 450 at USD 4
+        assert_close(info.total_bad_debt_covered_by_reserve.0, 450 * NEAR, NEAR / 100);
+        assert_close(info.reserve_stnear.0, 190 * NEAR - 1125 * NEAR / 10, NEAR / 100);
+        // BOB owes the covered debt, and got the same value of collateral
+        let bob = contract.get_account_info(BOB.into());
+        assert_close(bob.outstanding_loans_usdnear.0, bob_owed + info.total_bad_debt_covered_by_reserve.0, 10);
+        assert_close(bob.locked_stnear.0, bob_locked + info.total_bad_debt_covered_by_reserve.0 / 4, 10);
+        // USDNEAR in circulation is backed by debt again
+        assert_close(contract.ft_total_supply().0, bob.outstanding_loans_usdnear.0, NEAR / 100);
+    }
+
+    #[test]
+    fn uncovered_bad_debt_is_socialized_later() {
+        let mut contract = new_contract();
+        let t1 = setup(&mut contract, 0);
+        set_context(OWNER, 0, t1);
+        contract.set_bad_debt_config(BadDebtConfig { reserve_cover_bp: 100 * PERCENT_BP, socialize: false });
+        let bob_owed = contract.get_account_info(BOB.into()).outstanding_loans_usdnear.0;
+        set_context(CAROL, 0, t1);
+        contract.write_off_bad_debt(ALICE.into());
+
+        // the seized collateral covers USDNEAR 360, the rest waits
+        let info = contract.get_bad_debt_info();
+        assert_close(info.total_bad_debt_covered_by_reserve.0, 360 * NEAR, NEAR / 100);
+        assert_close(info.uncovered_bad_debt.0, 90 * NEAR, NEAR / 100);
+        assert_eq!(info.reserve_stnear.0, 0);
+
+        set_context(OWNER, 0, t1);
+        contract.set_bad_debt_config(BadDebtConfig::default());
+        set_context(CAROL, 0, t1);
+        contract.cover_bad_debt();
+        let info = contract.get_bad_debt_info();
+        assert_eq!(info.uncovered_bad_debt.0, 0);
+        assert_close(info.total_bad_debt_socialized.0, 90 * NEAR, NEAR / 100);
+        let bob = contract.get_account_info(BOB.into());
+        assert_close(bob.outstanding_loans_usdnear.0, bob_owed + info.total_bad_debt_covered_by_reserve.0 + info.total_bad_debt_socialized.0, 1000);
+        assert_close(contract.ft_total_supply().0, bob.outstanding_loans_usdnear.0, NEAR / 100);
+    }
+}
@@ -0,0 +1,215 @@
+//
+// PRICE CIRCUIT BREAKER
+// a price update that moves the price more than `max_price_change_bp_per_update` from the current price,
+// or more than `max_price_change_bp_per_hour` from the price one hour ago, is not applied.
+// Instead, borrowing is paused and the price is recorded as pending until the guardian confirms or rejects it
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+pub const ONE_HOUR: Duration = 60 * 60 * NANOS_PER_SECOND;
+pub const DEFAULT_MAX_PRICE_CHANGE_BP_PER_UPDATE: u32 = 25 * PERCENT_BP;
+pub const DEFAULT_MAX_PRICE_CHANGE_BP_PER_HOUR: u32 = 40 * PERCENT_BP;
+
+/// price update held by the circuit breaker
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct PendingPrice {
+    pub price: u128,
+    pub timestamp: Timestamp,
+    pub reported_by: AccountId,
+}
+
+/// price change in basis points from reference_price to new_price
+pub fn price_change_bp(reference_price: u128, new_price: u128) -> u128 {
+    if reference_price == 0 {
+        return 0;
+    }
+    return proportional(new_price.abs_diff(reference_price), 10000, reference_price);
+}
+
+impl UsdNearStableCoin {
+
+    /// price at one hour ago: the last observation older than one hour, or the oldest observation
+    pub(crate) fn price_one_hour_ago(&self) -> u128 {
+        let one_hour_ago = env::block_timestamp().saturating_sub(ONE_HOUR);
+        let mut observations = self.price_observations.to_vec();
+        observations.sort_by_key(|o| o.timestamp);
+        return match observations.iter().rev().find(|o| o.timestamp <= one_hour_ago) {
+            Some(observation) => observation.price,
+            None => observations.first().map(|o| o.price).unwrap_or(self.current_stnear_price),
+        };
+    }
+
+    /// checks the circuit breaker limits for a new price
+    /// returns None if the price can be applied, or the (kind, change_bp, limit_bp) that tripped it
+    pub(crate) fn check_circuit_breaker(&self, new_price: u128) -> Option<(&'static str, u128, u32)> {
+        let change_per_update = price_change_bp(self.current_stnear_price, new_price);
+        if change_per_update > self.max_price_change_bp_per_update as u128 {
+            return Some(("per_update", change_per_update, self.max_price_change_bp_per_update));
+        }
+        let change_per_hour = price_change_bp(self.price_one_hour_ago(), new_price);
+        if change_per_hour > self.max_price_change_bp_per_hour as u128 {
+            return Some(("per_hour", change_per_hour, self.max_price_change_bp_per_hour));
+        }
+        return None;
+    }
+
+    /// trips the circuit breaker: holds the price as pending and pauses borrowing. must not panic
+    pub(crate) fn trip_circuit_breaker(&mut self, new_price: u128, reported_by: &AccountId, kind: &str, change_bp: u128, limit_bp: u32) {
+        self.pending_price = Some(PendingPrice { price: new_price, timestamp: env::block_timestamp(), reported_by: reported_by.clone() });
+        self.internal_set_paused("borrowing", true);
+        log!("CIRCUIT BREAKER: price {} changes {}bp {}, limit is {}bp. Borrowing paused until the guardian confirms or rejects the price",
+            new_price, change_bp, kind, limit_bp);
+        events::CircuitBreakerTripped {
+            reported_by,
+            current_price: self.current_stnear_price.into(),
+            pending_price: new_price.into(),
+            kind,
+            change_bp: change_bp.into(),
+            limit_bp,
+        }.emit();
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Guardian's method.
+    /// Applies the price held by the circuit breaker. If `resume_borrowing`, borrowing is unpaused (only the owner can unpause)
+    pub fn confirm_pending_price(&mut self, resume_borrowing: bool) {
+        self.assert_role(Role::Guardian);
+        if resume_borrowing {
+            self.assert_owner_calling();
+        }
+        assert!(self.pending_price.is_some(), "there's no pending price");
+        let pending = self.pending_price.take().unwrap();
+        self.internal_apply_stnear_price(pending.price, &env::predecessor_account_id());
+        if resume_borrowing {
+            self.internal_set_paused("borrowing", false);
+        }
+        events::PendingPriceResolved { resolved_by: &env::predecessor_account_id(), price: pending.price.into(), confirmed: true, borrowing_paused: self.paused.borrowing }.emit();
+    }
+
+    /// Guardian's method.
+    /// Discards the price held by the circuit breaker. If `resume_borrowing`, borrowing is unpaused (only the owner can unpause)
+    pub fn reject_pending_price(&mut self, resume_borrowing: bool) {
+        self.assert_role(Role::Guardian);
+        if resume_borrowing {
+            self.assert_owner_calling();
+        }
+        assert!(self.pending_price.is_some(), "there's no pending price");
+        let pending = self.pending_price.take().unwrap();
+        if resume_borrowing {
+            self.internal_set_paused("borrowing", false);
+        }
+        events::PendingPriceResolved { resolved_by: &env::predecessor_account_id(), price: pending.price.into(), confirmed: false, borrowing_paused: self.paused.borrowing }.emit();
+    }
+
+    /// Parameter manager's method.
+    /// Sets the max price change (basis points) per update and per hour before the circuit breaker trips
+    pub fn set_circuit_breaker_limits(&mut self, max_price_change_bp_per_update: u32, max_price_change_bp_per_hour: u32) {
+        self.assert_role(Role::ParameterManager);
+        assert!(max_price_change_bp_per_update > 0 && max_price_change_bp_per_hour > 0, "limits must be positive");
+        self.max_price_change_bp_per_update = max_price_change_bp_per_update;
+        self.max_price_change_bp_per_hour = max_price_change_bp_per_hour;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "circuit_breaker_limits",
+            value: format!("per_update:{}bp per_hour:{}bp", max_price_change_bp_per_update, max_price_change_bp_per_hour),
+        }.emit();
+    }
+
+    /// Returns circuit breaker limits and the pending price, if any
+    pub fn get_circuit_breaker_status(&self) -> CircuitBreakerStatusJSON {
+        return CircuitBreakerStatusJSON {
+            current_stnear_price: self.current_stnear_price.into(),
+            price_one_hour_ago: self.price_one_hour_ago().into(),
+            max_price_change_bp_per_update: self.max_price_change_bp_per_update,
+            max_price_change_bp_per_hour: self.max_price_change_bp_per_hour,
+            pending_price: self.pending_price.as_ref().map(|p| p.price.into()),
+            pending_price_timestamp: self.pending_price.as_ref().map(|p| p.timestamp.into()),
+            pending_price_reported_by: self.pending_price.as_ref().map(|p| p.reported_by.clone()),
+            borrowing_paused: self.paused.borrowing,
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    const GUARDIAN: &str = "guardian.near";
+
+    /// a 30% drop trips the circuit breaker, GUARDIAN has the guardian role
+    fn contract_with_pending_price() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.grant_role(Role::Guardian, GUARDIAN.into());
+        set_price(&mut contract, 7 * NEAR, 60 * NANOS_PER_SECOND);
+        return contract;
+    }
+
+    #[test]
+    fn price_change_in_basis_points() {
+        assert_eq!(price_change_bp(10 * NEAR, 8 * NEAR), 2000);
+        assert_eq!(price_change_bp(10 * NEAR, 12 * NEAR), 2000);
+        assert_eq!(price_change_bp(0, 12 * NEAR), 0);
+    }
+
+    #[test]
+    fn large_update_is_held_and_pauses_borrowing() {
+        let contract = contract_with_pending_price();
+        assert_eq!(contract.current_stnear_price, INITIAL_PRICE);
+        let status = contract.get_circuit_breaker_status();
+        assert_eq!(status.pending_price, Some((7 * NEAR).into()));
+        assert_eq!(status.pending_price_reported_by, Some(OPERATOR.into()));
+        assert!(status.borrowing_paused);
+    }
+
+    #[test]
+    fn small_updates_trip_the_hourly_limit() {
+        let mut contract = new_contract();
+        set_price(&mut contract, 8 * NEAR, 10 * 60 * NANOS_PER_SECOND);
+        set_price(&mut contract, 65 * NEAR / 10, 20 * 60 * NANOS_PER_SECOND);
+        assert_eq!(contract.current_stnear_price, 65 * NEAR / 10);
+        // 15% from the last update, 45% from one hour ago
+        set_price(&mut contract, 55 * NEAR / 10, 30 * 60 * NANOS_PER_SECOND);
+        assert_eq!(contract.current_stnear_price, 65 * NEAR / 10);
+        assert_eq!(contract.pending_price.as_ref().unwrap().price, 55 * NEAR / 10);
+    }
+
+    #[test]
+    fn confirming_applies_the_pending_price() {
+        let mut contract = contract_with_pending_price();
+        set_context(GUARDIAN, 0, 120 * NANOS_PER_SECOND);
+        contract.confirm_pending_price(false);
+        assert_eq!(contract.current_stnear_price, 7 * NEAR);
+        assert!(contract.pending_price.is_none());
+        // only the owner resumes borrowing
+        assert!(contract.paused.borrowing);
+        set_context(OWNER, 0, 120 * NANOS_PER_SECOND);
+        contract.unpause("borrowing".into());
+        assert!(!contract.paused.borrowing);
+    }
+
+    #[test]
+    fn rejecting_keeps_the_current_price() {
+        let mut contract = contract_with_pending_price();
+        set_context(OWNER, 0, 120 * NANOS_PER_SECOND);
+        contract.reject_pending_price(true);
+        assert_eq!(contract.current_stnear_price, INITIAL_PRICE);
+        assert!(contract.pending_price.is_none());
+        assert!(!contract.paused.borrowing);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn the_guardian_can_not_resume_borrowing() {
+        let mut contract = contract_with_pending_price();
+        set_context(GUARDIAN, 0, 120 * NANOS_PER_SECOND);
+        contract.confirm_pending_price(true);
+    }
+}
@@ -0,0 +1,540 @@
+//
+// NEP-297 Events
+// https://github.com/near/NEPs/blob/master/specs/Standards/EventsFormat.md
+// every event is logged as `EVENT_JSON:{"standard":..,"version":..,"event":..,"data":[..]}`
+//
+
+use crate::*;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+
+pub const NEP141_STANDARD: &str = "nep141";
+pub const NEP141_VERSION: &str = "1.0.0";
+pub const USDNEAR_STANDARD: &str = "usdnear";
+pub const USDNEAR_EVENTS_VERSION: &str = "1.0.0";
+
+/// the NEP-297 log line of an event
+fn event_log<T: Serialize>(standard: &str, version: &str, event: &str, data: &T) -> String {
+    let event_json = json!({
+        "standard": standard,
+        "version": version,
+        "event": event,
+        "data": [data],
+    });
+    return format!("EVENT_JSON:{}", event_json);
+}
+
+fn emit_event<T: Serialize>(standard: &str, version: &str, event: &str, data: &T) {
+    log!("{}", event_log(standard, version, event, data));
+}
+
+//-- NEP-141 events
+
+/// USDNEAR minted (a loan was taken)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtMint<'a> {
+    pub owner_id: &'a str,
+    pub amount: U128String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+impl FtMint<'_> {
+    pub fn emit(&self) {
+        emit_event(NEP141_STANDARD, NEP141_VERSION, "ft_mint", self);
+    }
+}
+
+/// USDNEAR burned (repayment, liquidation, conversion)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtBurn<'a> {
+    pub owner_id: &'a str,
+    pub amount: U128String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+impl FtBurn<'_> {
+    pub fn emit(&self) {
+        emit_event(NEP141_STANDARD, NEP141_VERSION, "ft_burn", self);
+    }
+}
+
+/// USDNEAR transferred between accounts
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtTransfer<'a> {
+    pub old_owner_id: &'a str,
+    pub new_owner_id: &'a str,
+    pub amount: U128String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+impl FtTransfer<'_> {
+    pub fn emit(&self) {
+        emit_event(NEP141_STANDARD, NEP141_VERSION, "ft_transfer", self);
+    }
+}
+
+//-- STBL token events (see stbl.rs)
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblMint<'a> {
+    pub owner_id: &'a str,
+    pub amount: U128String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+impl StblMint<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "stbl_mint", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblBurn<'a> {
+    pub owner_id: &'a str,
+    pub amount: U128String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+impl StblBurn<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "stbl_burn", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblTransfer<'a> {
+    pub old_owner_id: &'a str,
+    pub new_owner_id: &'a str,
+    pub amount: U128String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+impl StblTransfer<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "stbl_transfer", self);
+    }
+}
+
+/// STBL staked, unstaked (cooldown starts), withdrawn after the cooldown, or stNEAR rewards claimed (see staking.rs)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblStaking<'a> {
+    pub account_id: &'a str,
+    /// stake, unstake, withdraw (STBL amount) or claim (stNEAR amount)
+    pub action: &'a str,
+    pub amount: U128String,
+}
+impl StblStaking<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "stbl_staking", self);
+    }
+}
+
+//-- STBL governance events (see governance.rs)
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceProposalUpdate<'a> {
+    pub proposal_id: U64String,
+    pub account_id: &'a str,
+    /// created, executed
+    pub action: &'a str,
+}
+impl GovernanceProposalUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "governance_proposal_update", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceVote<'a> {
+    pub proposal_id: U64String,
+    pub voter: &'a str,
+    pub support: bool,
+    pub voting_power: U128String,
+}
+impl GovernanceVote<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "governance_vote", self);
+    }
+}
+
+//-- USDNEAR contract events
+
+/// stNEAR deposited via stNEAR.ft_transfer_call
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositStnear<'a> {
+    pub account_id: &'a str,
+    pub amount: U128String,
+}
+impl DepositStnear<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "deposit_stnear", self);
+    }
+}
+
+/// free stNEAR transferred back to the user
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawStnear<'a> {
+    pub account_id: &'a str,
+    pub amount: U128String,
+}
+impl WithdrawStnear<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "withdraw_stnear", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TakeLoan<'a> {
+    pub account_id: &'a str,
+    pub usdnear_amount: U128String,
+    pub outstanding_loans_usdnear: U128String,
+    pub locked_stnear: U128String,
+}
+impl TakeLoan<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "take_loan", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RepayLoan<'a> {
+    pub account_id: &'a str,
+    pub usdnear_amount: U128String,
+    pub outstanding_loans_usdnear: U128String,
+    pub locked_stnear: U128String,
+}
+impl RepayLoan<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "repay_loan", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Liquidate<'a> {
+    pub loan_account_id: &'a str,
+    pub liquidator_id: &'a str,
+    pub usdnear_repaid: U128String,
+    pub stnear_seized: U128String,
+    pub collateralization_ratio: u32,
+}
+impl Liquidate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "liquidate", self);
+    }
+}
+
+/// USDNEAR converted to stNEAR in the conversion window
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConvertUsdnear<'a> {
+    pub account_id: &'a str,
+    pub usdnear_amount: U128String,
+    pub stnear_amount: U128String,
+}
+impl ConvertUsdnear<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "convert_usdnear", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ForgiveLoan<'a> {
+    pub account_id: &'a str,
+    pub usdnear_amount: U128String,
+}
+impl ForgiveLoan<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "forgive_loan", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceUpdate<'a> {
+    pub updated_by: &'a str,
+    pub old_price: U128String,
+    pub new_price: U128String,
+}
+impl PriceUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "price_update", self);
+    }
+}
+
+/// a whitelisted reporter reported a price
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceReport<'a> {
+    pub reporter_id: &'a str,
+    pub price: U128String,
+}
+impl PriceReport<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "price_report", self);
+    }
+}
+
+/// a price update was held by the circuit breaker and borrowing was paused
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreakerTripped<'a> {
+    pub reported_by: &'a str,
+    pub current_price: U128String,
+    pub pending_price: U128String,
+    /// "per_update" or "per_hour"
+    pub kind: &'a str,
+    pub change_bp: U128String,
+    pub limit_bp: u32,
+}
+impl CircuitBreakerTripped<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "circuit_breaker_tripped", self);
+    }
+}
+
+/// the guardian confirmed or rejected the price held by the circuit breaker
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingPriceResolved<'a> {
+    pub resolved_by: &'a str,
+    pub price: U128String,
+    pub confirmed: bool,
+    pub borrowing_paused: bool,
+}
+impl PendingPriceResolved<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "pending_price_resolved", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParamsUpdate<'a> {
+    pub updated_by: &'a str,
+    pub params: &'a ContractParamsJSON,
+}
+impl ParamsUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "params_update", self);
+    }
+}
+
+/// a params proposal was queued, executed, vetoed or removed as expired (see timelock.rs)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParamsProposalUpdate<'a> {
+    pub proposal_id: U64String,
+    pub updated_by: &'a str,
+    /// queued, executed, vetoed, expired
+    pub action: &'a str,
+    pub eta: U64String,
+    pub change: &'a ParamsChange,
+}
+impl ParamsProposalUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "params_proposal_update", self);
+    }
+}
+
+/// ownership transfer proposed, accepted or cancelled, or operator/treasury account changed
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountChange<'a> {
+    pub updated_by: &'a str,
+    /// owner_proposed, owner_accepted, owner_proposal_cancelled, operator, treasury
+    pub change: &'a str,
+    pub old_account_id: &'a str,
+    pub new_account_id: &'a str,
+}
+impl AccountChange<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "account_change", self);
+    }
+}
+
+/// a role was granted or revoked (see roles.rs)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleUpdate<'a> {
+    pub updated_by: &'a str,
+    pub role: &'a str,
+    pub account_id: &'a str,
+    pub granted: bool,
+}
+impl RoleUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "role_update", self);
+    }
+}
+
+/// an operation was paused or unpaused (see pause.rs)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseUpdate<'a> {
+    pub updated_by: &'a str,
+    pub operation: &'a str,
+    pub paused: bool,
+}
+impl PauseUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "pause_update", self);
+    }
+}
+
+/// a configuration value changed (e.g. ft_metadata, price_modes)
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConfigUpdate<'a> {
+    pub updated_by: &'a str,
+    pub key: &'a str,
+    pub value: String,
+}
+impl ConfigUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "config_update", self);
+    }
+}
+
+/// staking rewards collected and loan interest paid on an epoch
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EpochRewards {
+    pub epoch_height: U64String,
+    pub rewards_stnear: U128String,
+    pub interest_stnear: U128String,
+    /// part of the interest for STBL stakers
+    pub stakers_stnear: U128String,
+    pub operator_stnear: U128String,
+    pub treasury_stnear: U128String,
+    pub reserve_stnear: U128String,
+}
+impl EpochRewards {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "epoch_rewards", self);
+    }
+}
+
+/// the peg controller changed the loan APR on an epoch
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InterestRateUpdate<'a> {
+    pub signal: &'a str,
+    pub old_apr_bp: u32,
+    pub new_apr_bp: u32,
+}
+impl InterestRateUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "interest_rate_update", self);
+    }
+}
+
+/// stability pool deposit, withdraw or claim
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StabilityPoolUpdate<'a> {
+    pub account_id: &'a str,
+    pub action: &'a str,
+    pub usdnear_amount: U128String,
+    pub stnear_gain: U128String,
+}
+impl StabilityPoolUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "stability_pool_update", self);
+    }
+}
+
+/// liquidation auction started, bid, completed or cancelled
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionUpdate<'a> {
+    pub loan_account_id: &'a str,
+    pub account_id: &'a str,
+    pub action: &'a str,
+    pub price: U128String,
+    pub usdnear_amount: U128String,
+    pub stnear_amount: U128String,
+}
+impl AuctionUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "auction_update", self);
+    }
+}
+
+/// a step of a bad debt write-off: write_off, reserve_cover, socialized, uncovered
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BadDebt<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loan_account_id: Option<&'a str>,
+    pub step: &'a str,
+    pub usdnear_amount: U128String,
+    pub stnear_amount: U128String,
+}
+impl BadDebt<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "bad_debt", self);
+    }
+}
+
+/// stNEAR moved into or out of the protocol reserve
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReserveUpdate<'a> {
+    pub account_id: &'a str,
+    pub action: &'a str,
+    pub stnear_amount: U128String,
+}
+impl ReserveUpdate<'_> {
+    pub fn emit(&self) {
+        emit_event(USDNEAR_STANDARD, USDNEAR_EVENTS_VERSION, "reserve_update", self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::events::*;
+    use near_sdk::serde_json::{self, Value};
+
+    fn parse_event_log(log: &str) -> Value {
+        assert!(log.starts_with("EVENT_JSON:"));
+        return serde_json::from_str(&log["EVENT_JSON:".len()..]).unwrap();
+    }
+
+    #[test]
+    fn nep141_events_follow_nep297() {
+        let transfer = FtTransfer { old_owner_id: "alice.near", new_owner_id: "bob.near", amount: (5 * NEAR).into(), memo: None };
+        let event = parse_event_log(&event_log(NEP141_STANDARD, NEP141_VERSION, "ft_transfer", &transfer));
+        assert_eq!(event["standard"], "nep141");
+        assert_eq!(event["version"], "1.0.0");
+        assert_eq!(event["event"], "ft_transfer");
+        // data is an array, amounts are strings and a missing memo is left out
+        assert_eq!(event["data"], serde_json::json!([{
+            "old_owner_id": "alice.near",
+            "new_owner_id": "bob.near",
+            "amount": (5 * NEAR).to_string(),
+        }]));
+    }
+
+    #[test]
+    fn memos_are_logged_when_present() {
+        let burn = FtBurn { owner_id: "alice.near", amount: NEAR.into(), memo: Some("repay_loan") };
+        let event = parse_event_log(&event_log(NEP141_STANDARD, NEP141_VERSION, "ft_burn", &burn));
+        assert_eq!(event["data"][0]["memo"], "repay_loan");
+    }
+}
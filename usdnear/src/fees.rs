@@ -0,0 +1,161 @@
+//
+// EPOCH INTEREST & FEES
+// every epoch the loan interest (in stNEAR) is split between STBL stakers, the protocol reserve (see reserve.rs), the operator and the treasury.
+// The operator and treasury shares are credited as free stNEAR to their accounts (withdrawable with withdraw_stnear).
+// Both accounts are always registered: new() registers them, the setters require it and they can't unregister
+// The last MAX_EPOCH_RECORDS distributions are kept on-chain in a ring buffer
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+/// ring buffer capacity, about a month of epochs
+pub const MAX_EPOCH_RECORDS: u64 = 60;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct EpochRecord {
+    pub epoch_height: EpochHeight,
+    pub timestamp: Timestamp,
+    /// staking rewards collected
+    pub rewards_stnear: u128,
+    /// loan interest, part of rewards_stnear
+    pub interest_stnear: u128,
+    pub stakers_stnear: u128,
+    pub operator_stnear: u128,
+    pub treasury_stnear: u128,
+    pub reserve_stnear: u128,
+}
+
+impl UsdNearStableCoin {
+
+    /// splits the interest left after the STBL stakers share between operator and treasury,
+    /// crediting it as free stNEAR. Returns (operator, treasury) amounts. Must not panic
+    pub(crate) fn pay_operator_and_treasury_fees(&mut self, amount: u128) -> (u128, u128) {
+        let amount_for_operator = apply_pct(self.operator_fee_basis_points as u32, amount);
+        let amount_for_treasury = amount - amount_for_operator;
+        let operator_id = self.operator_account_id.clone();
+        let treasury_id = self.treasury_account_id.clone();
+        self.add_amount_and_free_shares_preserve_share_price(operator_id.clone(), amount_for_operator);
+        self.add_amount_and_free_shares_preserve_share_price(treasury_id.clone(), amount_for_treasury);
+        self.update_mining_account(&operator_id);
+        self.update_mining_account(&treasury_id);
+        self.total_operator_fees_stnear += amount_for_operator;
+        self.total_treasury_fees_stnear += amount_for_treasury;
+        return (amount_for_operator, amount_for_treasury);
+    }
+
+    /// adds a record to the epoch ring buffer
+    pub(crate) fn record_epoch(&mut self, record: &EpochRecord) {
+        if self.epoch_records.len() < MAX_EPOCH_RECORDS {
+            self.epoch_records.push(record);
+        }
+        else {
+            self.epoch_records.replace(self.epoch_records_next, record);
+        }
+        self.epoch_records_next = (self.epoch_records_next + 1) % MAX_EPOCH_RECORDS;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Returns the interest distribution of the last epochs, oldest first
+    pub fn get_epoch_records(&self) -> Vec<EpochRecordJSON> {
+        let mut records = self.epoch_records.to_vec();
+        records.sort_by_key(|r| r.epoch_height);
+        return records.iter()
+            .map(|r| EpochRecordJSON {
+                epoch_height: r.epoch_height.into(),
+                timestamp: r.timestamp.into(),
+                rewards_stnear: r.rewards_stnear.into(),
+                interest_stnear: r.interest_stnear.into(),
+                stakers_stnear: r.stakers_stnear.into(),
+                operator_stnear: r.operator_stnear.into(),
+                treasury_stnear: r.treasury_stnear.into(),
+                reserve_stnear: r.reserve_stnear.into(),
+            })
+            .collect();
+    }
+
+    /// Returns the fees paid to operator, treasury and STBL stakers since deployment
+    pub fn get_fee_totals(&self) -> FeeTotalsJSON {
+        return FeeTotalsJSON {
+            operator_account_id: self.operator_account_id.clone(),
+            operator_fee_basis_points: self.operator_fee_basis_points,
+            total_operator_fees_stnear: self.total_operator_fees_stnear.into(),
+            treasury_account_id: self.treasury_account_id.clone(),
+            treasury_fee_basis_points: self.treasury_fee_basis_points,
+            total_treasury_fees_stnear: self.total_treasury_fees_stnear.into(),
+            total_stakers_fees_stnear: self.stbl_staking.total_distributed_stnear.into(),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn operator_and_treasury_are_registered_and_paid() {
+        let mut contract = new_contract();
+        assert!(contract.storage_balance_of(OPERATOR.into()).is_some());
+        assert!(contract.storage_balance_of(TREASURY.into()).is_some());
+
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        let t1 = t0 + 30 * EPOCH_DURATION;
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear + 10 * NEAR;
+        run_epoch(&mut contract, total_balance, t1);
+
+        let totals = contract.get_fee_totals();
+        assert!(totals.total_operator_fees_stnear.0 > 0);
+        assert!(totals.total_treasury_fees_stnear.0 > 0);
+        assert_eq!(contract.get_account_info(OPERATOR.into()).stnear.0, totals.total_operator_fees_stnear.0);
+        assert_eq!(contract.get_account_info(TREASURY.into()).stnear.0, totals.total_treasury_fees_stnear.0);
+        let record = &contract.get_epoch_records()[0];
+        assert_eq!(record.operator_stnear.0 + record.treasury_stnear.0 + record.stakers_stnear.0 + record.reserve_stnear.0, record.interest_stnear.0);
+    }
+
+    #[test]
+    fn epoch_records_keep_the_last_epochs() {
+        let mut contract = new_contract();
+        for epoch_height in 1..=MAX_EPOCH_RECORDS + 5 {
+            contract.record_epoch(&EpochRecord {
+                epoch_height,
+                timestamp: epoch_height * EPOCH_DURATION,
+                rewards_stnear: epoch_height as u128 * NEAR,
+                interest_stnear: 0,
+                stakers_stnear: 0,
+                operator_stnear: 0,
+                treasury_stnear: 0,
+                reserve_stnear: 0,
+            });
+        }
+        // the 5 oldest records were overwritten, the rest are returned oldest first
+        let records = contract.get_epoch_records();
+        assert_eq!(records.len() as u64, MAX_EPOCH_RECORDS);
+        assert_eq!(records[0].epoch_height.0, 6);
+        assert_eq!(records.last().unwrap().epoch_height.0, MAX_EPOCH_RECORDS + 5);
+        assert!(records.windows(2).all(|pair| pair[1].epoch_height.0 == pair[0].epoch_height.0 + 1));
+        assert_eq!(contract.epoch_records_next, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "not registered")]
+    fn treasury_must_be_registered() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, 10 * NANOS_PER_SECOND);
+        contract.set_treasury_account_id(CAROL.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "can't unregister")]
+    fn treasury_can_not_unregister() {
+        let mut contract = new_contract();
+        set_context(TREASURY, 1, 10 * NANOS_PER_SECOND);
+        contract.storage_unregister(None);
+    }
+}
@@ -3,15 +3,47 @@
 //
 
 pub const USDNEAR:&str="USDNEAR";
+pub const USDNEAR_NAME:&str="USDNEAR Stablecoin";
+pub const USDNEAR_DECIMALS:u8=24;
+pub const USDNEAR_ICON:&str="data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 32 32'%3E%3Ccircle cx='16' cy='16' r='16' fill='%2300c08b'/%3E%3Ctext x='16' y='22' font-size='16' font-family='sans-serif' text-anchor='middle' fill='white'%3E$%3C/text%3E%3C/svg%3E";
 
-use near_sdk::{near_bindgen};
+pub fn default_usdnear_metadata() -> FungibleTokenMetadata {
+    return FungibleTokenMetadata {
+        spec: FT_METADATA_SPEC.into(),
+        name: USDNEAR_NAME.into(),
+        symbol: USDNEAR.into(),
+        icon: Some(USDNEAR_ICON.into()),
+        reference: None,
+        reference_hash: None,
+        decimals: USDNEAR_DECIMALS,
+    }
+}
+
+pub fn assert_valid_ft_metadata(metadata: &FungibleTokenMetadata) {
+    assert_eq!(metadata.spec, FT_METADATA_SPEC, "metadata spec must be {}", FT_METADATA_SPEC);
+    assert!(metadata.reference.is_some() == metadata.reference_hash.is_some(), "reference and reference_hash must be set together");
+    if let Some(reference_hash) = &metadata.reference_hash {
+        assert_eq!(reference_hash.0.len(), 32, "reference_hash must be a sha256 hash (32 bytes)");
+    }
+}
+
+use near_sdk::{near_bindgen, Promise, PromiseResult};
 use crate::*;
 
 // callbacks here defined as traits to make it easy to create the promise
 #[ext_contract(ext_ft_receiver)]
 pub trait FunTokReceiver {
     //NEP-141 single fun token for the default token USDNEAR
-    fn ft_on_transfer(&mut self,sender_id: AccountId,amount: U128String, _msg: String); 
+    fn ft_on_transfer(&mut self,sender_id: AccountId,amount: U128String, msg: String) -> U128String; 
+}
+
+impl UsdNearStableCoin {
+    /// the account is registered, or still has storage from before NEP-145 (a USDNEAR balance or a borrowing account)
+    pub(crate) fn holds_usdnear_storage(&self, account_id: &AccountId) -> bool {
+        return self.storage_deposits.contains_key(account_id)
+            || self.usdnear_balances.contains_key(account_id)
+            || self.b_accounts.get(account_id).is_some();
+    }
 }
 
 pub fn assert_one_yocto(){
@@ -26,6 +58,11 @@ impl UsdNearStableCoin {
         return self.total_usdnear.into()
     }
 
+    /// NEP-148 Returns the token metadata
+    pub fn ft_metadata(&self) -> FungibleTokenMetadata {
+        return self.usdnear_metadata.clone()
+    }
+
     /// Returns the balance of the given account ID. Returns `0` balance, if the account doesn't exist.
     pub fn ft_balance_of(&self, account_id: AccountId) -> U128String {
         return self.get_usdnear_balance(&account_id).into()
@@ -33,73 +70,264 @@ impl UsdNearStableCoin {
 
     /// Transfer `amount` of tokens from the caller (`predecessor_id`) to `receiver_id`.
     /// Requirements:
+    /// * 1 yoctoNEAR attached (see security.rs)
     #[payable]
-    pub fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128String, #[allow(unused_variables)] memo:Option<String>){
+    pub fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128String, memo:Option<String>){
 
         //block function-call keys
-        //assert_one_yocto(); commented until fixed in wallet (no way to attach a single yocto)
+        self.assert_one_yocto_for("ft_transfer");
+        self.assert_not_paused("transfers");
+
+        //receiver must have paid for its storage
+        self.assert_registered(&receiver_id);
 
         //transfer
         self.usdnear_transfer(&env::predecessor_account_id(), &receiver_id, amount.0);
+        events::FtTransfer { old_owner_id: &env::predecessor_account_id(), new_owner_id: &receiver_id, amount, memo: memo.as_deref() }.emit();
     }
 
     /// Transfer `amount` of tokens from the caller of the contract (`predecessor_id`) to a contract at `receiver_id`.
     /// Requirements:
-    /// * receiver_id must be a contract and must respond to `ft_on_transfer(&mut self, sender_id: AccountId, amount: U128String, _msg: String ) -> u128`
+    /// * 1 yoctoNEAR attached (see security.rs)
+    /// * receiver_id must be a contract and must respond to `ft_on_transfer(&mut self, sender_id: AccountId, amount: U128String, msg: String ) -> U128String`
     /// * if receiver_id is not a contract or `ft_on_transfer` fails, the transfer is rolled-back
+    /// Returns a promise resolving to the amount actually transferred (see ft_resolve_transfer)
     #[payable]
-    pub fn ft_transfer_call(&mut self, receiver_id: AccountId, amount: U128String, msg:String, #[allow(unused_variables)] memo:Option<String>){
+    pub fn ft_transfer_call(&mut self, receiver_id: AccountId, amount: U128String, msg:String, memo:Option<String>) -> Promise {
 
         //block function-call keys
-        //assert_one_yocto(); commented until fixed in wallet (no way to attach a single yocto)
+        self.assert_one_yocto_for("ft_transfer_call");
+        self.assert_not_paused("transfers");
+
+        assert!(env::prepaid_gas() >= gas::FT_TRANSFER_CALL + gas::FT_ON_TRANSFER + gas::FT_RESOLVE_TRANSFER, "not enough gas attached");
+
+        //receiver must have paid for its storage
+        self.assert_registered(&receiver_id);
 
         //transfer
         self.usdnear_transfer(&env::predecessor_account_id(), &receiver_id, amount.0);
+        events::FtTransfer { old_owner_id: &env::predecessor_account_id(), new_owner_id: &receiver_id, amount, memo: memo.as_deref() }.emit();
 
         //call the receiving contract
-        ext_ft_receiver::ft_on_transfer(
+        return ext_ft_receiver::ft_on_transfer(
             env::predecessor_account_id(),
             amount,
             msg,
             //promise params:
             &receiver_id, //contract
-            0, //attached native NEAR amount
-            100_000_000_000_000, //100TGAS
+            NO_DEPOSIT, //attached native NEAR amount
+            env::prepaid_gas() - gas::FT_TRANSFER_CALL - gas::FT_RESOLVE_TRANSFER,
         )
-        .then(ext_self_callback::after_ft_on_transfer_usdnear(
+        .then(ext_self_callback::ft_resolve_transfer(
             env::predecessor_account_id(),
             receiver_id,
             amount,
             //promise params:
             &env::current_account_id(),//contract
-            0, //attached native NEAR amount
-            30_000_000_000_000, //30TGAS
+            NO_DEPOSIT, //attached native NEAR amount
+            gas::FT_RESOLVE_TRANSFER,
         ));
 
     }
-    /// After Transfer `amount` of symbol tokens to a contract at `receiver_id`.
-    /// Check if the contract completed execution of on_multifuntok_transfer
-    /// and undo trasnfer if it failed
-    pub fn after_ft_on_transfer_usdnear(&mut self, sender_id:AccountId, receiver_id: AccountId, amount: U128String, #[callback] unused_tokens: U128String){
+
+    /// NEP-141 callback after `ft_transfer_call` - must not panic
+    /// Checks the result of `receiver_id.ft_on_transfer` and refunds the unused amount to `sender_id`.
+    /// If `ft_on_transfer` failed, the whole amount is refunded.
+    /// Only what the receiver still holds can be refunded (it could have spent the tokens already).
+    /// If the sender unregistered meanwhile (and has no balance or borrowing account left), the refund is burned.
+    /// Returns the net amount transferred to the receiver
+    pub fn ft_resolve_transfer(&mut self, sender_id:AccountId, receiver_id: AccountId, amount: U128String) -> U128String {
 
         assert_callback_calling();
 
-        let amt = amount.0;
-        if !is_promise_success() {
-            //call failed/panicked
-            //undo the transfer
-            log!("call failed transfer reverted");
-            self.usdnear_transfer(&receiver_id, &sender_id, amt);
-        }
-        else {
-            if unused_tokens.0 > 0 {
-                //some tokens returned, max to undo is the amount trasnferred
-                let undo_amt = std::cmp::min(amt,unused_tokens.0);
-                //partially undo the transfer - max to undo is the amount trasnferred
-                self.usdnear_transfer(&receiver_id, &sender_id, undo_amt);
-                log!("{} unused tokens returned", undo_amt);
+        let amount = amount.0;
+
+        // get the unused amount from the `ft_on_transfer` call result
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128String>(&value) {
+                    std::cmp::min(amount, unused_amount.0)
+                } else {
+                    amount
+                }
+            }
+            //call failed/panicked, undo the transfer
+            _ => amount,
+        };
+
+        if unused_amount > 0 {
+            let receiver_balance = self.get_usdnear_balance(&receiver_id);
+            if receiver_balance > 0 {
+                //max to refund is what the receiver still has
+                let refund_amount = std::cmp::min(receiver_balance, unused_amount);
+                self.set_usdnear_balance(&receiver_id, receiver_balance - refund_amount);
+
+                if self.holds_usdnear_storage(&sender_id) {
+                    let sender_balance = self.get_usdnear_balance(&sender_id);
+                    self.set_usdnear_balance(&sender_id, sender_balance + refund_amount);
+                    events::FtTransfer { old_owner_id: &receiver_id, new_owner_id: &sender_id, amount: refund_amount.into(), memo: Some("refund") }.emit();
+                    return (amount - refund_amount).into();
+                }
+                else {
+                    // the sender unregistered and has nothing stored, burn the refund
+                    self.burn_usdnear_from_circulation(refund_amount);
+                    events::FtBurn { owner_id: &receiver_id, amount: refund_amount.into(), memo: Some("refund, sender is not registered") }.emit();
+                    return amount.into();
+                }
             }
         }
+        return amount.into();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::funtoken::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    /// ALICE borrows USDNEAR 450, BOB is registered
+    fn contract_with_usdnear() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        register(&mut contract, BOB, NANOS_PER_SECOND);
+        return contract;
+    }
+
+    /// ALICE sends USDNEAR 100 to BOB with ft_transfer_call
+    fn transfer_call_to_bob(contract: &mut UsdNearStableCoin) {
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        contract.ft_transfer_call(BOB.into(), (100 * NEAR).into(), String::new(), None);
     }
 
+    #[test]
+    fn ft_transfer_moves_the_balance() {
+        let mut contract = contract_with_usdnear();
+        let alice_balance = contract.ft_balance_of(ALICE.into()).0;
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        contract.ft_transfer(BOB.into(), (100 * NEAR).into(), None);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, alice_balance - 100 * NEAR);
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 100 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account carol.near is not registered")]
+    fn ft_transfer_requires_a_registered_receiver() {
+        let mut contract = contract_with_usdnear();
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        contract.ft_transfer(CAROL.into(), (100 * NEAR).into(), None);
+    }
+
+    #[test]
+    fn ft_resolve_transfer_refunds_the_unused_amount() {
+        let mut contract = contract_with_usdnear();
+        let alice_balance = contract.ft_balance_of(ALICE.into()).0;
+        transfer_call_to_bob(&mut contract);
+
+        // BOB used 60 and returns 40
+        let unused = near_sdk::serde_json::to_vec(&U128String::from(40 * NEAR)).unwrap();
+        set_callback_context(PromiseResult::Successful(unused), NANOS_PER_SECOND);
+        let transferred = contract.ft_resolve_transfer(ALICE.into(), BOB.into(), (100 * NEAR).into());
+
+        assert_eq!(transferred.0, 60 * NEAR);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, alice_balance - 60 * NEAR);
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 60 * NEAR);
+    }
+
+    #[test]
+    fn ft_resolve_transfer_refunds_only_what_the_receiver_holds() {
+        let mut contract = contract_with_usdnear();
+        let alice_balance = contract.ft_balance_of(ALICE.into()).0;
+        transfer_call_to_bob(&mut contract);
+        // BOB spends 70 before failing
+        set_context(BOB, 1, NANOS_PER_SECOND);
+        contract.ft_transfer(ALICE.into(), (70 * NEAR).into(), None);
+
+        set_callback_context(PromiseResult::Failed, NANOS_PER_SECOND);
+        let transferred = contract.ft_resolve_transfer(ALICE.into(), BOB.into(), (100 * NEAR).into());
+
+        assert_eq!(transferred.0, 70 * NEAR);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, alice_balance);
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 0);
+    }
+
+    #[test]
+    fn ft_resolve_transfer_burns_the_refund_of_an_unregistered_sender() {
+        let mut contract = contract_with_usdnear();
+        let alice_balance = contract.ft_balance_of(ALICE.into()).0;
+        let total_supply = contract.ft_total_supply().0;
+        transfer_call_to_bob(&mut contract);
+        // BOB sends everything back to ALICE with ft_transfer_call, and unregisters before the callback
+        set_context(BOB, 1, NANOS_PER_SECOND);
+        contract.ft_transfer_call(ALICE.into(), (100 * NEAR).into(), String::new(), None);
+        assert!(contract.storage_unregister(None));
+
+        set_callback_context(PromiseResult::Failed, NANOS_PER_SECOND);
+        let transferred = contract.ft_resolve_transfer(BOB.into(), ALICE.into(), (100 * NEAR).into());
+
+        assert_eq!(transferred.0, 100 * NEAR);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, alice_balance - 100 * NEAR);
+        assert_eq!(contract.ft_total_supply().0, total_supply - 100 * NEAR);
+    }
+
+    #[test]
+    fn ft_resolve_transfer_refunds_a_sender_with_a_loan() {
+        let mut contract = contract_with_usdnear();
+        let alice_balance = contract.ft_balance_of(ALICE.into()).0;
+        transfer_call_to_bob(&mut contract);
+        // a legacy account that was never registered
+        contract.storage_deposits.remove(&ALICE.into());
+
+        set_callback_context(PromiseResult::Failed, NANOS_PER_SECOND);
+        let transferred = contract.ft_resolve_transfer(ALICE.into(), BOB.into(), (100 * NEAR).into());
+
+        assert_eq!(transferred.0, 0);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, alice_balance);
+    }
+
+    #[test]
+    #[should_panic]
+    fn ft_resolve_transfer_is_a_private_callback() {
+        let mut contract = contract_with_usdnear();
+        transfer_call_to_bob(&mut contract);
+        set_context(BOB, 0, NANOS_PER_SECOND);
+        contract.ft_resolve_transfer(ALICE.into(), BOB.into(), (100 * NEAR).into());
+    }
+
+    #[test]
+    fn metadata_can_be_updated_but_not_the_symbol() {
+        let mut contract = new_contract();
+        let metadata = contract.ft_metadata();
+        assert_eq!(metadata.spec, FT_METADATA_SPEC);
+        assert_eq!(metadata.symbol, USDNEAR);
+        assert_eq!(metadata.decimals, 24);
+
+        let mut new_metadata = default_usdnear_metadata();
+        new_metadata.name = "USDNEAR Stablecoin v2".into();
+        new_metadata.reference = Some("https://example.com/usdnear.json".into());
+        new_metadata.reference_hash = Some(vec![7u8; 32].into());
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_ft_metadata(new_metadata);
+        assert_eq!(contract.ft_metadata().name, "USDNEAR Stablecoin v2");
+    }
+
+    #[test]
+    #[should_panic(expected = "symbol can not be changed")]
+    fn metadata_symbol_is_fixed() {
+        let mut contract = new_contract();
+        let mut metadata = default_usdnear_metadata();
+        metadata.symbol = "USDN".into();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_ft_metadata(metadata);
+    }
+
+    #[test]
+    #[should_panic(expected = "reference and reference_hash must be set together")]
+    fn metadata_reference_needs_its_hash() {
+        let mut metadata = default_usdnear_metadata();
+        metadata.reference = Some("https://example.com/usdnear.json".into());
+        assert_valid_ft_metadata(&metadata);
+    }
 }
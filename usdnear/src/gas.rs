@@ -7,3 +7,10 @@ pub const AFTER_TRANSFER_STNEAR: u64 = BASE_GAS*3;
 pub const GET_ACCOUNT_TOTAL_BALANCE: u64 = BASE_GAS*3;
 pub const AFTER_GET_ACCOUNT_TOTAL_BALANCE : u64 = BASE_GAS*5;
 
+
+pub const FT_TRANSFER_CALL: u64 = BASE_GAS;
+pub const FT_ON_TRANSFER: u64 = BASE_GAS*4;
+pub const FT_RESOLVE_TRANSFER: u64 = 30*TGAS;
+
+pub const GET_PRICE_DATA: u64 = BASE_GAS;
+pub const AFTER_GET_PRICE_DATA: u64 = BASE_GAS*2;
@@ -0,0 +1,625 @@
+//
+// STBL GOVERNANCE
+// staked STBL holders create and vote proposals to change contract params, price reporters, fee splits, the interest rate model and the STBL mint schedule.
+// Voting power is the STBL staked before the proposal was created (per-account stake checkpoints),
+// so STBL staked or moved during the vote doesn't count.
+// A proposal passes if, when the voting period ends, votes reach `quorum_bp` of the total voting power
+// (the total staked STBL before the proposal was created, same rule as the voters' power)
+// and yes votes reach `threshold_bp` of the votes. Anyone can then execute it, through the same code path as the owner's methods.
+// Contract params, fee split, rate model and mint schedule changes are not applied on execution: they are queued behind the params timelock (see timelock.rs)
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+/// default voting period: 3 days
+pub const DEFAULT_VOTING_PERIOD: Duration = 3 * 24 * ONE_HOUR;
+/// default quorum: 10% of the staked STBL
+pub const DEFAULT_QUORUM_BP: u32 = 10 * PERCENT_BP;
+/// default threshold: more than 50% yes votes
+pub const DEFAULT_THRESHOLD_BP: u32 = 50 * PERCENT_BP;
+/// default staked STBL to create a proposal
+pub const DEFAULT_MIN_PROPOSAL_STAKE: u128 = 10_000 * NEAR;
+/// per-account stake checkpoints kept. Checkpoints older than the active proposals are dropped,
+/// then the two oldest are merged (see record_stake_checkpoint)
+pub const MAX_STAKE_CHECKPOINTS: usize = 10;
+/// per-account votes kept for active proposals, so the account storage is bounded
+pub const MAX_ACTIVE_VOTES: usize = 10;
+pub const MAX_PROPOSAL_DESCRIPTION_LEN: usize = 1024;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum GovernanceAction {
+    /// queued as a contract params change (same path as propose_contract_params)
+    ContractParams(ContractParamsJSON),
+    AddPriceReporter { account_id: AccountId },
+    RemovePriceReporter { account_id: AccountId },
+    /// interest split between operator & treasury, and the STBL stakers share. Queued as a contract params change
+    FeeSplit { operator_fee_basis_points: u16, treasury_fee_basis_points: u16, stbl_stakers_fee_share_bp: u32 },
+    /// selects and parameterizes the interest rate model (see rate_model.rs). Queued as a contract params change
+    RateModel(RateModel),
+    /// STBL emission and hard cap (see stbl.rs). Queued as a contract params change
+    StblMintSchedule { emission_per_second: U128String, max_supply: U128String },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct GovernanceVote {
+    pub proposal_id: u64,
+    pub support: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct StakeCheckpoint {
+    pub timestamp: Timestamp,
+    pub staked: u128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Active,
+    Passed,
+    Rejected,
+    Executed,
+    Expired,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct GovernanceProposal {
+    pub action: GovernanceAction,
+    pub description: String,
+    pub proposer: AccountId,
+    pub created_at: Timestamp,
+    pub voting_ends_at: Timestamp,
+    /// total staked STBL before creation
+    pub total_voting_power: u128,
+    pub yes_votes: u128,
+    pub no_votes: u128,
+    pub executed: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceConfig {
+    pub voting_period_seconds: U64String,
+    /// min votes (yes+no), basis points of total_voting_power
+    pub quorum_bp: u32,
+    /// min yes votes, basis points of yes+no
+    pub threshold_bp: u32,
+    /// min staked STBL to create a proposal
+    pub min_proposal_stake: U128String,
+}
+
+impl Default for GovernanceConfig {
+    fn default() -> Self {
+        Self {
+            voting_period_seconds: (DEFAULT_VOTING_PERIOD / NANOS_PER_SECOND).into(),
+            quorum_bp: DEFAULT_QUORUM_BP,
+            threshold_bp: DEFAULT_THRESHOLD_BP,
+            min_proposal_stake: DEFAULT_MIN_PROPOSAL_STAKE.into(),
+        }
+    }
+}
+
+impl UsdNearStableCoin {
+
+    /// records the account's staked STBL, called on every stake/unstake
+    pub(crate) fn record_stake_checkpoint(&mut self, account_id: &AccountId, staked: u128) {
+        let now = env::block_timestamp();
+        let mut checkpoints = self.stake_checkpoints.get(account_id).unwrap_or_default();
+        match checkpoints.last_mut() {
+            Some(last) if last.timestamp == now => last.staked = staked,
+            _ => checkpoints.push(StakeCheckpoint { timestamp: now, staked }),
+        }
+        if checkpoints.len() > MAX_STAKE_CHECKPOINTS {
+            // the last checkpoint before the oldest active proposal is its voting power, older ones are not needed
+            let snapshot = self.oldest_active_proposal_created_at();
+            let needed_from = checkpoints.iter().rposition(|c| c.timestamp < snapshot).unwrap_or(0);
+            checkpoints.drain(..needed_from);
+        }
+        if checkpoints.len() > MAX_STAKE_CHECKPOINTS {
+            // all may be the voting power of an active proposal: merge the two oldest with the lower stake,
+            // so a merge never adds voting power
+            let oldest = checkpoints.remove(0);
+            checkpoints[0] = StakeCheckpoint { timestamp: oldest.timestamp, staked: std::cmp::min(oldest.staked, checkpoints[0].staked) };
+        }
+        if checkpoints.len() == 1 && staked == 0 {
+            self.stake_checkpoints.remove(account_id);
+        } else {
+            self.stake_checkpoints.insert(account_id, &checkpoints);
+        }
+    }
+
+    /// created_at of the oldest active proposal (now if none), scanning back from the last proposal
+    fn oldest_active_proposal_created_at(&self) -> Timestamp {
+        let now = env::block_timestamp();
+        let mut oldest = now;
+        for index in (0..self.governance_proposals.len()).rev() {
+            let proposal = self.governance_proposals.get(index).unwrap();
+            if proposal.voting_ends_at <= now {
+                break;
+            }
+            oldest = proposal.created_at;
+        }
+        return oldest;
+    }
+
+    /// records the total staked STBL before it changes in this block, called on every stake/unstake
+    pub(crate) fn record_total_stake_checkpoint(&mut self) {
+        let now = env::block_timestamp();
+        if self.total_stake_checkpoint.timestamp != now {
+            self.total_stake_checkpoint = StakeCheckpoint { timestamp: now, staked: self.stbl_staking.total_staked };
+        }
+    }
+
+    /// total staked STBL before now, the total voting power of a proposal created now (same rule as voting_power_at)
+    pub(crate) fn total_voting_power_now(&self) -> u128 {
+        if self.total_stake_checkpoint.timestamp == env::block_timestamp() {
+            return self.total_stake_checkpoint.staked;
+        }
+        return self.stbl_staking.total_staked;
+    }
+
+    /// staked STBL before `timestamp`
+    pub(crate) fn voting_power_at(&self, account_id: &AccountId, timestamp: Timestamp) -> u128 {
+        let checkpoints = self.stake_checkpoints.get(account_id).unwrap_or_default();
+        return match checkpoints.iter().rev().find(|c| c.timestamp < timestamp) {
+            Some(checkpoint) => checkpoint.staked,
+            None => 0,
+        };
+    }
+
+    fn proposal_status(&self, proposal: &GovernanceProposal) -> ProposalStatus {
+        let now = env::block_timestamp();
+        if proposal.executed {
+            return ProposalStatus::Executed;
+        }
+        if now < proposal.voting_ends_at {
+            return ProposalStatus::Active;
+        }
+        let votes = proposal.yes_votes + proposal.no_votes;
+        let passed = votes > 0
+            && votes >= apply_pct(self.governance_config.quorum_bp, proposal.total_voting_power)
+            && proposal.yes_votes > apply_pct(self.governance_config.threshold_bp, votes);
+        if !passed {
+            return ProposalStatus::Rejected;
+        }
+        if now > proposal.voting_ends_at + PARAMS_PROPOSAL_GRACE_PERIOD {
+            return ProposalStatus::Expired;
+        }
+        return ProposalStatus::Passed;
+    }
+
+    fn assert_valid_governance_action(&self, action: &GovernanceAction) {
+        match action {
+            GovernanceAction::ContractParams(params) => self.assert_valid_contract_params(params),
+            GovernanceAction::AddPriceReporter { account_id } => {
+                assert!(env::is_valid_account_id(account_id.as_bytes()), "invalid account id {}", account_id);
+                assert!(self.price_reports.get(account_id).is_none(), "{} is already a price reporter", account_id);
+            }
+            GovernanceAction::RemovePriceReporter { account_id } => {
+                assert!(self.price_reports.get(account_id).is_some(), "{} is not a price reporter", account_id);
+            }
+            GovernanceAction::FeeSplit { operator_fee_basis_points, treasury_fee_basis_points, stbl_stakers_fee_share_bp } => {
+                assert!(operator_fee_basis_points + treasury_fee_basis_points == 10000, "fee split must add 100%");
+                assert!(*stbl_stakers_fee_share_bp <= 10000, "stbl_stakers_fee_share_bp must be <= 10000");
+            }
+            GovernanceAction::RateModel(rate_model) => rate_model.as_model().assert_valid(),
+            GovernanceAction::StblMintSchedule { max_supply, .. } => self.assert_valid_stbl_max_supply(max_supply.0),
+        }
+    }
+
+    fn execute_governance_action(&mut self, action: &GovernanceAction) {
+        match action {
+            GovernanceAction::ContractParams(params) => {
+                self.internal_queue_params_proposal(ParamsChange::ContractParams(params.clone()));
+            }
+            GovernanceAction::AddPriceReporter { account_id } => self.internal_add_price_reporter(account_id.clone()),
+            GovernanceAction::RemovePriceReporter { account_id } => self.internal_remove_price_reporter(account_id.clone()),
+            GovernanceAction::FeeSplit { operator_fee_basis_points, treasury_fee_basis_points, stbl_stakers_fee_share_bp } => {
+                self.internal_queue_params_proposal(ParamsChange::FeeSplit {
+                    operator_fee_basis_points: *operator_fee_basis_points,
+                    treasury_fee_basis_points: *treasury_fee_basis_points,
+                    stbl_stakers_fee_share_bp: *stbl_stakers_fee_share_bp,
+                });
+            }
+            GovernanceAction::RateModel(rate_model) => {
+                self.internal_queue_params_proposal(ParamsChange::RateModel(rate_model.clone()));
+            }
+            GovernanceAction::StblMintSchedule { emission_per_second, max_supply } => {
+                self.internal_queue_params_proposal(ParamsChange::StblMintSchedule {
+                    emission_per_second: *emission_per_second,
+                    max_supply: *max_supply,
+                });
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Creates a governance proposal. The caller needs `min_proposal_stake` staked STBL. Returns the proposal id
+    pub fn create_governance_proposal(&mut self, action: GovernanceAction, description: String) -> U64String {
+        let proposer = env::predecessor_account_id();
+        let staked = self.staking_accounts.get(&proposer).map(|s| s.staked).unwrap_or_default();
+        assert!(staked >= self.governance_config.min_proposal_stake.0, "you need STBL {} staked to create a proposal", self.governance_config.min_proposal_stake.0);
+        assert!(description.len() <= MAX_PROPOSAL_DESCRIPTION_LEN, "max description length is {}", MAX_PROPOSAL_DESCRIPTION_LEN);
+        self.assert_valid_governance_action(&action);
+
+        let proposal_id = self.governance_proposals.len();
+        let now = env::block_timestamp();
+        let proposal = GovernanceProposal {
+            action,
+            description,
+            proposer: proposer.clone(),
+            created_at: now,
+            voting_ends_at: now + self.governance_config.voting_period_seconds.0 * NANOS_PER_SECOND,
+            total_voting_power: self.total_voting_power_now(),
+            yes_votes: 0,
+            no_votes: 0,
+            executed: false,
+        };
+        self.governance_proposals.push(&proposal);
+        events::GovernanceProposalUpdate { proposal_id: proposal_id.into(), account_id: &proposer, action: "created" }.emit();
+        return proposal_id.into();
+    }
+
+    /// Votes an active proposal with the STBL staked before the proposal was created
+    pub fn vote(&mut self, proposal_id: U64String, support: bool) {
+        let voter = env::predecessor_account_id();
+        self.assert_registered(&voter);
+        let mut proposal = self.governance_proposals.get(proposal_id.0).expect("proposal not found");
+        assert!(self.proposal_status(&proposal) == ProposalStatus::Active, "voting has ended");
+        // only the votes of active proposals are kept
+        let mut votes = self.governance_votes.get(&voter).unwrap_or_default();
+        votes.retain(|v| self.proposal_status(&self.governance_proposals.get(v.proposal_id).unwrap()) == ProposalStatus::Active);
+        assert!(votes.iter().all(|v| v.proposal_id != proposal_id.0), "already voted");
+        assert!(votes.len() < MAX_ACTIVE_VOTES, "you can vote at most {} active proposals", MAX_ACTIVE_VOTES);
+        let voting_power = self.voting_power_at(&voter, proposal.created_at);
+        assert!(voting_power > 0, "no voting power, you had no STBL staked when the proposal was created");
+        if support {
+            proposal.yes_votes += voting_power;
+        } else {
+            proposal.no_votes += voting_power;
+        }
+        votes.push(GovernanceVote { proposal_id: proposal_id.0, support });
+        self.governance_votes.insert(&voter, &votes);
+        self.governance_proposals.replace(proposal_id.0, &proposal);
+        events::GovernanceVote { proposal_id, voter: &voter, support, voting_power: voting_power.into() }.emit();
+    }
+
+    /// Executes a passed proposal. Anyone can call it
+    pub fn execute_governance_proposal(&mut self, proposal_id: U64String) {
+        let mut proposal = self.governance_proposals.get(proposal_id.0).expect("proposal not found");
+        let status = self.proposal_status(&proposal);
+        assert!(status == ProposalStatus::Passed, "proposal is {:?}", status);
+        proposal.executed = true;
+        self.governance_proposals.replace(proposal_id.0, &proposal);
+        self.execute_governance_action(&proposal.action);
+        events::GovernanceProposalUpdate { proposal_id, account_id: &env::predecessor_account_id(), action: "executed" }.emit();
+    }
+
+    /// Owner's method.
+    /// Sets voting period, quorum, threshold and min stake to create proposals. Applies to new and active proposals
+    pub fn set_governance_config(&mut self, config: GovernanceConfig) {
+        self.assert_owner_calling();
+        assert!(config.voting_period_seconds.0 > 0, "voting period must be positive");
+        assert!(config.quorum_bp <= 10000 && config.threshold_bp < 10000, "invalid quorum or threshold");
+        self.governance_config = config;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "governance_config",
+            value: format!("voting_period_seconds:{} quorum_bp:{} threshold_bp:{} min_proposal_stake:{}",
+                self.governance_config.voting_period_seconds.0, self.governance_config.quorum_bp,
+                self.governance_config.threshold_bp, self.governance_config.min_proposal_stake.0),
+        }.emit();
+    }
+
+    pub fn get_governance_config(&self) -> GovernanceConfig {
+        return self.governance_config.clone();
+    }
+
+    pub fn get_number_of_governance_proposals(&self) -> u64 {
+        return self.governance_proposals.len();
+    }
+
+    /// Returns a partial list of governance proposals
+    pub fn get_governance_proposals(&self, from_index: u64, limit: u32) -> Vec<GovernanceProposalJSON> {
+        return (from_index..std::cmp::min(from_index + limit as u64, self.governance_proposals.len()))
+            .map(|index| {
+                let proposal = self.governance_proposals.get(index).unwrap();
+                GovernanceProposalJSON {
+                    proposal_id: index.into(),
+                    status: self.proposal_status(&proposal),
+                    description: proposal.description,
+                    proposer: proposal.proposer,
+                    created_at: proposal.created_at.into(),
+                    voting_ends_at: proposal.voting_ends_at.into(),
+                    total_voting_power: proposal.total_voting_power.into(),
+                    yes_votes: proposal.yes_votes.into(),
+                    no_votes: proposal.no_votes.into(),
+                    action: proposal.action,
+                }
+            })
+            .collect();
+    }
+
+    /// Returns the voting power of `account_id` for a proposal and its vote, if any
+    pub fn get_governance_vote(&self, proposal_id: U64String, account_id: AccountId) -> GovernanceVoteJSON {
+        let proposal = self.governance_proposals.get(proposal_id.0).expect("proposal not found");
+        return GovernanceVoteJSON {
+            voting_power: self.voting_power_at(&account_id, proposal.created_at).into(),
+            vote: self.governance_votes.get(&account_id).unwrap_or_default().iter()
+                .find(|v| v.proposal_id == proposal_id.0)
+                .map(|v| v.support),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    fn pass(contract: &mut UsdNearStableCoin, action: GovernanceAction, block_timestamp: Timestamp) -> Timestamp {
+        set_context(ALICE, 0, block_timestamp);
+        let proposal_id = contract.create_governance_proposal(action, "test".into());
+        set_context(ALICE, 0, block_timestamp + ONE_HOUR);
+        contract.vote(proposal_id, true);
+        let voting_ends_at = block_timestamp + DEFAULT_VOTING_PERIOD;
+        set_context(BOB, 0, voting_ends_at);
+        contract.execute_governance_proposal(proposal_id);
+        return voting_ends_at;
+    }
+
+    #[test]
+    fn fee_split_is_queued_behind_the_params_timelock() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        let params_before = contract.get_contract_params();
+        let fee_share_before = contract.stbl_staking.fee_share_bp;
+        let action = GovernanceAction::FeeSplit { operator_fee_basis_points: 2000, treasury_fee_basis_points: 8000, stbl_stakers_fee_share_bp: fee_share_before + 1000 };
+        let executed_at = pass(&mut contract, action, t0 + ONE_HOUR);
+
+        // executing the governance proposal only queues the change
+        assert_eq!(contract.get_contract_params().operator_fee_basis_points, params_before.operator_fee_basis_points);
+        assert_eq!(contract.stbl_staking.fee_share_bp, fee_share_before);
+        let queued = contract.get_params_proposals();
+        assert_eq!(queued.len(), 1);
+        assert!(matches!(queued[0].change, ParamsChange::FeeSplit { stbl_stakers_fee_share_bp, .. } if stbl_stakers_fee_share_bp == fee_share_before + 1000));
+        assert!(!queued[0].executable);
+
+        set_context(BOB, 0, executed_at + DEFAULT_PARAMS_TIMELOCK);
+        contract.execute_params_proposal(queued[0].proposal_id);
+        assert_eq!(contract.get_contract_params().operator_fee_basis_points, 2000);
+        assert_eq!(contract.get_contract_params().treasury_fee_basis_points, 8000);
+        assert_eq!(contract.stbl_staking.fee_share_bp, fee_share_before + 1000);
+    }
+
+    #[test]
+    fn rate_model_is_queued_behind_the_params_timelock() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        let apr_before = contract.current_apr_basis_points();
+        let rate_model = RateModel::KinkedUtilization(KinkedUtilizationRate {
+            base_apr_bp: apr_before + 100, slope1_bp: 0, kink_utilization_bp: 8000, slope2_bp: 0,
+        });
+        let executed_at = pass(&mut contract, GovernanceAction::RateModel(rate_model), t0 + ONE_HOUR);
+        assert_eq!(contract.current_apr_basis_points(), apr_before);
+
+        // a params change executed in between is not reverted by the rate model proposal
+        set_context(OWNER, 0, executed_at);
+        let mut params = contract.get_contract_params();
+        params.min_collateral_basis_points += 100;
+        let params_proposal_id = contract.propose_contract_params(params.clone());
+        set_context(BOB, 0, executed_at + DEFAULT_PARAMS_TIMELOCK);
+        contract.execute_params_proposal(params_proposal_id);
+        contract.execute_params_proposal(0.into());
+        assert_eq!(contract.current_apr_basis_points(), apr_before + 100);
+        assert_eq!(contract.get_contract_params().min_collateral_basis_points, params.min_collateral_basis_points);
+    }
+
+    #[test]
+    fn stbl_mint_schedule_is_queued_behind_the_params_timelock() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        let action = GovernanceAction::StblMintSchedule { emission_per_second: NEAR.into(), max_supply: (1_000_000 * NEAR).into() };
+        let executed_at = pass(&mut contract, action, t0 + ONE_HOUR);
+        assert_eq!(contract.get_stbl_mint_schedule().emission_per_second.0, DEFAULT_STBL_EMISSION_PER_SECOND);
+
+        set_context(BOB, 0, executed_at + DEFAULT_PARAMS_TIMELOCK);
+        contract.execute_params_proposal(0.into());
+        let schedule = contract.get_stbl_mint_schedule();
+        assert_eq!(schedule.emission_per_second.0, NEAR);
+        assert_eq!(schedule.max_supply.0, 1_000_000 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "max supply can not be lower than the total supply")]
+    fn stbl_max_supply_can_not_be_proposed_below_the_total_supply() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        set_context(ALICE, 0, t0 + ONE_HOUR);
+        let action = GovernanceAction::StblMintSchedule { emission_per_second: NEAR.into(), max_supply: (10_000 * NEAR).into() };
+        contract.create_governance_proposal(action, "test".into());
+    }
+
+    #[test]
+    fn many_stake_changes_keep_the_voting_power_of_active_proposals() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        stake_stbl(&mut contract, BOB, 10_000 * NEAR, t0);
+        contract.set_stbl_balance(&BOB.into(), 11 * NEAR);
+        contract.total_stbl += 11 * NEAR;
+        let t1 = t0 + ONE_HOUR;
+        set_context(ALICE, 0, t1);
+        let proposal_id = contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+
+        // BOB stakes 11 times during the vote: more checkpoints than MAX_STAKE_CHECKPOINTS
+        for n in 1..=11 {
+            set_context(BOB, 1, t1 + n * NANOS_PER_SECOND);
+            contract.stake_stbl(NEAR.into());
+        }
+        assert_eq!(contract.stake_checkpoints.get(&BOB.into()).unwrap().len(), MAX_STAKE_CHECKPOINTS);
+        set_context(BOB, 0, t1 + ONE_HOUR);
+        contract.vote(proposal_id, false);
+        assert_eq!(contract.get_governance_vote(proposal_id, BOB.into()).voting_power.0, 10_000 * NEAR);
+        assert_eq!(contract.get_governance_proposals(proposal_id.0, 1)[0].no_votes.0, 10_000 * NEAR);
+    }
+
+    #[test]
+    fn stake_checkpoints_older_than_the_active_proposals_are_dropped() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        contract.set_stbl_balance(&ALICE.into(), 11 * NEAR);
+        contract.total_stbl += 11 * NEAR;
+        for n in 1..=11 {
+            set_context(ALICE, 1, t0 + n * NANOS_PER_SECOND);
+            contract.stake_stbl(NEAR.into());
+        }
+        // no active proposal: the 11th checkpoint pruned them to the current stake and the one before its block
+        let checkpoints = contract.stake_checkpoints.get(&ALICE.into()).unwrap();
+        assert_eq!(checkpoints.len(), 3);
+        assert_eq!(checkpoints[2].staked, 20_011 * NEAR);
+        set_context(ALICE, 0, t0 + ONE_HOUR);
+        assert_eq!(contract.voting_power_at(&ALICE.into(), t0 + ONE_HOUR), 20_011 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "can not be executed before")]
+    fn contract_params_wait_for_the_params_timelock() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        let mut params = contract.get_contract_params();
+        params.usdnear_apr_basis_points = 500;
+        let executed_at = pass(&mut contract, GovernanceAction::ContractParams(params), t0 + ONE_HOUR);
+        assert_eq!(contract.get_contract_params().usdnear_apr_basis_points, 250);
+
+        set_context(BOB, 0, executed_at + ONE_HOUR);
+        contract.execute_params_proposal(0.into());
+    }
+
+    #[test]
+    fn total_voting_power_excludes_stake_in_the_creation_block() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_governance_config(GovernanceConfig { quorum_bp: 50 * PERCENT_BP, ..GovernanceConfig::default() });
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        // BOB stakes in the block the proposal is created: no voting power, and not in the quorum base
+        let t1 = t0 + ONE_HOUR;
+        stake_stbl(&mut contract, BOB, 100_000 * NEAR, t1);
+        set_context(ALICE, 0, t1);
+        let proposal_id = contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        let proposal = &contract.get_governance_proposals(proposal_id.0, 1)[0];
+        assert_eq!(proposal.total_voting_power.0, 20_000 * NEAR);
+        assert_eq!(contract.get_governance_vote(proposal_id, BOB.into()).voting_power.0, 0);
+
+        set_context(ALICE, 0, t1 + ONE_HOUR);
+        contract.vote(proposal_id, true);
+        set_context(BOB, 0, t1 + DEFAULT_VOTING_PERIOD);
+        assert_eq!(contract.get_governance_proposals(proposal_id.0, 1)[0].status, ProposalStatus::Passed);
+    }
+
+    #[test]
+    fn votes_are_bounded_to_the_active_proposals() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        let t1 = t0 + ONE_HOUR;
+        set_context(ALICE, 0, t1);
+        for _ in 0..MAX_ACTIVE_VOTES + 1 {
+            contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        }
+        set_context(ALICE, 0, t1 + ONE_HOUR);
+        for proposal_id in 0..MAX_ACTIVE_VOTES as u64 {
+            contract.vote(proposal_id.into(), true);
+        }
+        assert_eq!(contract.get_governance_vote(0.into(), ALICE.into()).vote, Some(true));
+
+        // once the first proposals end their votes are dropped, making room for new ones
+        let t2 = t1 + DEFAULT_VOTING_PERIOD;
+        set_context(ALICE, 0, t2);
+        let proposal_id = contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        set_context(ALICE, 0, t2 + ONE_HOUR);
+        contract.vote(proposal_id, false);
+        assert_eq!(contract.governance_votes.get(&ALICE.into()).unwrap().len(), 1);
+        assert_eq!(contract.get_governance_vote(proposal_id, ALICE.into()).vote, Some(false));
+    }
+
+    #[test]
+    #[should_panic(expected = "you can vote at most")]
+    fn votes_of_active_proposals_are_limited() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        let t1 = t0 + ONE_HOUR;
+        set_context(ALICE, 0, t1);
+        for _ in 0..MAX_ACTIVE_VOTES + 1 {
+            contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        }
+        set_context(ALICE, 0, t1 + ONE_HOUR);
+        for proposal_id in 0..MAX_ACTIVE_VOTES as u64 + 1 {
+            contract.vote(proposal_id.into(), true);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal is Rejected")]
+    fn proposals_below_the_quorum_are_rejected() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        stake_stbl(&mut contract, BOB, 200_000 * NEAR, t0);
+        // ALICE's votes are 9.09% of the staked STBL, the quorum is 10%
+        let t1 = t0 + ONE_HOUR;
+        set_context(ALICE, 0, t1);
+        let proposal_id = contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        set_context(ALICE, 0, t1 + ONE_HOUR);
+        contract.vote(proposal_id, true);
+        set_context(BOB, 0, t1 + DEFAULT_VOTING_PERIOD);
+        assert_eq!(contract.get_governance_proposals(proposal_id.0, 1)[0].status, ProposalStatus::Rejected);
+        contract.execute_governance_proposal(proposal_id);
+    }
+
+    #[test]
+    fn proposals_need_more_yes_votes_than_the_threshold() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 20_000 * NEAR, t0);
+        stake_stbl(&mut contract, BOB, 20_000 * NEAR, t0);
+        stake_stbl(&mut contract, CAROL, NEAR, t0);
+        let t1 = t0 + ONE_HOUR;
+        set_context(ALICE, 0, t1);
+        let tied = contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        let passed = contract.create_governance_proposal(GovernanceAction::AddPriceReporter { account_id: CAROL.into() }, "test".into());
+        let t2 = t1 + ONE_HOUR;
+        for proposal_id in [tied, passed] {
+            set_context(ALICE, 0, t2);
+            contract.vote(proposal_id, true);
+            set_context(BOB, 0, t2);
+            contract.vote(proposal_id, false);
+        }
+        // 50% yes votes is not more than the threshold, CAROL's vote breaks the tie
+        set_context(CAROL, 0, t2);
+        contract.vote(passed, true);
+
+        set_context(BOB, 0, t1 + DEFAULT_VOTING_PERIOD);
+        assert_eq!(contract.get_governance_proposals(tied.0, 1)[0].status, ProposalStatus::Rejected);
+        assert_eq!(contract.get_governance_proposals(passed.0, 1)[0].status, ProposalStatus::Passed);
+        contract.execute_governance_proposal(passed);
+        assert_eq!(contract.get_governance_proposals(passed.0, 1)[0].status, ProposalStatus::Executed);
+    }
+}
@@ -0,0 +1,226 @@
+//
+// LOAN INTEREST - global borrow index
+// each account's debt is tracked as normalized debt (`shares_usdnear_owed`), its USDNEAR debt is
+// normalized_debt * borrow_index / BORROW_INDEX_ONE
+// - the borrow index grows every second at the APR of the interest rate model (see rate_model.rs), so every loan accrues interest
+// - when USDNEAR is burned from circulation (conversions), or the accrued interest is paid with collateral
+//   staking rewards (every epoch, see compute_rewards_and_interest), the index is reduced so all borrowers owe proportionally less
+// the accrued interest not yet paid is tracked in `accrued_interest_usdnear`: it grows when the index accrues and
+// is reduced when it's paid, in USDNEAR (repaying or liquidating a loan pays its share of the interest first) or
+// with collateral staking rewards, or written off with the loan (see bad_debt.rs)
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+/// borrow index precision, the index starts at 1
+pub const BORROW_INDEX_ONE: u128 = NEAR;
+pub const NANOS_PER_YEAR: u128 = 365 * 24 * ONE_HOUR as u128;
+
+impl UsdNearStableCoin {
+
+    /// borrow index at the current block, including the interest accrued since the last update
+    pub(crate) fn current_borrow_index(&self) -> u128 {
+        let elapsed = env::block_timestamp().saturating_sub(self.borrow_index_updated_at) as u128;
+        if elapsed == 0 || self.total_usdnear_shares == 0 {
+            return self.borrow_index;
+        }
+        let growth = (U256::from(self.borrow_index) * U256::from(self.current_apr_basis_points()) * U256::from(elapsed)
+            / U256::from(10000 * NANOS_PER_YEAR)).as_u128();
+        return self.borrow_index + growth;
+    }
+
+    /// stores the current borrow index and adds the interest accrued since the last update to accrued_interest_usdnear.
+    /// Call it before changing the APR or scaling the index
+    pub(crate) fn accrue_borrow_interest(&mut self) {
+        if self.total_usdnear_shares == 0 {
+            self.borrow_index = BORROW_INDEX_ONE;
+            self.accrued_interest_usdnear = 0;
+        }
+        else {
+            self.accrued_interest_usdnear += self.pending_interest_usdnear();
+            self.borrow_index = self.current_borrow_index();
+        }
+        self.borrow_index_updated_at = env::block_timestamp();
+    }
+
+    /// interest accrued since the last accrue_borrow_interest
+    fn pending_interest_usdnear(&self) -> u128 {
+        let debt_at_stored_index = proportional(self.total_usdnear_shares, self.borrow_index, BORROW_INDEX_ONE);
+        return self.total_debt_usdnear().saturating_sub(debt_at_stored_index);
+    }
+
+    /// total USDNEAR owed by all borrowers, including accrued interest
+    pub(crate) fn total_debt_usdnear(&self) -> u128 {
+        return self.amount_from_usdnear_shares(self.total_usdnear_shares);
+    }
+
+    /// accrued interest not paid yet, up to the current block
+    pub(crate) fn unpaid_interest_usdnear(&self) -> u128 {
+        return std::cmp::min(self.accrued_interest_usdnear + self.pending_interest_usdnear(), self.total_debt_usdnear());
+    }
+
+    /// share of the unpaid interest owed by a loan of `normalized_debt`, proportional to its debt
+    pub(crate) fn accrued_interest_of(&self, normalized_debt: u128) -> u128 {
+        if self.total_usdnear_shares == 0 {
+            return 0;
+        }
+        return proportional(self.unpaid_interest_usdnear(), normalized_debt, self.total_usdnear_shares);
+    }
+
+    /// the first USDNEAR repaid of a loan of `normalized_debt` pay its share of the unpaid interest.
+    /// Call it after accrue_borrow_interest. Returns the interest paid. Must not panic
+    pub(crate) fn settle_accrued_interest(&mut self, normalized_debt: u128, usdnear_repaid: u128) -> u128 {
+        let interest = std::cmp::min(usdnear_repaid, self.accrued_interest_of(normalized_debt));
+        self.accrued_interest_usdnear = self.accrued_interest_usdnear.saturating_sub(interest);
+        return interest;
+    }
+
+    /// reduces every borrower's debt proportionally by `amount` in total. Must not panic
+    pub(crate) fn reduce_total_debt(&mut self, amount: u128) {
+        self.accrue_borrow_interest();
+        let total_debt = self.total_debt_usdnear();
+        if total_debt == 0 || amount == 0 {
+            return;
+        }
+        let remaining_debt = total_debt.saturating_sub(amount);
+        self.borrow_index = std::cmp::max(1, proportional(self.borrow_index, remaining_debt, total_debt));
+    }
+
+    /// increases every borrower's debt proportionally by `amount` in total (bad debt socialization). Must not panic
+    pub(crate) fn increase_total_debt(&mut self, amount: u128) {
+        self.accrue_borrow_interest();
+        let total_debt = self.total_debt_usdnear();
+        if total_debt == 0 || amount == 0 {
+            return;
+        }
+        self.borrow_index = proportional(self.borrow_index, total_debt + amount, total_debt);
+    }
+
+    /// burns USDNEAR from circulation, so all users with outstanding loans owe proportionally less
+    pub(crate) fn burn_usdnear_from_circulation(&mut self, amount: u128) {
+        self.reduce_total_debt(amount);
+        self.total_usdnear = self.total_usdnear.saturating_sub(amount);
+    }
+
+    /// pays the accrued interest with the collateral staking rewards, up to `max_stnear`.
+    /// Returns the stNEAR used. Must not panic
+    pub(crate) fn pay_interest_with_stnear(&mut self, max_stnear: u128) -> u128 {
+        self.accrue_borrow_interest();
+        let unpaid_interest_stnear = self.usdnear_to_stnear(self.unpaid_interest_usdnear());
+        let interest_stnear = std::cmp::min(unpaid_interest_stnear, max_stnear);
+        if interest_stnear > 0 {
+            let paid_usdnear = proportional(self.unpaid_interest_usdnear(), interest_stnear, unpaid_interest_stnear);
+            self.reduce_total_debt(paid_usdnear);
+            self.accrued_interest_usdnear = self.accrued_interest_usdnear.saturating_sub(paid_usdnear);
+            self.total_interest_paid_usdnear += paid_usdnear;
+        }
+        return interest_stnear;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Returns the outstanding loan of `account_id` and its accrued interest not yet paid
+    pub fn get_account_interest(&self, account_id: AccountId) -> AccountInterestJSON {
+        let acc = self.internal_get_account(&account_id);
+        return AccountInterestJSON {
+            normalized_debt: acc.shares_usdnear_owed.into(),
+            borrow_index: self.current_borrow_index().into(),
+            outstanding_loans_usdnear: acc.outstanding_loans_usdnear(self).into(),
+            accrued_interest_usdnear: self.accrued_interest_of(acc.shares_usdnear_owed).into(),
+            account_id,
+        };
+    }
+
+    /// Returns the borrow index, the APR, total debt and interest
+    pub fn get_borrow_index_info(&self) -> BorrowIndexInfoJSON {
+        return BorrowIndexInfoJSON {
+            borrow_index: self.current_borrow_index().into(),
+            current_apr_basis_points: self.current_apr_basis_points(),
+            total_normalized_debt: self.total_usdnear_shares.into(),
+            total_debt_usdnear: self.total_debt_usdnear().into(),
+            total_usdnear: self.total_usdnear.into(),
+            unpaid_interest_usdnear: self.unpaid_interest_usdnear().into(),
+            total_interest_paid_usdnear: self.total_interest_paid_usdnear.into(),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    const ONE_YEAR: Timestamp = NANOS_PER_YEAR as Timestamp;
+
+    #[test]
+    fn repaid_interest_is_not_charged_again_at_the_epoch() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 100 * NEAR, t0);
+        open_loan(&mut contract, BOB, 100 * NEAR, 100 * NEAR, t0);
+
+        // one year at 2.5% APR: each owes 102.5
+        let t1 = t0 + ONE_YEAR;
+        set_context(BOB, 1, t1);
+        assert_close(contract.get_account_interest(BOB.into()).outstanding_loans_usdnear.0, 1025 * NEAR / 10, 1000);
+        assert_close(contract.get_borrow_index_info().unpaid_interest_usdnear.0, 5 * NEAR, 1000);
+
+        // ALICE repays the loan and its interest in USDNEAR
+        contract.ft_transfer(ALICE.into(), (3 * NEAR).into(), None);
+        set_context(ALICE, 1, t1);
+        contract.repay_loan((200 * NEAR).into());
+        assert_eq!(contract.get_account_interest(ALICE.into()).outstanding_loans_usdnear.0, 0);
+        assert_close(contract.get_borrow_index_info().unpaid_interest_usdnear.0, 25 * NEAR / 10, 1000);
+
+        // the epoch only charges BOB's interest, BOB's debt goes back to the principal
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear + 10 * NEAR;
+        run_epoch(&mut contract, total_balance, t1);
+        set_context(BOB, 0, t1);
+        assert_close(contract.get_account_interest(BOB.into()).outstanding_loans_usdnear.0, 100 * NEAR, 1000);
+        assert_close(contract.get_borrow_index_info().unpaid_interest_usdnear.0, 0, 1000);
+        assert_close(contract.total_interest_paid_usdnear, 25 * NEAR / 10, 1000);
+    }
+
+    #[test]
+    fn index_compounds_across_epochs_without_rewards() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 100 * NEAR, t0);
+        open_loan(&mut contract, BOB, 100 * NEAR, 100 * NEAR, t0);
+
+        // two epochs half a year apart with no staking rewards: the interest is not paid,
+        // each epoch stores the index so the second half accrues on top of the first (1.0125^2)
+        let t1 = t0 + ONE_YEAR / 2;
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear;
+        run_epoch(&mut contract, total_balance, t1);
+        set_context(ALICE, 0, t1);
+        assert_close(contract.get_borrow_index_info().borrow_index.0, 10125 * BORROW_INDEX_ONE / 10000, 1000);
+        assert_close(contract.get_borrow_index_info().unpaid_interest_usdnear.0, 25 * NEAR / 10, 1000);
+
+        let t2 = t1 + ONE_YEAR / 2;
+        set_price(&mut contract, INITIAL_PRICE, t2);
+        run_epoch(&mut contract, total_balance, t2);
+        set_context(ALICE, 0, t2);
+        let info = contract.get_borrow_index_info();
+        assert_close(info.borrow_index.0, 102515625 * BORROW_INDEX_ONE / 100000000, 1000);
+        assert_close(info.unpaid_interest_usdnear.0, 503125 * NEAR / 100000, 1000);
+        assert_close(info.total_debt_usdnear.0, 200 * NEAR + info.unpaid_interest_usdnear.0, 1000);
+        assert_close(contract.get_account_interest(BOB.into()).outstanding_loans_usdnear.0, 102515625 * NEAR / 1000000, 1000);
+        assert_eq!(contract.total_interest_paid_usdnear, 0);
+
+        // the next epoch with rewards pays the accrued interest, the index goes back to the principal
+        set_price(&mut contract, INITIAL_PRICE, t2);
+        run_epoch(&mut contract, total_balance + 10 * NEAR, t2);
+        set_context(ALICE, 0, t2);
+        let info = contract.get_borrow_index_info();
+        assert_close(info.unpaid_interest_usdnear.0, 0, 1000);
+        assert_close(info.total_debt_usdnear.0, 200 * NEAR, 1000);
+        assert_close(contract.total_interest_paid_usdnear, 503125 * NEAR / 100000, 1000);
+    }
+}
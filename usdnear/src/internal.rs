@@ -17,20 +17,25 @@ impl UsdNearStableCoin {
         assert!(&env::predecessor_account_id()==&self.owner_account_id,"Can only be called by the owner");
     }
 
+    /// Asserts the account has registered and paid for its storage (NEP-145)
+    pub(crate) fn assert_registered(&self, account_id: &AccountId) {
+        assert!(self.storage_deposits.contains_key(account_id),"The account {} is not registered",account_id);
+    }
+
     /// Asserts the contract is not busy between async calls
     pub(crate) fn assert_not_busy(&self) {
         assert!(!self.busy,"busy");
     }
 
 
-    //applies current_price to a stNEAR amount to get a USD valuation
+    //applies current_price (or the use-case price, see twap.rs) to a stNEAR amount to get a USD valuation
     pub(crate) fn stnear_to_usd(&self, stnear:u128) -> u128 {
-        return (U256::from(stnear) * U256::from(self.current_stnear_price) / U256::from(NEAR)).as_u128();
+        return (U256::from(stnear) * U256::from(self.stnear_valuation_price()) / U256::from(NEAR)).as_u128();
     }
 
-    //applies current_price to convert from USDNEAR to stNEAR 
+    //applies current_price (or the use-case price, see twap.rs) to convert from USDNEAR to stNEAR 
     pub(crate) fn usdnear_to_stnear(&self, usdnear:u128) -> u128 {
-        return (U256::from(usdnear) * U256::from(NEAR) / U256::from(self.current_stnear_price)).as_u128();
+        return (U256::from(usdnear) * U256::from(NEAR) / U256::from(self.stnear_valuation_price())).as_u128();
     }
     
 
@@ -109,14 +114,13 @@ impl UsdNearStableCoin {
     }
     */
 
-    /// Returns the amount corresponding to the given number of usdnear-owed shares.
+    /// Returns the amount corresponding to the given number of usdnear-owed shares (normalized debt), at the current borrow index
     pub(crate) fn amount_from_usdnear_shares(&self, num_shares: u128) -> u128 {
-        return amount_from_shares(num_shares, self.total_usdnear, self.total_usdnear_shares);
+        return proportional(num_shares, self.current_borrow_index(), BORROW_INDEX_ONE);
     }
-    /// Returns the number of shares corresponding to the given USDNEAR amount
-    /// if the amount & the shares are incorporated, price remains the same
+    /// Returns the number of shares (normalized debt) corresponding to the given USDNEAR amount, at the current borrow index
     pub(crate) fn usdnear_shares_from_amount(&self, usdnear_amount: u128) -> u128 {
-        return shares_from_amount(usdnear_amount, self.total_usdnear, self.total_usdnear_shares);
+        return proportional(usdnear_amount, BORROW_INDEX_ONE, self.current_borrow_index());
     }
 
     /// Returns the amount corresponding to the given number of free shares.
@@ -155,6 +159,35 @@ impl UsdNearStableCoin {
         }
     }
 
+    /// Measures the storage used by one registered account with the longest possible account id
+    /// (storage deposit + usdnear balance + stbl balance + borrowing, mining & staking accounts + stake checkpoints + governance votes
+    /// + stability deposit + liquidation auction) to compute NEP-145 storage_balance_bounds
+    pub(crate) fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = "a".repeat(64);
+        self.storage_deposits.insert(&tmp_account_id, &0);
+        self.usdnear_balances.insert(&tmp_account_id, &0);
+        self.stbl_balances.insert(&tmp_account_id, &0);
+        self.b_accounts.insert(&tmp_account_id, &BorrowingAccount::default());
+        self.mining_accounts.insert(&tmp_account_id, &MiningAccount::default());
+        self.staking_accounts.insert(&tmp_account_id, &StakingAccount::default());
+        self.stake_checkpoints.insert(&tmp_account_id, &vec![StakeCheckpoint { timestamp: 0, staked: 0 }; MAX_STAKE_CHECKPOINTS]);
+        self.governance_votes.insert(&tmp_account_id, &vec![GovernanceVote { proposal_id: 0, support: false }; MAX_ACTIVE_VOTES]);
+        self.stability_deposits.insert(&tmp_account_id, &StabilityDeposit::default());
+        self.auctions.insert(&tmp_account_id, &Auction { started_at: 0, start_price: 0, floor_price: 0, duration: 0, usdnear_to_raise: 0, stnear_for_sale: 0 });
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.auctions.remove(&tmp_account_id);
+        self.stability_deposits.remove(&tmp_account_id);
+        self.governance_votes.remove(&tmp_account_id);
+        self.stake_checkpoints.remove(&tmp_account_id);
+        self.staking_accounts.remove(&tmp_account_id);
+        self.mining_accounts.remove(&tmp_account_id);
+        self.b_accounts.remove(&tmp_account_id);
+        self.stbl_balances.remove(&tmp_account_id);
+        self.usdnear_balances.remove(&tmp_account_id);
+        self.storage_deposits.remove(&tmp_account_id);
+    }
+
     /// Inner method to get usdnear balance for an account or 0
     pub(crate) fn get_usdnear_balance(&self, account_id: &String) -> u128 {
         return self.usdnear_balances.get(&account_id).unwrap_or_default();
@@ -11,15 +11,33 @@ const CONTRACT_VERSION: &str = "0.1.0";
 const DEFAULT_WEB_APP_URL: &str = "https://www.narwallets.com/dapp/testnet/usdnear/";
 const DEFAULT_AUDITOR_ACCOUNT_ID: &str = "auditors.near";
 
-use near_sdk::{env, ext_contract, near_bindgen, AccountId};
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, StorageUsage};
 use near_sdk::json_types::Base58PublicKey;
-use near_sdk::collections::{UnorderedMap};
+use near_sdk::collections::{UnorderedMap, Vector};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 
 pub use crate::internal::*;
 pub use crate::owner::*;
 pub use crate::types::*;
 pub use crate::utils::*;
+pub use crate::oracle::*;
+pub use crate::twap::*;
+pub use crate::circuit_breaker::*;
+pub use crate::pause::*;
+pub use crate::roles::*;
+pub use crate::timelock::*;
+pub use crate::stbl::*;
+pub use crate::mining::*;
+pub use crate::staking::*;
+pub use crate::governance::*;
+pub use crate::fees::*;
+pub use crate::interest::*;
+pub use crate::rate_model::*;
+pub use crate::liquidation::*;
+pub use crate::stability_pool::*;
+pub use crate::auction::*;
+pub use crate::reserve::*;
+pub use crate::bad_debt::*;
 
 pub mod gas;
 pub mod types;
@@ -27,7 +45,31 @@ pub mod utils;
 pub mod internal;
 pub mod owner;
 pub mod funtoken;
+pub mod storage;
+pub mod events;
+pub mod security;
+pub mod oracle;
+pub mod twap;
+pub mod circuit_breaker;
+pub mod pause;
+pub mod roles;
+pub mod timelock;
+pub mod stbl;
+pub mod mining;
+pub mod staking;
+pub mod governance;
+pub mod fees;
+pub mod interest;
+pub mod rate_model;
+pub mod liquidation;
+pub mod stability_pool;
+pub mod auction;
+pub mod reserve;
+pub mod bad_debt;
+pub mod migrate;
 pub mod persistent_map;
+#[cfg(test)]
+mod test_utils;
 
 pub use persistent_map::*;
 
@@ -61,13 +103,15 @@ pub trait SelfCallbacks {
         stnear_to_receive:u128
     );
 
-    fn after_ft_on_transfer_usdnear(&mut self, sender_id:AccountId, receiver_id: AccountId, amount: U128String, #[callback] unused_tokens: U128String);
+    fn ft_resolve_transfer(&mut self, sender_id:AccountId, receiver_id: AccountId, amount: U128String) -> U128String;
 
     fn after_get_meta_contract_stnear_total_balance(
         &mut self,
         #[callback] total_balance: U128String,
     );
 
+    fn after_get_price_data(&mut self, oracle_contract_id: AccountId, #[callback] price_data: PriceData);
+
 }
 
 // -----------------
@@ -86,7 +130,7 @@ pub struct BorrowingAccount {
     /// usdnear owed -> shares of total usdnear in circulation 
     shares_usdnear_owed: u128, 
     //-- STBL
-    // governance token - TODO
+    // legacy field, STBL balances are in `stbl_balances` (see stbl.rs, migrate_stbl_balances)
     pub stbl: u128,
 
 }
@@ -170,6 +214,7 @@ impl BorrowingAccount {
         main:&mut UsdNearStableCoin
     ) {
         if amount > 0 {
+            main.accrue_borrow_interest();
             let num_shares = main.usdnear_shares_from_amount(amount);
             //add shares to the the user acc
             self.shares_usdnear_owed += num_shares;
@@ -184,6 +229,9 @@ impl BorrowingAccount {
         main:&mut UsdNearStableCoin
     ) {
         if amount > 0 {
+            main.accrue_borrow_interest();
+            //the repaid USDNEAR pays the loan's share of the accrued interest first
+            main.settle_accrued_interest(self.shares_usdnear_owed, amount);
             let num_shares = main.usdnear_shares_from_amount(amount);
             //burn shares in the user acc
             self.shares_usdnear_owed = self.shares_usdnear_owed.saturating_sub(num_shares);
@@ -283,9 +331,49 @@ impl BorrowingAccount {
 pub struct UsdNearStableCoin {
     /// Owner's account ID (it will be a DAO on phase II)
     pub owner_account_id: String,
+    /// proposed new owner, must call accept_ownership to become the owner
+    pub pending_owner_account_id: Option<String>,
+
+    /// accounts holding each role (see roles.rs). The owner has every role
+    pub role_members: UnorderedMap<Role, Vec<AccountId>>,
 
-    /// updated by external oracle
+    /// updated by external oracle, median of fresh reports (see oracle.rs)
     pub current_stnear_price: u128,
+    /// when current_stnear_price was last updated (nanoseconds)
+    pub last_price_timestamp: Timestamp,
+    /// whitelisted price reporters and their last report
+    pub price_reports: UnorderedMap<String, PriceReport>,
+    /// reports older than this are ignored, and if current_stnear_price is older borrowing & withdrawals are blocked
+    pub price_max_age: Duration,
+    /// min number of fresh reports to update current_stnear_price
+    pub min_fresh_reports: u16,
+    /// price-oracle contract to pull prices from (see pull_oracle_price)
+    pub oracle_contract_id: Option<AccountId>,
+    /// stNEAR asset id in the price-oracle contract
+    pub oracle_asset_id: String,
+    /// expected decimals of the oracle price
+    pub oracle_price_decimals: u8,
+    /// ring buffer of price updates, to compute the TWAP (see twap.rs)
+    pub price_observations: Vector<PriceObservation>,
+    /// next index to overwrite when the ring buffer is full
+    pub price_observations_next: u64,
+    pub twap_window: Duration,
+    /// spot, twap or conservative price for take_loan, liquidate and convert_usdnear
+    pub price_modes: PriceModes,
+    /// circuit breaker: max price change per update & per hour (basis points), see circuit_breaker.rs
+    pub max_price_change_bp_per_update: u32,
+    pub max_price_change_bp_per_hour: u32,
+    /// price update held by the circuit breaker until the guardian confirms or rejects it
+    pub pending_price: Option<PendingPrice>,
+    /// price to value stNEAR during this call, set by use_price_for. Not persisted
+    #[borsh_skip]
+    pub valuation_price: Option<u128>,
+
+    /// queued contract params changes (see timelock.rs)
+    pub params_proposals: UnorderedMap<u64, ParamsProposal>,
+    pub next_params_proposal_id: u64,
+    /// delay between queueing and executing a params proposal
+    pub params_timelock: Duration,
 
     /// collateral % (default 200%)
     pub collateral_basis_points: u32, 
@@ -299,7 +387,24 @@ pub struct UsdNearStableCoin {
     /// how many "usdnear shares" exist. Everytime someone mints usdnear, shares are created
     /// When usdnear is converted to stNEAR, total_usdnear is decremented but shares remain the same
     /// to every user with outstanding loans, owes a little less
+    /// shares are normalized debt: USDNEAR owed = shares * borrow_index (see interest.rs)
     pub total_usdnear_shares: u128,
+    /// cumulative borrow index (x BORROW_INDEX_ONE), grows with the APR and is reduced when debt is burned or interest paid
+    pub borrow_index: u128,
+    pub borrow_index_updated_at: Timestamp,
+    /// accrued interest not yet paid, as of borrow_index_updated_at (see interest.rs)
+    pub accrued_interest_usdnear: u128,
+    /// interest paid with collateral staking rewards since deployment
+    pub total_interest_paid_usdnear: u128,
+    /// interest rate model selected by governance (see rate_model.rs)
+    pub rate_model: RateModel,
+    /// APR set by the peg controller, updated every epoch
+    pub peg_controller_apr_bp: u32,
+    /// USDNEAR market price reported by the operator, peg controller signal
+    pub usdnear_market_price: u128,
+    pub usdnear_market_price_timestamp: Timestamp,
+    /// USDNEAR converted since the last epoch, peg controller signal
+    pub epoch_converted_usdnear: u128,
 
     /// This amount increments with users depositing stNEAR and decrements with users withdrawing stNEAR
     /// This amouns also is incremented when the staking rewards are collected every epoch
@@ -319,9 +424,54 @@ pub struct UsdNearStableCoin {
 
     /// STBL is the governance token. Total stbl minted
     pub total_stbl: u128,
-
-    /// if the price oracle is delayed or the market is too unstable, borrowing can be paused
-    pub borrowing_paused: bool,
+    /// STBL balances (see stbl.rs)
+    pub stbl_balances: PersistentMap<String, u128>,
+    /// STBL token metadata
+    pub stbl_metadata: FungibleTokenMetadata,
+    /// STBL emission rate & max supply, set by governance
+    pub stbl_mint_schedule: StblMintSchedule,
+    /// STBL liquidity mining pools (see mining.rs)
+    pub stbl_mining: StblMining,
+    pub mining_accounts: UnorderedMap<String, MiningAccount>,
+    /// STBL staking, stakers share the epoch interest (see staking.rs)
+    pub stbl_staking: StblStaking,
+    pub staking_accounts: UnorderedMap<String, StakingAccount>,
+    /// STBL-weighted governance (see governance.rs)
+    pub governance_config: GovernanceConfig,
+    pub governance_proposals: Vector<GovernanceProposal>,
+    /// per-account votes of active proposals
+    pub governance_votes: PersistentMap<String, Vec<GovernanceVote>>,
+    /// staked STBL history per account, to compute voting power at proposal creation
+    pub stake_checkpoints: PersistentMap<String, Vec<StakeCheckpoint>>,
+    /// total staked STBL before the last block it changed in, to compute the total voting power at proposal creation
+    pub total_stake_checkpoint: StakeCheckpoint,
+    /// USDNEAR stability pool absorbing liquidations (see stability_pool.rs)
+    pub stability_pool: StabilityPool,
+    pub stability_deposits: PersistentMap<String, StabilityDeposit>,
+    /// S accumulator per (epoch, scale)
+    pub stability_pool_sums: PersistentMap<(u64, u64), u128>,
+    /// fixed-discount or auction liquidations (see auction.rs)
+    pub liquidation_mode: LiquidationMode,
+    pub auction_config: AuctionConfig,
+    pub auctions: UnorderedMap<String, Auction>,
+    /// bad debt waterfall (see bad_debt.rs)
+    pub bad_debt_config: BadDebtConfig,
+    /// shortfall (debt - collateral value) of the positions written off, since deployment
+    pub total_bad_debt: u128,
+    /// USDNEAR in circulation not backed by debt, waiting to be covered by the reserve or socialized
+    pub uncovered_bad_debt: u128,
+    pub total_bad_debt_covered_by_reserve: u128,
+    pub total_bad_debt_socialized: u128,
+    /// protocol reserve / surplus buffer (see reserve.rs)
+    pub reserve_config: ReserveConfig,
+    pub reserve_records: Vector<ReserveRecord>,
+    pub reserve_records_next: u64,
+    pub total_reserve_inflows_stnear: u128,
+    /// stNEAR moved to the treasury above the reserve target
+    pub total_reserve_overflow_stnear: u128,
+
+    /// if the price oracle is delayed or the market is too unstable, borrowing and other operations can be paused (see pause.rs)
+    pub paused: PauseFlags,
 
     //user's usdnear balances. Separated so a user receiving/having only USDNEAR uses a low amount of storage
     pub usdnear_balances: PersistentMap<String, u128>,
@@ -329,7 +479,18 @@ pub struct UsdNearStableCoin {
     //user's borrowing accounts. hold collateral shares and outstanding loans
     pub b_accounts: UnorderedMap<String, BorrowingAccount>,
 
-    ///annual percentage rate for outstandig loans
+    /// value-moving methods exempted by the owner from the 1 yoctoNEAR deposit requirement (see security.rs)
+    pub one_yocto_exempt_methods: Vec<String>,
+
+    /// NEP-148 USDNEAR token metadata
+    pub usdnear_metadata: FungibleTokenMetadata,
+
+    /// NEP-145 registered accounts and their storage deposit
+    pub storage_deposits: PersistentMap<String, u128>,
+    /// storage bytes used by a registered account (storage deposit + usdnear balance + borrowing account)
+    pub account_storage_usage: StorageUsage,
+
+    ///annual percentage rate for outstandig loans (fixed rate model, see rate_model.rs)
     pub usdnear_apr_basis_points: u32, //250 => 2.5%
     pub epochs_per_year: u32, //365*2 epochs per year in NEAR
 
@@ -359,6 +520,13 @@ pub struct UsdNearStableCoin {
 
     pub last_rewards_epoch_height: EpochHeight,
 
+    /// interest distribution of the last epochs (see fees.rs)
+    pub epoch_records: Vector<EpochRecord>,
+    pub epoch_records_next: u64,
+    /// fees credited as free stNEAR since deployment
+    pub total_operator_fees_stnear: u128,
+    pub total_treasury_fees_stnear: u128,
+
 }
 
 impl Default for UsdNearStableCoin {
@@ -380,11 +548,31 @@ impl UsdNearStableCoin {
     ) -> Self {
         assert!(!env::state_exists(), "The contract is already initialized");
 
-        return Self {
+        let mut this = Self {
+            role_members: UnorderedMap::new("L".into()),
+            pending_owner_account_id: None,
             owner_account_id,
-            operator_account_id,
+            operator_account_id: operator_account_id.clone(),
             treasury_account_id,
             current_stnear_price: current_stnear_price.0,
+            last_price_timestamp: env::block_timestamp(),
+            price_reports: UnorderedMap::new("R".into()),
+            price_max_age: DEFAULT_PRICE_MAX_AGE,
+            min_fresh_reports: 1,
+            oracle_contract_id: None,
+            oracle_asset_id: String::from(META_POOL_STNEAR_CONTRACT),
+            oracle_price_decimals: 28,
+            price_observations: Vector::new("O".into()),
+            price_observations_next: 0,
+            twap_window: DEFAULT_TWAP_WINDOW,
+            price_modes: PriceModes::default(),
+            max_price_change_bp_per_update: DEFAULT_MAX_PRICE_CHANGE_BP_PER_UPDATE,
+            max_price_change_bp_per_hour: DEFAULT_MAX_PRICE_CHANGE_BP_PER_HOUR,
+            pending_price: None,
+            valuation_price: None,
+            params_proposals: UnorderedMap::new("P".into()),
+            next_params_proposal_id: 0,
+            params_timelock: DEFAULT_PARAMS_TIMELOCK,
             collateral_basis_points: 200*PERCENT_BP,
             min_collateral_basis_points: 150*PERCENT_BP,
             usdnear_apr_basis_points: 250,   //2.5%
@@ -395,19 +583,73 @@ impl UsdNearStableCoin {
             auditor_account_id: Some(String::from(DEFAULT_AUDITOR_ACCOUNT_ID)),
             operator_fee_basis_points: 3000, //30% from 2.5%
             treasury_fee_basis_points: 7000, //70% from 2.5%
-            borrowing_paused: false,  
+            paused: PauseFlags::default(),
             total_usdnear: 0,
             total_usdnear_shares: 0,
+            borrow_index: BORROW_INDEX_ONE,
+            borrow_index_updated_at: env::block_timestamp(),
+            accrued_interest_usdnear: 0,
+            total_interest_paid_usdnear: 0,
+            rate_model: RateModel::Fixed,
+            peg_controller_apr_bp: 250,
+            usdnear_market_price: 0,
+            usdnear_market_price_timestamp: 0,
+            epoch_converted_usdnear: 0,
             total_free_stnear: 0,
             total_free_shares: 0,
             total_collateral_stnear: 0,
             total_collateral_shares: 0,
             total_stbl: 0,
+            stbl_balances: PersistentMap::new("T".into()),
+            stbl_metadata: default_stbl_metadata(),
+            stbl_mint_schedule: StblMintSchedule::new(DEFAULT_STBL_EMISSION_PER_SECOND, DEFAULT_STBL_MAX_SUPPLY),
+            stbl_mining: StblMining::default(),
+            mining_accounts: UnorderedMap::new("M".into()),
+            stbl_staking: StblStaking::default(),
+            staking_accounts: UnorderedMap::new("K".into()),
+            governance_config: GovernanceConfig::default(),
+            governance_proposals: Vector::new("G".into()),
+            governance_votes: PersistentMap::new("W".into()),
+            stake_checkpoints: PersistentMap::new("C".into()),
+            total_stake_checkpoint: StakeCheckpoint { timestamp: 0, staked: 0 },
+            stability_pool: StabilityPool::default(),
+            stability_deposits: PersistentMap::new("D".into()),
+            stability_pool_sums: PersistentMap::new("Y".into()),
+            liquidation_mode: LiquidationMode::FixedDiscount,
+            auction_config: AuctionConfig::default(),
+            auctions: UnorderedMap::new("N".into()),
+            bad_debt_config: BadDebtConfig::default(),
+            total_bad_debt: 0,
+            uncovered_bad_debt: 0,
+            total_bad_debt_covered_by_reserve: 0,
+            total_bad_debt_socialized: 0,
+            reserve_config: ReserveConfig::default(),
+            reserve_records: Vector::new("V".into()),
+            reserve_records_next: 0,
+            total_reserve_inflows_stnear: 0,
+            total_reserve_overflow_stnear: 0,
             usdnear_balances: PersistentMap::new("U".into()),
             b_accounts: UnorderedMap::new("A".into()),
+            one_yocto_exempt_methods: Vec::new(),
+            usdnear_metadata: crate::funtoken::default_usdnear_metadata(),
+            storage_deposits: PersistentMap::new("S".into()),
+            account_storage_usage: 0,
             busy: false,
             last_rewards_epoch_height:0,
+            epoch_records: Vector::new("E".into()),
+            epoch_records_next: 0,
+            total_operator_fees_stnear: 0,
+            total_treasury_fees_stnear: 0,
         };
+        this.measure_account_storage_usage();
+        //operator & treasury are credited with fees, so they must be registered
+        this.register_protocol_account(&this.operator_account_id.clone());
+        this.register_protocol_account(&this.treasury_account_id.clone());
+        //the operator is in charge of the price oracle
+        this.price_reports.insert(&operator_account_id, &PriceReport::default());
+        this.internal_grant_role(Role::Operator, &operator_account_id);
+        this.record_price_observation(this.current_stnear_price);
+        return this;
     }
 
     /// ---Indirect DEPOSIT/ADD free stNEAR--- (stNEAR is a NEP-141 fungible token standard)
@@ -416,16 +658,27 @@ impl UsdNearStableCoin {
     pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128String, _msg: String ) -> u128 { 
         //verify this is a callback from META_POOL_STNEAR_CONTRACT
         assert_eq!(env::predecessor_account_id(), META_POOL_STNEAR_CONTRACT);
+        //if deposits are paused, panic so the stNEAR is refunded
+        self.assert_not_paused("deposits");
+        //the sender must have paid for its storage (NEP-145), if not, panic so the stNEAR is refunded
+        self.assert_registered(&sender_id);
         //register the stNEAR into our internal accounting for the sender
-        self.add_amount_and_free_shares_preserve_share_price(sender_id, amount.0);
+        self.add_amount_and_free_shares_preserve_share_price(sender_id.clone(), amount.0);
+        self.update_mining_account(&sender_id);
+        events::DepositStnear { account_id: &sender_id, amount }.emit();
         //all stNEAR used
         return 0;
     }
 
     /// Withdraws collateral(stNEAR) from this contract to the user's META_POOL_STNEAR_CONTRACT account
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
     pub fn withdraw_stnear(&mut self, amount: U128String) {
         
+        self.assert_one_yocto_for("withdraw_stnear");
+        self.assert_not_paused("withdrawals");
         self.assert_not_busy();
+        self.assert_price_fresh();
 
         let account_id = env::predecessor_account_id();
         let acc = self.internal_get_account(&account_id);
@@ -480,12 +733,21 @@ impl UsdNearStableCoin {
         if is_promise_success() {
             //the stNEAR withdrawal was successful
             self.remove_amount_and_free_shares_preserve_share_price(&account_id,amount.0);
+            self.update_mining_account(&account_id);
+            events::WithdrawStnear { account_id: &account_id, amount }.emit();
         }
     }
 
 
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
     pub fn take_loan(&mut self, usdnear_amount:U128String) {
+        self.assert_one_yocto_for("take_loan");
+        self.assert_not_paused("borrowing");
         assert!(usdnear_amount.0>=5*NEAR,"min loan is 5 USDNEAR");
+        self.assert_registered(&env::predecessor_account_id());
+        self.assert_price_fresh();
+        self.use_price_for(PriceUseCase::TakeLoan);
         //get account
         let mut acc = self.internal_get_account(&env::predecessor_account_id());
         //get current credit limit
@@ -501,10 +763,24 @@ impl UsdNearStableCoin {
         self.internal_update_account(&env::predecessor_account_id(), &acc);
         //add corresponding newly minted USDNEAR to the user usdnear balance
         self.set_usdnear_balance(&env::predecessor_account_id(), usdnear_balance+usdnear_amount.0);
+        //update STBL liquidity mining stakes
+        self.update_mining_account(&env::predecessor_account_id());
+
+        events::FtMint { owner_id: &env::predecessor_account_id(), amount: usdnear_amount, memo: None }.emit();
+        events::TakeLoan {
+            account_id: &env::predecessor_account_id(),
+            usdnear_amount,
+            outstanding_loans_usdnear: acc.outstanding_loans_usdnear(self).into(),
+            locked_stnear: acc.locked_stnear(self).into(),
+        }.emit();
 
     }
 
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
     pub fn repay_loan(&mut self, usdnear_amount:U128String) {
+        self.assert_one_yocto_for("repay_loan");
+        self.assert_not_paused("repaying");
         //get account
         let mut acc = self.internal_get_account(&env::predecessor_account_id());
         // do the user owe usdnear?
@@ -523,15 +799,30 @@ impl UsdNearStableCoin {
         acc.balance_locked_collateral(self);
         //save account
         self.internal_update_account(&env::predecessor_account_id(), &acc);
+        //update STBL liquidity mining stakes
+        self.update_mining_account(&env::predecessor_account_id());
+
+        events::FtBurn { owner_id: &env::predecessor_account_id(), amount: to_repay.into(), memo: Some("repay_loan") }.emit();
+        events::RepayLoan {
+            account_id: &env::predecessor_account_id(),
+            usdnear_amount: to_repay.into(),
+            outstanding_loans_usdnear: acc.outstanding_loans_usdnear(self).into(),
+            locked_stnear: acc.locked_stnear(self).into(),
+        }.emit();
     }
 
     /// if loan_account_id collateral ratio is below self.min_collateral_basis_points
     /// predecesor_account_id can try to liquidate X amount
     /// in order to move collateral ratio back to self.collateral_basis_points
-    /// 
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
     pub fn liquidate(&mut self, loan_account_id:String, max_usdnear_buy:U128String) {
 
+        self.assert_one_yocto_for("liquidate");
+        self.assert_liquidation_mode(LiquidationMode::FixedDiscount);
+        self.assert_not_paused("liquidations");
         self.assert_not_busy();
+        self.use_price_for(PriceUseCase::Liquidate);
 
         assert!(max_usdnear_buy.0 >= TEN_NEAR, "minimun amount to buy is USDNEAR 10");
 
@@ -546,33 +837,12 @@ impl UsdNearStableCoin {
         let mut loan_acc = self.internal_get_account(&loan_account_id);
         // do the loan_acc owe usdnear?
         assert!(loan_acc.shares_usdnear_owed>0,"no USDNEAR owed");
-        // check collateralization
-        let rate = loan_acc.get_current_collateralization_ratio(self);
-        assert!(rate < self.min_collateral_basis_points, "coll.rate.BP is {}. Can't liquidate",rate);
-        // compute usdnear to repay in order to to restore collatellar rate
-        let locked_collateral_stnear = loan_acc.locked_stnear(self);
-        let valued_collateral_usd = self.stnear_to_usd(locked_collateral_stnear);
-        let owed_usdnear = loan_acc.outstanding_loans_usdnear(self);
-        let required_collateral_usd = apply_pct(self.collateral_basis_points, owed_usdnear);
-        let liq_fee_plus_100:u32 = 10000+self.liquidaton_fee_basis_points as u32;
-        //cross-check, shouldn't happen at this point
-        assert!(valued_collateral_usd < required_collateral_usd, "ERR: valued.collat {} >= req.coll {}",valued_collateral_usd,required_collateral_usd);
-        let max_usdnear_repay: u128;
-        if valued_collateral_usd < owed_usdnear { 
-            //catasthrophic. underwater loan. It's the responsibility of the liquidator to check this condition before this call
-            //at this point we accept the liquidation even if at face value is not benefical to the liquidator
-            max_usdnear_repay = owed_usdnear;
-        }
-        else {
-            //some room for a liquidation fee
-            //compute exact usdnear amount 
-            max_usdnear_repay = (U256::from(required_collateral_usd - valued_collateral_usd) * U256::from(10000) / 
-                    U256::from(self.collateral_basis_points - liq_fee_plus_100)).as_u128();
-        }        
-
-        //the amount to repay is limited to the amount the liquidator indicated as max
-        //and also the total owed
-        let usdnear_repay = std::cmp::min(owed_usdnear, std::cmp::min(max_usdnear_repay, max_usdnear_buy.0));
+        // compute collateralization, usdnear to repay and stnear to receive (see liquidation.rs)
+        // underwater loans are accepted even if at face value not benefical. It's the responsibility of the liquidator to check this condition before this call
+        let quote = self.liquidation_quote(&loan_acc, self.stnear_valuation_price(), max_usdnear_buy.0);
+        let rate = quote.collateralization_ratio;
+        assert!(self.is_liquidatable(&quote), "coll.rate.BP is {}. Can't liquidate",rate);
+        let usdnear_repay = quote.usdnear_repay;
 
         // get liquidator's usdnear balance
         let liquidator_usdnear_balance = self.get_usdnear_balance(&liquidator_id);
@@ -585,22 +855,44 @@ impl UsdNearStableCoin {
         // repay loan with liquidator's usdnear (and burn used usdnear, remove from circulation)
         loan_acc.remove_owed_usdnear_preserve_share_price(usdnear_repay, self);
 
-        //stnear_to_receive should be usdnear*(1+fee%) worth of stnear, with a hard limit set at all_collateral_stnear
-        let stnear_to_receive = std::cmp::min(locked_collateral_stnear, self.usdnear_to_stnear(apply_pct(liq_fee_plus_100, usdnear_repay)));
-        // remove stnear from user's collateral, and add it to liquidator's account
-        loan_acc.remove_locked_amount_preserve_share_price(stnear_to_receive,self);
+        //stnear_to_receive is usdnear*(1+fee%) worth of stnear, with a hard limit set at all_collateral_stnear, minus the reserve share of the fee
+        let stnear_to_receive = quote.stnear_to_receive;
+        // remove stnear from user's collateral, and add it to liquidator's account and the reserve
+        loan_acc.remove_locked_amount_preserve_share_price(stnear_to_receive + quote.stnear_to_reserve,self);
         liquidator_acc.add_free_amount_preserve_share_price(stnear_to_receive,self);
 
         // save loan acc
         self.internal_update_account(&loan_account_id, &loan_acc);
         // save liquidator acc
         self.internal_update_account(&liquidator_id, &liquidator_acc);
+        //update STBL liquidity mining stakes
+        self.update_mining_account(&loan_account_id);
+        self.update_mining_account(&liquidator_id);
+        self.add_to_reserve(&loan_account_id, "liquidation_penalty", quote.stnear_to_reserve);
+
+        events::FtBurn { owner_id: &liquidator_id, amount: usdnear_repay.into(), memo: Some("liquidate") }.emit();
+        events::Liquidate {
+            loan_account_id: &loan_account_id,
+            liquidator_id: &liquidator_id,
+            usdnear_repaid: usdnear_repay.into(),
+            stnear_seized: stnear_to_receive.into(),
+            collateralization_ratio: rate,
+        }.emit();
 
     }
 
     //a user that received USDNEAR as payment, chooses to convert it to stNEAR 
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
     pub fn convert_usdnear(&mut self, usdnear_to_convert:U128String){
 
+        self.assert_one_yocto_for("convert_usdnear");
+        self.assert_not_paused("conversions");
+        self.use_price_for(PriceUseCase::ConvertUsdnear);
+
+        //the converted stNEAR goes to the user's borrowing account
+        self.assert_registered(&env::predecessor_account_id());
+
         // get usdnear balance for this user
         let usdnear_balance = self.get_usdnear_balance(&env::predecessor_account_id());
         // can't use what they don't have
@@ -609,7 +901,8 @@ impl UsdNearStableCoin {
         self.set_usdnear_balance(&env::predecessor_account_id(),usdnear_balance - usdnear_to_convert.0);
         // burn usdnear tokens (but owed_usdnear_shares remain the same), so all users with outstanding loans now owe a little less 
         assert!(self.total_usdnear>=usdnear_to_convert.0,"ERR Not enough usdnear in circ."); //can't happen
-        self.total_usdnear -= usdnear_to_convert.0;
+        self.burn_usdnear_from_circulation(usdnear_to_convert.0);
+        self.epoch_converted_usdnear += usdnear_to_convert.0;
 
         //compute stNEAR amount the converter will receive
         let stnear = self.usdnear_to_stnear(usdnear_to_convert.0);
@@ -617,13 +910,28 @@ impl UsdNearStableCoin {
         let mut acc = self.internal_get_account(&env::predecessor_account_id());
         // remove stnear from collateral pool, and add it to user's acc free-stnear
         // collateral shares remain the same, so the stNEAR is paid proportionally by all users with outstanding loans
-        assert!(self.total_collateral_stnear>=stnear,"Not enough collateral available"); //can only happen if NEAR price crashes
+        // STBL liquidity mining emits at the share prices before the conversion
+        self.update_mining_accumulators();
+        if self.total_collateral_stnear < stnear {
+            //can only happen if NEAR price crashes, the reserve covers the shortfall
+            self.reserve_cover_conversion(stnear - self.total_collateral_stnear);
+        }
+        assert!(self.total_collateral_stnear>=stnear,"Not enough collateral available");
         // remove stnear from collateral pool
         self.total_collateral_stnear-=stnear;
         // add it to user's acc free-stnear
         acc.add_free_amount_preserve_share_price(stnear,self);
         //save account
         self.internal_update_account(&env::predecessor_account_id(), &acc);
+        //update STBL liquidity mining stakes
+        self.update_mining_account(&env::predecessor_account_id());
+
+        events::FtBurn { owner_id: &env::predecessor_account_id(), amount: usdnear_to_convert, memo: Some("convert_usdnear") }.emit();
+        events::ConvertUsdnear {
+            account_id: &env::predecessor_account_id(),
+            usdnear_amount: usdnear_to_convert,
+            stnear_amount: stnear.into(),
+        }.emit();
     }
 
 
@@ -0,0 +1,220 @@
+//
+// LIQUIDATION QUOTES
+// the amounts of a liquidation are computed by `liquidation_quote`, used by `liquidate` and by the keeper views
+// (`get_liquidatable_accounts`, `preview_liquidation`), so the views return exactly what `liquidate` would do now.
+// Quotes use the liquidation price (see twap.rs, price_modes.liquidate).
+// In auction mode the views return what a bid on the running auction would get now instead (see auction.rs)
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+/// amounts of a liquidation at a given price
+pub struct LiquidationQuote {
+    /// basis points, max 999%
+    pub collateralization_ratio: u32,
+    pub owed_usdnear: u128,
+    pub locked_collateral_stnear: u128,
+    pub valued_collateral_usd: u128,
+    /// valued collateral < debt, there's no room for the liquidation fee
+    pub underwater: bool,
+    /// USDNEAR to repay to restore collateral_basis_points (all the debt if underwater)
+    pub max_usdnear_repay: u128,
+    /// min(owed, max_usdnear_repay, max_usdnear_buy)
+    pub usdnear_repay: u128,
+    /// usdnear_repay + liquidation fee worth of stNEAR, limited to the locked collateral, minus stnear_to_reserve
+    pub stnear_to_receive: u128,
+    /// reserve share of the liquidation fee (see reserve.rs)
+    pub stnear_to_reserve: u128,
+}
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn is_liquidatable(&self, quote: &LiquidationQuote) -> bool {
+        return quote.owed_usdnear > 0 && quote.collateralization_ratio < self.min_collateral_basis_points;
+    }
+
+    /// computes the liquidation of `loan_acc` at `price`. Must not panic
+    pub(crate) fn liquidation_quote(&self, loan_acc: &BorrowingAccount, price: u128, max_usdnear_buy: u128) -> LiquidationQuote {
+        const MAX_RATIO: u32 = 999*PERCENT_BP;
+        let locked_collateral_stnear = loan_acc.locked_stnear(self);
+        let valued_collateral_usd = (U256::from(locked_collateral_stnear) * U256::from(price) / U256::from(NEAR)).as_u128();
+        let owed_usdnear = loan_acc.outstanding_loans_usdnear(self);
+        let collateralization_ratio = if loan_acc.shares_usdnear_owed == 0 || owed_usdnear == 0 { MAX_RATIO } else {
+            std::cmp::min(proportional(valued_collateral_usd, 10000, owed_usdnear), MAX_RATIO as u128) as u32
+        };
+
+        // compute usdnear to repay in order to to restore collatellar rate
+        let required_collateral_usd = apply_pct(self.collateral_basis_points, owed_usdnear);
+        let liq_fee_plus_100: u32 = 10000+self.liquidaton_fee_basis_points as u32;
+        let underwater = valued_collateral_usd < owed_usdnear;
+        let max_usdnear_repay = if underwater {
+            //catasthrophic. underwater loan. the liquidation is accepted even if at face value is not benefical to the liquidator
+            owed_usdnear
+        }
+        else {
+            //some room for a liquidation fee
+            (U256::from(required_collateral_usd.saturating_sub(valued_collateral_usd)) * U256::from(10000) /
+                U256::from(self.collateral_basis_points - liq_fee_plus_100)).as_u128()
+        };
+
+        //the amount to repay is limited to the amount the liquidator indicated as max
+        //and also the total owed
+        let usdnear_repay = std::cmp::min(owed_usdnear, std::cmp::min(max_usdnear_repay, max_usdnear_buy));
+
+        //stnear_to_receive should be usdnear*(1+fee%) worth of stnear, with a hard limit set at all_collateral_stnear
+        let stnear_seized = std::cmp::min(locked_collateral_stnear,
+            (U256::from(apply_pct(liq_fee_plus_100, usdnear_repay)) * U256::from(NEAR) / U256::from(price)).as_u128());
+        //the reserve takes its share of the liquidation fee
+        let fee_stnear = stnear_seized.saturating_sub(proportional(usdnear_repay, NEAR, price));
+        let stnear_to_reserve = apply_pct(self.reserve_config.liquidation_penalty_share_bp, fee_stnear);
+
+        return LiquidationQuote {
+            collateralization_ratio,
+            owed_usdnear,
+            locked_collateral_stnear,
+            valued_collateral_usd,
+            underwater,
+            max_usdnear_repay,
+            usdnear_repay,
+            stnear_to_receive: stnear_seized - stnear_to_reserve,
+            stnear_to_reserve,
+        };
+    }
+
+    fn liquidation_preview_json(&self, loan_account_id: AccountId, price: u128, quote: LiquidationQuote, max_usdnear_buy: u128) -> LiquidationPreviewJSON {
+        let mut preview = LiquidationPreviewJSON {
+            liquidatable: self.is_liquidatable(&quote),
+            loan_account_id,
+            stnear_price_usd: price.into(),
+            collateralization_ratio: quote.collateralization_ratio,
+            outstanding_loans_usdnear: quote.owed_usdnear.into(),
+            locked_stnear: quote.locked_collateral_stnear.into(),
+            valued_collateral_usd: quote.valued_collateral_usd.into(),
+            underwater: quote.underwater,
+            max_usdnear_repay: quote.max_usdnear_repay.into(),
+            usdnear_repay: quote.usdnear_repay.into(),
+            stnear_to_receive: quote.stnear_to_receive.into(),
+            stnear_to_reserve: quote.stnear_to_reserve.into(),
+            liquidation_mode: self.liquidation_mode,
+            auction: None,
+        };
+        if self.liquidation_mode == LiquidationMode::Auction {
+            // `liquidate` is disabled: quote a bid on the running auction, nothing if it must be (re)started
+            let now = env::block_timestamp();
+            let auction = self.auctions.get(&preview.loan_account_id).filter(|auction| !auction.is_expired(now));
+            let bid = auction.as_ref()
+                .filter(|_| preview.liquidatable)
+                .map(|auction| auction.bid_at(now, quote.owed_usdnear, quote.locked_collateral_stnear, u128::MAX, max_usdnear_buy));
+            preview.max_usdnear_repay = bid.as_ref().map(|bid| bid.usdnear_to_raise).unwrap_or_default().into();
+            preview.usdnear_repay = bid.as_ref().map(|bid| bid.usdnear_cost).unwrap_or_default().into();
+            preview.stnear_to_receive = bid.as_ref().map(|bid| bid.stnear).unwrap_or_default().into();
+            preview.stnear_to_reserve = 0.into();
+            preview.auction = auction.map(|auction| self.auction_json(preview.loan_account_id.clone(), &auction));
+        }
+        return preview;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Returns the liquidatable accounts (collateralization ratio below min_collateral_basis_points)
+    /// among the borrowing accounts [from_index, from_index+limit), with the max USDNEAR to repay and the stNEAR to receive.
+    /// In auction mode, with the running auction and what a bid gets now
+    pub fn get_liquidatable_accounts(&self, from_index: u64, limit: u32) -> Vec<LiquidationPreviewJSON> {
+        assert!(limit<10000);
+        let price = self.price_for(PriceUseCase::Liquidate);
+        let keys = self.b_accounts.keys_as_vector();
+        return (from_index..std::cmp::min(from_index + limit as u64, keys.len()))
+            .filter_map(|index| {
+                let account_id = keys.get(index).unwrap();
+                let acc = self.internal_get_account(&account_id);
+                if acc.shares_usdnear_owed == 0 {
+                    return None;
+                }
+                let quote = self.liquidation_quote(&acc, price, u128::MAX);
+                if !self.is_liquidatable(&quote) {
+                    return None;
+                }
+                Some(self.liquidation_preview_json(account_id, price, quote, u128::MAX))
+            })
+            .collect();
+    }
+
+    /// Dry-run of liquidate(loan_account_id, max_usdnear_buy) at the current liquidation price.
+    /// In auction mode, dry-run of a bid paying at most max_usdnear_buy on the running auction
+    pub fn preview_liquidation(&self, loan_account_id: AccountId, max_usdnear_buy: U128String) -> LiquidationPreviewJSON {
+        let price = self.price_for(PriceUseCase::Liquidate);
+        let acc = self.internal_get_account(&loan_account_id);
+        let quote = self.liquidation_quote(&acc, price, max_usdnear_buy.0);
+        return self.liquidation_preview_json(loan_account_id, price, quote, max_usdnear_buy.0);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// ALICE and BOB (a liquidator, locking more than 100 stNEAR) borrow at 200%, CAROL only deposits.
+    /// the price drops from 10 to 7 in two hours: the loans are at 140%
+    fn contract_with_undercollateralized_loans() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        open_loan(&mut contract, BOB, 300 * NEAR, 600 * NEAR, NANOS_PER_SECOND);
+        register(&mut contract, CAROL, NANOS_PER_SECOND);
+        deposit_stnear(&mut contract, CAROL, 100 * NEAR, NANOS_PER_SECOND);
+        assert!(contract.get_liquidatable_accounts(0, 10).is_empty());
+        set_price(&mut contract, 8 * NEAR, NANOS_PER_SECOND + ONE_HOUR);
+        set_price(&mut contract, 7 * NEAR, NANOS_PER_SECOND + 2 * ONE_HOUR);
+        return contract;
+    }
+
+    #[test]
+    fn liquidatable_accounts_are_listed() {
+        let contract = contract_with_undercollateralized_loans();
+        let accounts = contract.get_liquidatable_accounts(0, 10);
+        let ids: Vec<&str> = accounts.iter().map(|a| a.loan_account_id.as_str()).collect();
+        assert_eq!(ids, vec![ALICE, BOB]);
+        for account in accounts.iter() {
+            assert!(account.liquidatable && !account.underwater);
+            assert_eq!(account.stnear_price_usd.0, 7 * NEAR);
+            assert_close(account.collateralization_ratio as u128, 140 * PERCENT_BP as u128, 1);
+            assert_eq!(account.usdnear_repay, account.max_usdnear_repay);
+        }
+        // pagination
+        assert_eq!(contract.get_liquidatable_accounts(1, 10).len(), 1);
+        assert!(contract.get_liquidatable_accounts(3, 10).is_empty());
+    }
+
+    #[test]
+    fn preview_matches_the_liquidation() {
+        let mut contract = contract_with_undercollateralized_loans();
+        let preview = contract.preview_liquidation(ALICE.into(), (100 * NEAR).into());
+        assert!(preview.liquidatable);
+        assert_eq!(preview.usdnear_repay.0, 100 * NEAR);
+
+        let t = NANOS_PER_SECOND + 2 * ONE_HOUR;
+        let owed = preview.outstanding_loans_usdnear.0;
+        let bob_free_stnear = contract.internal_get_account(&BOB.into()).free_stnear(&contract);
+        set_context(BOB, 1, t);
+        contract.liquidate(ALICE.into(), (100 * NEAR).into());
+
+        let alice = contract.internal_get_account(&ALICE.into());
+        assert_eq!(alice.outstanding_loans_usdnear(&contract), owed - 100 * NEAR);
+        let bob = contract.internal_get_account(&BOB.into());
+        assert_eq!(bob.free_stnear(&contract) - bob_free_stnear, preview.stnear_to_receive.0);
+    }
+
+    #[test]
+    fn healthy_loans_are_not_liquidatable() {
+        let mut contract = new_contract();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        let preview = contract.preview_liquidation(ALICE.into(), (100 * NEAR).into());
+        assert!(!preview.liquidatable);
+        assert_eq!(preview.max_usdnear_repay.0, 0);
+        assert_eq!(preview.usdnear_repay.0, 0);
+    }
+}
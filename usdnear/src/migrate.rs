@@ -0,0 +1,337 @@
+//
+// STATE MIGRATION
+// upgrades a contract deployed with the original state layout (`OldState` below) to the current `UsdNearStableCoin`.
+// The owner deploys the new code and calls `migrate()` (in the same batch transaction, so no method runs on the old state).
+// Every new field is initialized as in `new()`, except:
+// - borrowing_paused becomes the borrowing pause flag
+// - the borrow index is set so every loan still owes the same USDNEAR (shares are normalized debt now, see interest.rs)
+// - the per-account storage is measured again, and the operator & treasury are registered (see storage.rs)
+// Borrowing accounts keep their layout. Their legacy `stbl` field is moved to the STBL balances
+// calling `migrate_stbl_balances(from_index, limit)` after `migrate()`, in pages to stay within the gas limit.
+// Legacy borrowers and USDNEAR holders are registered without a deposit by `migrate_storage_registrations(from_index, limit)`
+// and `migrate_usdnear_holders(account_ids)`, also in pages
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+/// contract state before the upgrade
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldState {
+    pub owner_account_id: String,
+    pub current_stnear_price: u128,
+    pub collateral_basis_points: u32,
+    pub min_collateral_basis_points: u32,
+    pub total_usdnear: u128,
+    pub total_usdnear_shares: u128,
+    pub total_free_stnear: u128,
+    pub total_free_shares: u128,
+    pub total_collateral_stnear: u128,
+    pub total_collateral_shares: u128,
+    pub total_stbl: u128,
+    pub borrowing_paused: bool,
+    pub usdnear_balances: PersistentMap<String, u128>,
+    pub b_accounts: UnorderedMap<String, BorrowingAccount>,
+    pub usdnear_apr_basis_points: u32,
+    pub epochs_per_year: u32,
+    pub liquidaton_fee_basis_points: u16,
+    pub operator_account_id: String,
+    pub operator_fee_basis_points: u16,
+    pub treasury_account_id: String,
+    pub treasury_fee_basis_points: u16,
+    pub min_account_balance: u128,
+    pub web_app_url: Option<String>,
+    pub auditor_account_id: Option<String>,
+    pub busy: bool,
+    pub last_rewards_epoch_height: EpochHeight,
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Owner's method (or the contract itself, deploying the new code).
+    /// Migrates the state from the original layout. Call `migrate_stbl_balances`, `migrate_storage_registrations`
+    /// and `migrate_usdnear_holders` afterwards
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let old: OldState = env::state_read().expect("no contract state to migrate");
+        let predecessor = env::predecessor_account_id();
+        assert!(predecessor == old.owner_account_id || predecessor == env::current_account_id(), "Can only be called by the owner");
+
+        // USDNEAR owed = shares * total_usdnear / total_usdnear_shares, keep it with the borrow index
+        let borrow_index = if old.total_usdnear_shares == 0 {
+            BORROW_INDEX_ONE
+        } else {
+            std::cmp::max(1, proportional(old.total_usdnear, BORROW_INDEX_ONE, old.total_usdnear_shares))
+        };
+
+        let mut this = Self {
+            role_members: UnorderedMap::new("L".into()),
+            pending_owner_account_id: None,
+            owner_account_id: old.owner_account_id,
+            operator_account_id: old.operator_account_id.clone(),
+            treasury_account_id: old.treasury_account_id,
+            current_stnear_price: old.current_stnear_price,
+            last_price_timestamp: env::block_timestamp(),
+            price_reports: UnorderedMap::new("R".into()),
+            price_max_age: DEFAULT_PRICE_MAX_AGE,
+            min_fresh_reports: 1,
+            oracle_contract_id: None,
+            oracle_asset_id: String::from(META_POOL_STNEAR_CONTRACT),
+            oracle_price_decimals: 28,
+            price_observations: Vector::new("O".into()),
+            price_observations_next: 0,
+            twap_window: DEFAULT_TWAP_WINDOW,
+            price_modes: PriceModes::default(),
+            max_price_change_bp_per_update: DEFAULT_MAX_PRICE_CHANGE_BP_PER_UPDATE,
+            max_price_change_bp_per_hour: DEFAULT_MAX_PRICE_CHANGE_BP_PER_HOUR,
+            pending_price: None,
+            valuation_price: None,
+            params_proposals: UnorderedMap::new("P".into()),
+            next_params_proposal_id: 0,
+            params_timelock: DEFAULT_PARAMS_TIMELOCK,
+            collateral_basis_points: old.collateral_basis_points,
+            min_collateral_basis_points: old.min_collateral_basis_points,
+            usdnear_apr_basis_points: old.usdnear_apr_basis_points,
+            epochs_per_year: old.epochs_per_year,
+            liquidaton_fee_basis_points: old.liquidaton_fee_basis_points,
+            min_account_balance: old.min_account_balance,
+            web_app_url: old.web_app_url,
+            auditor_account_id: old.auditor_account_id,
+            operator_fee_basis_points: old.operator_fee_basis_points,
+            treasury_fee_basis_points: old.treasury_fee_basis_points,
+            paused: PauseFlags { borrowing: old.borrowing_paused, ..PauseFlags::default() },
+            total_usdnear: old.total_usdnear,
+            total_usdnear_shares: old.total_usdnear_shares,
+            borrow_index,
+            borrow_index_updated_at: env::block_timestamp(),
+            accrued_interest_usdnear: 0,
+            total_interest_paid_usdnear: 0,
+            rate_model: RateModel::Fixed,
+            peg_controller_apr_bp: old.usdnear_apr_basis_points,
+            usdnear_market_price: 0,
+            usdnear_market_price_timestamp: 0,
+            epoch_converted_usdnear: 0,
+            total_free_stnear: old.total_free_stnear,
+            total_free_shares: old.total_free_shares,
+            total_collateral_stnear: old.total_collateral_stnear,
+            total_collateral_shares: old.total_collateral_shares,
+            // includes the legacy BorrowingAccount.stbl balances
+            total_stbl: old.total_stbl,
+            stbl_balances: PersistentMap::new("T".into()),
+            stbl_metadata: default_stbl_metadata(),
+            stbl_mint_schedule: StblMintSchedule::new(0, std::cmp::max(DEFAULT_STBL_MAX_SUPPLY, old.total_stbl)),
+            stbl_mining: StblMining::default(),
+            mining_accounts: UnorderedMap::new("M".into()),
+            stbl_staking: StblStaking::default(),
+            staking_accounts: UnorderedMap::new("K".into()),
+            governance_config: GovernanceConfig::default(),
+            governance_proposals: Vector::new("G".into()),
+            governance_votes: PersistentMap::new("W".into()),
+            stake_checkpoints: PersistentMap::new("C".into()),
+            total_stake_checkpoint: StakeCheckpoint { timestamp: 0, staked: 0 },
+            stability_pool: StabilityPool::default(),
+            stability_deposits: PersistentMap::new("D".into()),
+            stability_pool_sums: PersistentMap::new("Y".into()),
+            liquidation_mode: LiquidationMode::FixedDiscount,
+            auction_config: AuctionConfig::default(),
+            auctions: UnorderedMap::new("N".into()),
+            bad_debt_config: BadDebtConfig::default(),
+            total_bad_debt: 0,
+            uncovered_bad_debt: 0,
+            total_bad_debt_covered_by_reserve: 0,
+            total_bad_debt_socialized: 0,
+            reserve_config: ReserveConfig::default(),
+            reserve_records: Vector::new("V".into()),
+            reserve_records_next: 0,
+            total_reserve_inflows_stnear: 0,
+            total_reserve_overflow_stnear: 0,
+            usdnear_balances: old.usdnear_balances,
+            b_accounts: old.b_accounts,
+            one_yocto_exempt_methods: Vec::new(),
+            usdnear_metadata: crate::funtoken::default_usdnear_metadata(),
+            storage_deposits: PersistentMap::new("S".into()),
+            account_storage_usage: 0,
+            busy: old.busy,
+            last_rewards_epoch_height: old.last_rewards_epoch_height,
+            epoch_records: Vector::new("E".into()),
+            epoch_records_next: 0,
+            total_operator_fees_stnear: 0,
+            total_treasury_fees_stnear: 0,
+        };
+        this.measure_account_storage_usage();
+        //operator & treasury are credited with fees, so they must be registered
+        this.register_protocol_account(&this.operator_account_id.clone());
+        this.register_protocol_account(&this.treasury_account_id.clone());
+        //the operator is in charge of the price oracle
+        this.price_reports.insert(&old.operator_account_id, &PriceReport::default());
+        this.internal_grant_role(Role::Operator, &old.operator_account_id);
+        this.record_price_observation(this.current_stnear_price);
+        return this;
+    }
+
+    /// Owner's method.
+    /// Registers (NEP-145) the legacy borrowing accounts in [from_index, from_index+limit), after `migrate`.
+    /// Their storage was already paid under the original layout, so no deposit is charged (see storage.rs).
+    /// Returns how many accounts were registered, repeat with the next page until from_index reaches get_number_of_accounts()
+    pub fn migrate_storage_registrations(&mut self, from_index: u64, limit: u32) -> u32 {
+        self.assert_owner_calling();
+        let keys = self.b_accounts.keys_as_vector();
+        let account_ids: Vec<AccountId> = (from_index..std::cmp::min(from_index + limit as u64, keys.len()))
+            .map(|index| keys.get(index).unwrap())
+            .collect();
+        return self.register_legacy_accounts(account_ids);
+    }
+
+    /// Owner's method.
+    /// Registers (NEP-145) legacy USDNEAR holders without a borrowing account, after `migrate`.
+    /// USDNEAR balances can not be iterated, so the holders are listed off-chain (e.g. from the transfer logs).
+    /// Accounts without a USDNEAR balance are skipped. Returns how many accounts were registered
+    pub fn migrate_usdnear_holders(&mut self, account_ids: Vec<AccountId>) -> u32 {
+        self.assert_owner_calling();
+        let holders: Vec<AccountId> = account_ids.into_iter()
+            .filter(|account_id| self.usdnear_balances.contains_key(account_id))
+            .collect();
+        return self.register_legacy_accounts(holders);
+    }
+
+}
+
+impl UsdNearStableCoin {
+    fn register_legacy_accounts(&mut self, account_ids: Vec<AccountId>) -> u32 {
+        let mut registered = 0;
+        for account_id in account_ids {
+            if self.register_contract_paid_account(&account_id) {
+                registered += 1;
+            }
+        }
+        return registered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::migrate::OldState;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    /// writes a state with the original layout: ALICE owes USDNEAR 100 after a conversion, and has legacy STBL.
+    /// ALICE holds USDNEAR 80 and CAROL, without a borrowing account, USDNEAR 20
+    fn old_contract_state() {
+        env::take_blockchain_interface();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        let mut usdnear_balances = PersistentMap::new("U".into());
+        usdnear_balances.insert(&String::from(ALICE), &(80 * NEAR));
+        usdnear_balances.insert(&String::from(CAROL), &(20 * NEAR));
+        let mut b_accounts = UnorderedMap::new("A".into());
+        b_accounts.insert(&String::from(ALICE), &BorrowingAccount {
+            free_shares: 0,
+            locked_collateral_shares: 50 * NEAR,
+            shares_usdnear_owed: 200 * NEAR,
+            stbl: 7 * NEAR,
+        });
+        env::state_write(&OldState {
+            owner_account_id: OWNER.into(),
+            current_stnear_price: INITIAL_PRICE,
+            collateral_basis_points: 200 * PERCENT_BP,
+            min_collateral_basis_points: 150 * PERCENT_BP,
+            total_usdnear: 100 * NEAR,
+            total_usdnear_shares: 200 * NEAR,
+            total_free_stnear: 0,
+            total_free_shares: 0,
+            total_collateral_stnear: 50 * NEAR,
+            total_collateral_shares: 50 * NEAR,
+            total_stbl: 7 * NEAR,
+            borrowing_paused: true,
+            usdnear_balances,
+            b_accounts,
+            usdnear_apr_basis_points: 300,
+            epochs_per_year: 365 * 2,
+            liquidaton_fee_basis_points: 1000,
+            operator_account_id: OPERATOR.into(),
+            operator_fee_basis_points: 3000,
+            treasury_account_id: TREASURY.into(),
+            treasury_fee_basis_points: 7000,
+            min_account_balance: NEAR,
+            web_app_url: None,
+            auditor_account_id: None,
+            busy: false,
+            last_rewards_epoch_height: 3,
+        });
+    }
+
+    #[test]
+    fn migrate_keeps_balances_and_debt() {
+        old_contract_state();
+        let t0 = 10 * NANOS_PER_SECOND;
+        set_context(OWNER, 0, t0);
+        let mut contract = UsdNearStableCoin::migrate();
+
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, 80 * NEAR);
+        assert_eq!(contract.ft_total_supply().0, 100 * NEAR);
+        let alice = contract.internal_get_account(&ALICE.into());
+        assert_eq!(alice.outstanding_loans_usdnear(&contract), 100 * NEAR);
+        assert_eq!(alice.locked_stnear(&contract), 50 * NEAR);
+        assert_eq!(contract.get_contract_params().usdnear_apr_basis_points, 300);
+        assert!(contract.paused.borrowing);
+        assert_eq!(contract.last_rewards_epoch_height, 3);
+        // storage is measured and the protocol accounts registered
+        assert!(contract.account_storage_usage > 0);
+        assert!(contract.storage_balance_of(OPERATOR.into()).is_some());
+        assert!(contract.storage_balance_of(TREASURY.into()).is_some());
+
+        // the legacy STBL moves to the STBL balances
+        assert_eq!(contract.migrate_stbl_balances(0, 10), 1);
+        assert_eq!(contract.migrate_stbl_balances(0, 10), 0);
+        assert_eq!(contract.stbl_balance_of(ALICE.into()).0, 7 * NEAR);
+        assert_eq!(contract.stbl_total_supply().0, 7 * NEAR);
+    }
+
+    /// ALICE sends USDNEAR 10 to `receiver_id` with ft_transfer_call, and the receiver's ft_on_transfer fails
+    fn refunded_transfer_call(contract: &mut UsdNearStableCoin, receiver_id: &str, block_timestamp: Timestamp) {
+        set_context(ALICE, 1, block_timestamp);
+        contract.ft_transfer_call(receiver_id.into(), (10 * NEAR).into(), String::new(), None);
+        set_callback_context(PromiseResult::Failed, block_timestamp);
+        assert_eq!(contract.ft_resolve_transfer(ALICE.into(), receiver_id.into(), (10 * NEAR).into()).0, 0);
+    }
+
+    #[test]
+    fn legacy_accounts_are_registered_and_refunded() {
+        old_contract_state();
+        let t0 = 10 * NANOS_PER_SECOND;
+        set_context(OWNER, 0, t0);
+        let mut contract = UsdNearStableCoin::migrate();
+
+        // not registered yet, the refund is not burned
+        refunded_transfer_call(&mut contract, OPERATOR, t0);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, 80 * NEAR);
+        assert_eq!(contract.ft_total_supply().0, 100 * NEAR);
+
+        set_context(OWNER, 0, t0);
+        assert_eq!(contract.migrate_storage_registrations(0, 10), 1);
+        assert_eq!(contract.migrate_storage_registrations(0, 10), 0);
+        assert_eq!(contract.migrate_usdnear_holders(vec![CAROL.into(), BOB.into()]), 1);
+        assert!(contract.storage_balance_of(ALICE.into()).is_some());
+        assert!(contract.storage_balance_of(CAROL.into()).is_some());
+        assert!(contract.storage_balance_of(BOB.into()).is_none());
+
+        // CAROL can receive now
+        refunded_transfer_call(&mut contract, CAROL, t0);
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, 80 * NEAR);
+        assert_eq!(contract.ft_balance_of(CAROL.into()).0, 20 * NEAR);
+
+        // no NEAR to refund on unregister, the contract paid for the storage
+        set_context(CAROL, 1, t0);
+        contract.storage_unregister(Some(true));
+        assert_eq!(near_transferred_to(CAROL), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn only_the_owner_can_migrate() {
+        old_contract_state();
+        set_context(ALICE, 0, 10 * NANOS_PER_SECOND);
+        UsdNearStableCoin::migrate();
+    }
+}
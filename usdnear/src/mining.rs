@@ -0,0 +1,344 @@
+//
+// STBL LIQUIDITY MINING
+// STBL is emitted per second (within the STBL mint schedule) to two pools:
+// - debt pool: proportional to the account's USDNEAR debt shares
+// - deposit pool: proportional to the account's deposited stNEAR (free + locked)
+// Stakes are share counts (USDNEAR debt shares, free stNEAR shares, locked collateral shares), so they stay exact
+// when other accounts move the share prices (e.g. `convert_usdnear` takes stNEAR from every borrower's collateral).
+// The deposit pool emission is split between free and locked shares by their stNEAR value at each accumulator update.
+// each stake has a global reward-per-share accumulator. An account's stakes are a snapshot updated
+// every time the account takes/repays a loan, deposits/withdraws stNEAR, liquidates or is liquidated.
+// Emitted STBL is minted to this contract's own STBL balance and transferred to the user on `claim_stbl`
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+/// accumulator precision
+pub const MINING_ACC_PRECISION: u128 = NEAR;
+/// a pool does not emit if its total stake is lower than this (keeps the accumulator in range)
+pub const MIN_MINING_POOL_STAKE: u128 = NEAR;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct MiningAccount {
+    /// USDNEAR debt shares at the last update
+    pub debt_stake: u128,
+    /// free stNEAR shares at the last update
+    pub free_stake: u128,
+    /// locked collateral shares at the last update
+    pub locked_stake: u128,
+    pub debt_acc_paid: u128,
+    pub free_acc_paid: u128,
+    pub locked_acc_paid: u128,
+    /// STBL earned and not claimed
+    pub pending_stbl: u128,
+}
+
+impl MiningAccount {
+    fn is_empty(&self) -> bool {
+        return self.debt_stake == 0 && self.free_stake == 0 && self.locked_stake == 0 && self.pending_stbl == 0;
+    }
+
+    /// pending STBL with the given accumulators
+    fn pending_at(&self, accs: &MiningAccs) -> u128 {
+        return self.pending_stbl
+            + proportional(self.debt_stake, accs.debt - self.debt_acc_paid, MINING_ACC_PRECISION)
+            + proportional(self.free_stake, accs.free - self.free_acc_paid, MINING_ACC_PRECISION)
+            + proportional(self.locked_stake, accs.locked - self.locked_acc_paid, MINING_ACC_PRECISION);
+    }
+}
+
+/// reward-per-share accumulators (x MINING_ACC_PRECISION), also used for the rewards of each stake
+#[derive(BorshDeserialize, BorshSerialize, Default, Clone, Copy)]
+pub struct MiningAccs {
+    pub debt: u128,
+    pub free: u128,
+    pub locked: u128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StblMining {
+    /// STBL emitted per second to both pools
+    pub stbl_per_second: u128,
+    /// share of the emission for the debt pool, the rest goes to the deposit pool
+    pub debt_pool_bp: u32,
+    /// USDNEAR debt shares of the mining accounts
+    pub total_debt_stake: u128,
+    /// free stNEAR shares of the mining accounts
+    pub total_free_stake: u128,
+    /// locked collateral shares of the mining accounts
+    pub total_locked_stake: u128,
+    pub accs: MiningAccs,
+    pub last_update: Timestamp,
+}
+
+impl Default for StblMining {
+    fn default() -> Self {
+        Self {
+            stbl_per_second: 0,
+            debt_pool_bp: 50 * PERCENT_BP,
+            total_debt_stake: 0,
+            total_free_stake: 0,
+            total_locked_stake: 0,
+            accs: MiningAccs::default(),
+            last_update: env::block_timestamp(),
+        }
+    }
+}
+
+impl StblMining {
+
+    /// rewards for the debt, free and locked stakes since last_update, limited to `mintable`.
+    /// The deposit pool is split by the stNEAR value of the free and locked stakes. Empty stakes do not emit
+    fn rewards_until(&self, now: Timestamp, mintable: u128, free_stnear: u128, locked_stnear: u128) -> MiningAccs {
+        let elapsed = now.saturating_sub(self.last_update) as u128;
+        let emitted = std::cmp::min(mintable, proportional(self.stbl_per_second, elapsed, NANOS_PER_SECOND as u128));
+        let for_debt = apply_pct(self.debt_pool_bp, emitted);
+        let for_deposits = emitted - for_debt;
+        let free_stnear = if self.total_free_stake >= MIN_MINING_POOL_STAKE { free_stnear } else { 0 };
+        let locked_stnear = if self.total_locked_stake >= MIN_MINING_POOL_STAKE { locked_stnear } else { 0 };
+        let deposit_stnear = free_stnear + locked_stnear;
+        let for_free = if deposit_stnear >= MIN_MINING_POOL_STAKE { proportional(for_deposits, free_stnear, deposit_stnear) } else { 0 };
+        let for_locked = if deposit_stnear >= MIN_MINING_POOL_STAKE { for_deposits - for_free } else { 0 };
+        return MiningAccs {
+            debt: if self.total_debt_stake >= MIN_MINING_POOL_STAKE { for_debt } else { 0 },
+            free: for_free,
+            locked: for_locked,
+        };
+    }
+
+    /// accumulators after adding the rewards
+    fn accs_with(&self, rewards: &MiningAccs) -> MiningAccs {
+        let mut accs = self.accs;
+        if rewards.debt > 0 {
+            accs.debt += proportional(rewards.debt, MINING_ACC_PRECISION, self.total_debt_stake);
+        }
+        if rewards.free > 0 {
+            accs.free += proportional(rewards.free, MINING_ACC_PRECISION, self.total_free_stake);
+        }
+        if rewards.locked > 0 {
+            accs.locked += proportional(rewards.locked, MINING_ACC_PRECISION, self.total_locked_stake);
+        }
+        return accs;
+    }
+}
+
+impl UsdNearStableCoin {
+
+    /// mining rewards since the last accumulator update, with `mintable` STBL
+    fn mining_rewards_until(&self, now: Timestamp, mintable: u128) -> MiningAccs {
+        return self.stbl_mining.rewards_until(now, mintable,
+            self.amount_from_free_shares(self.stbl_mining.total_free_stake),
+            self.amount_from_collateral_shares(self.stbl_mining.total_locked_stake));
+    }
+
+    /// updates the accumulators, minting the emitted STBL to this contract.
+    /// Call it before changing the stNEAR share prices
+    pub(crate) fn update_mining_accumulators(&mut self) {
+        self.accrue_stbl_emission();
+        let now = env::block_timestamp();
+        let rewards = self.mining_rewards_until(now, self.stbl_mint_schedule.mintable);
+        self.stbl_mining.accs = self.stbl_mining.accs_with(&rewards);
+        self.stbl_mining.last_update = now;
+        let emitted = rewards.debt + rewards.free + rewards.locked;
+        if emitted > 0 {
+            self.internal_mint_stbl(&env::current_account_id(), emitted, Some("liquidity_mining"));
+        }
+    }
+
+    /// settles the account's pending STBL and updates its stakes from the borrowing account.
+    /// Call it after every change to the account's debt or stNEAR
+    pub(crate) fn update_mining_account(&mut self, account_id: &AccountId) {
+        self.update_mining_accumulators();
+        let mut mining_acc = self.mining_accounts.get(account_id).unwrap_or_default();
+        mining_acc.pending_stbl = mining_acc.pending_at(&self.stbl_mining.accs);
+        mining_acc.debt_acc_paid = self.stbl_mining.accs.debt;
+        mining_acc.free_acc_paid = self.stbl_mining.accs.free;
+        mining_acc.locked_acc_paid = self.stbl_mining.accs.locked;
+
+        let acc = self.internal_get_account(account_id);
+        let (debt_stake, free_stake, locked_stake) = (acc.shares_usdnear_owed, acc.free_shares, acc.locked_collateral_shares);
+        self.stbl_mining.total_debt_stake = self.stbl_mining.total_debt_stake - mining_acc.debt_stake + debt_stake;
+        self.stbl_mining.total_free_stake = self.stbl_mining.total_free_stake - mining_acc.free_stake + free_stake;
+        self.stbl_mining.total_locked_stake = self.stbl_mining.total_locked_stake - mining_acc.locked_stake + locked_stake;
+        mining_acc.debt_stake = debt_stake;
+        mining_acc.free_stake = free_stake;
+        mining_acc.locked_stake = locked_stake;
+
+        if mining_acc.is_empty() {
+            self.mining_accounts.remove(account_id);
+        } else {
+            self.mining_accounts.insert(account_id, &mining_acc);
+        }
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Transfers the STBL earned by liquidity mining to the caller's STBL balance. Returns the amount claimed
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn claim_stbl(&mut self) -> U128String {
+        self.assert_one_yocto_for("claim_stbl");
+        let account_id = env::predecessor_account_id();
+        //the STBL balance uses storage, paid by the registration (NEP-145)
+        self.assert_registered(&account_id);
+        self.update_mining_account(&account_id);
+        let mut mining_acc = self.mining_accounts.get(&account_id).unwrap_or_default();
+        let amount = mining_acc.pending_stbl;
+        assert!(amount > 0, "no STBL to claim");
+        mining_acc.pending_stbl = 0;
+        if mining_acc.is_empty() {
+            self.mining_accounts.remove(&account_id);
+        } else {
+            self.mining_accounts.insert(&account_id, &mining_acc);
+        }
+        self.stbl_transfer_internal(&env::current_account_id(), &account_id, amount);
+        events::StblTransfer { old_owner_id: &env::current_account_id(), new_owner_id: &account_id, amount: amount.into(), memo: Some("claim_stbl") }.emit();
+        return amount.into();
+    }
+
+    /// Returns the STBL earned by `account_id` and not claimed yet
+    pub fn get_pending_stbl(&self, account_id: AccountId) -> U128String {
+        let mining_acc = match self.mining_accounts.get(&account_id) {
+            Some(mining_acc) => mining_acc,
+            None => return 0.into(),
+        };
+        let now = env::block_timestamp();
+        let mintable = self.stbl_mint_schedule.mintable_at(now, self.total_stbl);
+        let rewards = self.mining_rewards_until(now, mintable);
+        return mining_acc.pending_at(&self.stbl_mining.accs_with(&rewards)).into();
+    }
+
+    /// Owner's method.
+    /// Sets the STBL liquidity-mining emission and the share of it going to borrowers (the rest goes to stNEAR depositors)
+    pub fn set_stbl_mining_config(&mut self, stbl_per_second: U128String, debt_pool_bp: u32) {
+        self.assert_owner_calling();
+        assert!(debt_pool_bp <= 10000, "debt_pool_bp must be <= 10000");
+        // emit at the previous rate until now
+        self.update_mining_accumulators();
+        self.stbl_mining.stbl_per_second = stbl_per_second.0;
+        self.stbl_mining.debt_pool_bp = debt_pool_bp;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "stbl_mining_config",
+            value: format!("stbl_per_second:{} debt_pool_bp:{}", stbl_per_second.0, debt_pool_bp),
+        }.emit();
+    }
+
+    /// Returns liquidity-mining emission, pool stakes and accumulators
+    pub fn get_stbl_mining_info(&self) -> StblMiningInfoJSON {
+        return StblMiningInfoJSON {
+            stbl_per_second: self.stbl_mining.stbl_per_second.into(),
+            debt_pool_bp: self.stbl_mining.debt_pool_bp,
+            total_debt_stake: self.stbl_mining.total_debt_stake.into(),
+            total_free_stake: self.stbl_mining.total_free_stake.into(),
+            total_locked_stake: self.stbl_mining.total_locked_stake.into(),
+            total_deposit_stnear: (self.amount_from_free_shares(self.stbl_mining.total_free_stake)
+                + self.amount_from_collateral_shares(self.stbl_mining.total_locked_stake)).into(),
+            debt_acc: self.stbl_mining.accs.debt.into(),
+            free_acc: self.stbl_mining.accs.free.into(),
+            locked_acc: self.stbl_mining.accs.locked.into(),
+            unclaimed_stbl: self.get_stbl_balance(&env::current_account_id()).into(),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn borrower_and_depositor_earn_the_emission() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(NEAR, 1_000_000 * NEAR);
+        contract.set_stbl_mining_config(NEAR.into(), 50 * PERCENT_BP);
+
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        // ALICE is alone in both pools and earns all the emission
+        let t1 = t0 + 100 * NANOS_PER_SECOND;
+        set_context(ALICE, 1, t1);
+        assert_close(contract.claim_stbl().0, 100 * NEAR, NEAR / 1000);
+        assert_close(contract.get_stbl_balance(&ALICE.into()), 100 * NEAR, NEAR / 1000);
+        assert_eq!(contract.get_pending_stbl(ALICE.into()).0, 0);
+    }
+
+    #[test]
+    fn conversions_by_other_accounts_move_the_deposit_stakes() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(NEAR, 1_000_000 * NEAR);
+        contract.set_stbl_mining_config(NEAR.into(), 0);
+
+        // ALICE and BOB lock 90 stNEAR each, CAROL only deposits
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, t0);
+        open_loan(&mut contract, BOB, 100 * NEAR, 450 * NEAR, t0);
+        register(&mut contract, CAROL, t0);
+        deposit_stnear(&mut contract, CAROL, 100 * NEAR, t0);
+        // ALICE converts 300 USDNEAR: 30 stNEAR leave the collateral pool, 15 of them from BOB's locked stNEAR
+        set_context(ALICE, 1, t0);
+        contract.convert_usdnear((300 * NEAR).into());
+        let bob = contract.get_account_info(BOB.into());
+        assert_close(bob.stnear.0 + bob.locked_stnear.0, 85 * NEAR, NEAR / 1000);
+
+        // the deposit pool follows the stNEAR: BOB 85, CAROL 100, ALICE 115
+        let t1 = t0 + 300 * NANOS_PER_SECOND;
+        set_context(CAROL, 0, t1);
+        assert_close(contract.get_pending_stbl(BOB.into()).0, 85 * NEAR, NEAR / 100);
+        assert_close(contract.get_pending_stbl(CAROL.into()).0, 100 * NEAR, NEAR / 100);
+        assert_close(contract.get_pending_stbl(ALICE.into()).0, 115 * NEAR, NEAR / 100);
+    }
+
+    #[test]
+    fn debt_pool_is_shared_by_debt() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(NEAR, 1_000_000 * NEAR);
+        contract.set_stbl_mining_config(NEAR.into(), 100 * PERCENT_BP);
+
+        // same collateral, BOB owes 3 times ALICE's debt. CAROL only deposits and earns nothing
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 100 * NEAR, t0);
+        open_loan(&mut contract, BOB, 100 * NEAR, 300 * NEAR, t0);
+        register(&mut contract, CAROL, t0);
+        deposit_stnear(&mut contract, CAROL, 100 * NEAR, t0);
+        let t1 = t0 + 400 * NANOS_PER_SECOND;
+        set_context(CAROL, 0, t1);
+        assert_close(contract.get_pending_stbl(ALICE.into()).0, 100 * NEAR, NEAR / 100);
+        assert_close(contract.get_pending_stbl(BOB.into()).0, 300 * NEAR, NEAR / 100);
+        assert_eq!(contract.get_pending_stbl(CAROL.into()).0, 0);
+    }
+
+    #[test]
+    fn emission_stops_at_the_max_supply() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(NEAR, 50 * NEAR);
+        contract.set_stbl_mining_config(NEAR.into(), 50 * PERCENT_BP);
+
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        let t1 = t0 + 100 * NANOS_PER_SECOND;
+        set_context(ALICE, 1, t1);
+        assert_close(contract.claim_stbl().0, 50 * NEAR, NEAR / 1000);
+        assert!(contract.total_stbl <= 50 * NEAR);
+
+        // nothing more is emitted
+        set_context(ALICE, 0, t1 + 100 * NANOS_PER_SECOND);
+        assert_eq!(contract.get_pending_stbl(ALICE.into()).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not registered")]
+    fn claim_requires_registration() {
+        let mut contract = new_contract();
+        set_context(CAROL, 1, 10 * NANOS_PER_SECOND);
+        contract.claim_stbl();
+    }
+}
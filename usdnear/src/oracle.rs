@@ -0,0 +1,500 @@
+//
+// PRICE FEED
+// a whitelist of reporters push timestamped stNEAR prices,
+// current_stnear_price is the median of the fresh reports
+// if the price is older than price_max_age, borrowing and stNEAR withdrawals are blocked
+//
+// PULL ORACLE
+// the price can also be pulled from a standard NEAR price-oracle contract (`get_price_data`)
+// the oracle contract counts as one more reporter in the median
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+/// default max age of the price: 2 hours (the operator reports every hour)
+pub const DEFAULT_PRICE_MAX_AGE: Duration = 2 * 60 * 60 * NANOS_PER_SECOND;
+/// max number of reporters, to keep median computation gas bounded
+pub const MAX_PRICE_REPORTERS: u64 = 20;
+/// oracle timestamps can be ahead of the block timestamp by this much (clock drift)
+pub const MAX_ORACLE_CLOCK_DRIFT: Duration = 60 * NANOS_PER_SECOND;
+
+/// price-oracle contract interface (NEAR DeFi price oracle)
+#[ext_contract(ext_price_oracle)]
+pub trait ExtPriceOracle {
+    fn get_price_data(&self, asset_ids: Option<Vec<String>>) -> PriceData;
+}
+
+/// price of the smallest unit of the asset in USD: multiplier / 10^decimals
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OraclePrice {
+    pub multiplier: U128String,
+    pub decimals: u8,
+}
+
+impl OraclePrice {
+    /// the oracle price is the USD value of one yocto-stNEAR: multiplier / 10^decimals
+    /// returns the USD value of one stNEAR with 24 decimals: multiplier * 10^24 * 10^24 / 10^decimals, or None if out of range
+    pub fn stnear_price_usd(&self) -> Option<u128> {
+        let scaled = U256::from(self.multiplier.0).checked_mul(U256::from(NEAR))?.checked_mul(U256::from(NEAR))?;
+        let price = scaled / U256::from(10).checked_pow(U256::from(self.decimals))?;
+        if price > U256::from(u128::MAX) {
+            return None;
+        }
+        return Some(price.as_u128());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetOptionalPrice {
+    pub asset_id: String,
+    pub price: Option<OraclePrice>,
+}
+
+/// returned by oracle.get_price_data
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    /// nanoseconds
+    pub timestamp: U64String,
+    pub recency_duration_sec: u32,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+/// last price reported by a whitelisted reporter
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct PriceReport {
+    pub price: u128,
+    /// 0 => never reported
+    pub timestamp: Timestamp,
+}
+
+/// returns the median of a list of prices, or None if the list is empty
+pub fn median(mut prices: Vec<u128>) -> Option<u128> {
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort();
+    let mid = prices.len() / 2;
+    if prices.len() % 2 == 1 {
+        return Some(prices[mid]);
+    }
+    return Some((prices[mid - 1] + prices[mid]) / 2);
+}
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn is_report_fresh(&self, report: &PriceReport) -> bool {
+        return report.timestamp > 0 && env::block_timestamp().saturating_sub(report.timestamp) <= self.price_max_age;
+    }
+
+    pub(crate) fn is_price_stale(&self) -> bool {
+        return env::block_timestamp().saturating_sub(self.last_price_timestamp) > self.price_max_age;
+    }
+
+    /// Asserts current_stnear_price is not older than price_max_age
+    pub(crate) fn assert_price_fresh(&self) {
+        assert!(!self.is_price_stale(), "stNEAR price is stale, last update at {}. Try again later", self.last_price_timestamp);
+    }
+
+    /// prices from reporters that reported within price_max_age
+    pub(crate) fn fresh_reported_prices(&self) -> Vec<u128> {
+        return self.price_reports.values()
+            .filter(|report| self.is_report_fresh(report))
+            .map(|report| report.price)
+            .collect();
+    }
+
+    /// records a report from a whitelisted reporter and
+    /// updates current_stnear_price to the median of fresh reports if there are enough of them
+    pub(crate) fn internal_report_price(&mut self, reporter_id: &AccountId, price: u128) {
+        assert!(self.price_reports.get(reporter_id).is_some(), "{} is not a price reporter", reporter_id);
+        assert!(price > 0, "price must be positive");
+
+        self.price_reports.insert(reporter_id, &PriceReport { price, timestamp: env::block_timestamp() });
+        events::PriceReport { reporter_id, price: price.into() }.emit();
+
+        let fresh_prices = self.fresh_reported_prices();
+        if fresh_prices.len() < self.min_fresh_reports as usize {
+            log!("{} fresh reports, {} required to update the price", fresh_prices.len(), self.min_fresh_reports);
+            return;
+        }
+        let median_price = median(fresh_prices).unwrap();
+        self.internal_set_stnear_price(median_price, reporter_id);
+    }
+
+    /// sets current_stnear_price, unless the change trips the circuit breaker (see circuit_breaker.rs)
+    pub(crate) fn internal_set_stnear_price(&mut self, new_price: u128, updated_by: &AccountId) {
+        assert!(new_price > 0, "price must be positive");
+        if let Some((kind, change_bp, limit_bp)) = self.check_circuit_breaker(new_price) {
+            self.trip_circuit_breaker(new_price, updated_by, kind, change_bp, limit_bp);
+            return;
+        }
+        self.internal_apply_stnear_price(new_price, updated_by);
+    }
+
+    /// sets current_stnear_price and its timestamp, records the observation for the TWAP
+    pub(crate) fn internal_apply_stnear_price(&mut self, new_price: u128, updated_by: &AccountId) {
+        events::PriceUpdate { updated_by, old_price: self.current_stnear_price.into(), new_price: new_price.into() }.emit();
+        // the price changes utilization, accrue at the previous APR
+        self.accrue_borrow_interest();
+        self.current_stnear_price = new_price;
+        self.last_price_timestamp = env::block_timestamp();
+        self.record_price_observation(new_price);
+    }
+
+    /// adds an account to the price reporters whitelist (owner or governance)
+    pub(crate) fn internal_add_price_reporter(&mut self, account_id: AccountId) {
+        assert!(env::is_valid_account_id(account_id.as_bytes()), "invalid account id {}", account_id);
+        assert!(self.price_reports.get(&account_id).is_none(), "{} is already a price reporter", account_id);
+        assert!(self.price_reports.len() < MAX_PRICE_REPORTERS, "max {} price reporters", MAX_PRICE_REPORTERS);
+        self.price_reports.insert(&account_id, &PriceReport::default());
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "add_price_reporter", value: account_id }.emit();
+    }
+
+    /// removes an account from the price reporters whitelist (owner or governance)
+    pub(crate) fn internal_remove_price_reporter(&mut self, account_id: AccountId) {
+        assert!(self.oracle_contract_id.as_ref() != Some(&account_id), "use set_price_oracle to remove the price oracle");
+        assert!(self.price_reports.remove(&account_id).is_some(), "{} is not a price reporter", account_id);
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "remove_price_reporter", value: account_id }.emit();
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Reporter's method.
+    /// Reports the current stNEAR price in USD (24 decimals). When there are at least `min_fresh_reports`
+    /// fresh reports, current_stnear_price is updated to their median
+    pub fn report_stnear_price(&mut self, stnear_price_usd: U128String) {
+        self.internal_report_price(&env::predecessor_account_id(), stnear_price_usd.0);
+    }
+
+    /// Pulls the stNEAR price from the configured price-oracle contract.
+    /// Open to anyone (like compute_rewards_and_interest), the oracle's answer is validated in the callback
+    pub fn pull_oracle_price(&mut self) {
+        assert!(self.oracle_contract_id.is_some(), "no price oracle configured");
+        let oracle_contract_id = self.oracle_contract_id.clone().unwrap();
+        ext_price_oracle::get_price_data(
+            Some(vec![self.oracle_asset_id.clone()]),
+            //promise params
+            &oracle_contract_id,
+            NO_DEPOSIT,
+            gas::GET_PRICE_DATA,
+        )
+        .then(ext_self_callback::after_get_price_data(
+            oracle_contract_id,
+            //promise params
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            gas::AFTER_GET_PRICE_DATA,
+        ));
+    }
+    /// prev fn continues here
+    /// validates asset id, recency (not older than price_max_age nor from the future) and decimals of the oracle's answer, then reports the price
+    pub fn after_get_price_data(&mut self, oracle_contract_id: AccountId, #[callback] price_data: PriceData) {
+
+        assert_callback_calling();
+
+        //the oracle could have been reconfigured while the call was in flight
+        if self.oracle_contract_id.as_ref() != Some(&oracle_contract_id) {
+            log!("price oracle changed, ignoring price from {}", oracle_contract_id);
+            return;
+        }
+
+        // a future timestamp would keep the price fresh after the oracle stops updating
+        if price_data.timestamp.0 > env::block_timestamp() + MAX_ORACLE_CLOCK_DRIFT {
+            log!("oracle price data is from the future, timestamp {}", price_data.timestamp.0);
+            return;
+        }
+        let age = env::block_timestamp().saturating_sub(price_data.timestamp.0);
+        if age > self.price_max_age || age > price_data.recency_duration_sec as u64 * NANOS_PER_SECOND {
+            log!("oracle price data is too old, timestamp {}", price_data.timestamp.0);
+            return;
+        }
+
+        let asset_price = price_data.prices.iter().find(|p| p.asset_id == self.oracle_asset_id);
+        let price = match asset_price.and_then(|p| p.price.clone()) {
+            Some(price) => price,
+            None => {
+                log!("oracle has no price for {}", self.oracle_asset_id);
+                return;
+            }
+        };
+        if price.decimals != self.oracle_price_decimals {
+            log!("oracle price decimals {} expected {}", price.decimals, self.oracle_price_decimals);
+            return;
+        }
+
+        // checked math, a panic here would lose the price update
+        let stnear_price_usd = match price.stnear_price_usd() {
+            Some(stnear_price_usd) if stnear_price_usd > 0 => stnear_price_usd,
+            _ => {
+                log!("oracle price {} with {} decimals is out of range", price.multiplier.0, price.decimals);
+                return;
+            }
+        };
+
+        self.internal_report_price(&oracle_contract_id, stnear_price_usd);
+    }
+
+    /// Owner's method.
+    /// Adds an account to the price reporters whitelist
+    pub fn add_price_reporter(&mut self, account_id: AccountId) {
+        self.assert_owner_calling();
+        self.internal_add_price_reporter(account_id);
+    }
+
+    /// Owner's method.
+    /// Removes an account from the price reporters whitelist
+    pub fn remove_price_reporter(&mut self, account_id: AccountId) {
+        self.assert_owner_calling();
+        self.internal_remove_price_reporter(account_id);
+    }
+
+    /// Parameter manager's method.
+    /// Sets the max age of price reports and the min number of fresh reports required to compute the median
+    pub fn set_price_feed_config(&mut self, price_max_age_seconds: U64String, min_fresh_reports: u16) {
+        self.assert_role(Role::ParameterManager);
+        assert!(price_max_age_seconds.0 > 0, "price_max_age_seconds must be positive");
+        assert!(min_fresh_reports > 0, "min_fresh_reports must be at least 1");
+        self.price_max_age = price_max_age_seconds.0 * NANOS_PER_SECOND;
+        self.min_fresh_reports = min_fresh_reports;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "price_feed_config",
+            value: format!("price_max_age_seconds:{} min_fresh_reports:{}", price_max_age_seconds.0, min_fresh_reports),
+        }.emit();
+    }
+
+    /// Owner's method.
+    /// Configures the price-oracle contract to pull prices from. An empty `oracle_contract_id` disables it.
+    /// `price_decimals` is the expected decimals of the oracle price for `asset_id` (the stNEAR token)
+    pub fn set_price_oracle(&mut self, oracle_contract_id: String, asset_id: String, price_decimals: u8) {
+        self.assert_owner_calling();
+        assert!(price_decimals <= 48, "price_decimals must be <= 48");
+        if let Some(previous) = self.oracle_contract_id.take() {
+            self.price_reports.remove(&previous);
+        }
+        if !oracle_contract_id.is_empty() {
+            assert!(self.price_reports.get(&oracle_contract_id).is_none(), "{} is already a price reporter", oracle_contract_id);
+            assert!(self.price_reports.len() < MAX_PRICE_REPORTERS, "max {} price reporters", MAX_PRICE_REPORTERS);
+            self.price_reports.insert(&oracle_contract_id, &PriceReport::default());
+            self.oracle_contract_id = Some(oracle_contract_id.clone());
+        }
+        self.oracle_asset_id = asset_id;
+        self.oracle_price_decimals = price_decimals;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "price_oracle",
+            value: format!("{} {} {}", oracle_contract_id, self.oracle_asset_id, price_decimals),
+        }.emit();
+    }
+
+    /// Returns each reporter's last report
+    pub fn get_price_reporters(&self) -> Vec<PriceReportJSON> {
+        return self.price_reports.iter()
+            .map(|(reporter_id, report)| PriceReportJSON {
+                reporter_id,
+                price: report.price.into(),
+                timestamp: report.timestamp.into(),
+                fresh: self.is_report_fresh(&report),
+            })
+            .collect();
+    }
+
+    /// Returns the current price, its age and the price feed config
+    pub fn get_price_feed_status(&self) -> PriceFeedStatusJSON {
+        return PriceFeedStatusJSON {
+            current_stnear_price: self.current_stnear_price.into(),
+            last_price_timestamp: self.last_price_timestamp.into(),
+            is_stale: self.is_price_stale(),
+            price_max_age_seconds: (self.price_max_age / NANOS_PER_SECOND).into(),
+            min_fresh_reports: self.min_fresh_reports,
+            fresh_reports: self.fresh_reported_prices().len() as u16,
+            oracle_contract_id: self.oracle_contract_id.clone(),
+            oracle_asset_id: self.oracle_asset_id.clone(),
+            oracle_price_decimals: self.oracle_price_decimals,
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    const ORACLE: &str = "oracle.near";
+
+    fn price_data(multiplier: u128, decimals: u8, timestamp: Timestamp) -> PriceData {
+        return PriceData {
+            timestamp: timestamp.into(),
+            recency_duration_sec: 90,
+            prices: vec![AssetOptionalPrice {
+                asset_id: META_POOL_STNEAR_CONTRACT.into(),
+                price: Some(OraclePrice { multiplier: multiplier.into(), decimals }),
+            }],
+        };
+    }
+
+    fn contract_with_oracle(decimals: u8) -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_price_oracle(ORACLE.into(), META_POOL_STNEAR_CONTRACT.into(), decimals);
+        return contract;
+    }
+
+    #[test]
+    fn oracle_price_is_scaled_to_24_decimals() {
+        // USD 10.5 per stNEAR, 28 decimals per yocto-stNEAR => multiplier 105000
+        assert_eq!(OraclePrice { multiplier: 105000.into(), decimals: 28 }.stnear_price_usd(), Some(105 * NEAR / 10));
+        assert_eq!(OraclePrice { multiplier: u128::MAX.into(), decimals: 0 }.stnear_price_usd(), None);
+        assert_eq!(OraclePrice { multiplier: 1.into(), decimals: 0 }.stnear_price_usd(), None);
+        assert_eq!(OraclePrice { multiplier: 1.into(), decimals: 48 }.stnear_price_usd(), Some(1));
+        assert_eq!(OraclePrice { multiplier: 1.into(), decimals: 255 }.stnear_price_usd(), None);
+    }
+
+    #[test]
+    fn oracle_price_out_of_range_is_ignored() {
+        for (multiplier, decimals) in [(u128::MAX, 0), (1, 0), (0, 28)].iter() {
+            let mut contract = contract_with_oracle(*decimals);
+            let t = 10 * NANOS_PER_SECOND;
+            set_context(CONTRACT, 0, t);
+            contract.after_get_price_data(ORACLE.into(), price_data(*multiplier, *decimals, t));
+            assert_not_reported(&contract);
+        }
+    }
+
+    #[test]
+    fn oracle_price_is_reported() {
+        let mut contract = contract_with_oracle(28);
+        let t = 10 * NANOS_PER_SECOND;
+        set_context(CONTRACT, 0, t);
+        contract.after_get_price_data(ORACLE.into(), price_data(105000, 28, t));
+        assert_eq!(contract.price_reports.get(&ORACLE.into()).unwrap().price, 105 * NEAR / 10);
+    }
+
+    fn assert_not_reported(contract: &UsdNearStableCoin) {
+        assert_eq!(contract.price_reports.get(&ORACLE.into()).unwrap().timestamp, 0);
+        assert_eq!(contract.current_stnear_price, INITIAL_PRICE);
+    }
+
+    #[test]
+    fn stale_oracle_price_is_ignored() {
+        let mut contract = contract_with_oracle(28);
+        // older than recency_duration_sec (90 seconds)
+        let t = 10 * ONE_HOUR;
+        set_context(CONTRACT, 0, t);
+        contract.after_get_price_data(ORACLE.into(), price_data(105000, 28, t - 91 * NANOS_PER_SECOND));
+        assert_not_reported(&contract);
+    }
+
+    #[test]
+    fn future_dated_oracle_price_is_ignored() {
+        let mut contract = contract_with_oracle(28);
+        let t = 10 * NANOS_PER_SECOND;
+        set_context(CONTRACT, 0, t);
+        contract.after_get_price_data(ORACLE.into(), price_data(105000, 28, t + MAX_ORACLE_CLOCK_DRIFT + 1));
+        assert_not_reported(&contract);
+        // a small drift is accepted
+        contract.after_get_price_data(ORACLE.into(), price_data(105000, 28, t + MAX_ORACLE_CLOCK_DRIFT));
+        assert_eq!(contract.price_reports.get(&ORACLE.into()).unwrap().price, 105 * NEAR / 10);
+    }
+
+    #[test]
+    fn oracle_price_of_another_asset_is_ignored() {
+        let mut contract = contract_with_oracle(28);
+        let t = 10 * NANOS_PER_SECOND;
+        set_context(CONTRACT, 0, t);
+        let mut data = price_data(105000, 28, t);
+        data.prices[0].asset_id = "wrap.near".into();
+        contract.after_get_price_data(ORACLE.into(), data);
+        assert_not_reported(&contract);
+    }
+
+    #[test]
+    fn oracle_price_with_other_decimals_is_ignored() {
+        let mut contract = contract_with_oracle(28);
+        let t = 10 * NANOS_PER_SECOND;
+        set_context(CONTRACT, 0, t);
+        contract.after_get_price_data(ORACLE.into(), price_data(10500, 27, t));
+        assert_not_reported(&contract);
+    }
+
+    /// ALICE and BOB join the operator as price reporters, 2 fresh reports required within 60 seconds
+    fn contract_with_reporters() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.add_price_reporter(ALICE.into());
+        contract.add_price_reporter(BOB.into());
+        contract.set_price_feed_config(60.into(), 2);
+        return contract;
+    }
+
+    fn report(contract: &mut UsdNearStableCoin, reporter: &str, price: u128, block_timestamp: Timestamp) {
+        set_context(reporter, 0, block_timestamp);
+        contract.report_stnear_price(price.into());
+    }
+
+    #[test]
+    fn median_of_odd_and_even_lists() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3, 1, 2]), Some(2));
+        assert_eq!(median(vec![4, 1, 3, 2]), Some(2));
+        assert_eq!(median(vec![7]), Some(7));
+    }
+
+    #[test]
+    fn price_is_the_median_of_enough_fresh_reports() {
+        let mut contract = contract_with_reporters();
+        let t = 10 * NANOS_PER_SECOND;
+        // one fresh report is not enough
+        report(&mut contract, ALICE, 11 * NEAR, t);
+        assert_eq!(contract.current_stnear_price, INITIAL_PRICE);
+        report(&mut contract, BOB, 12 * NEAR, t);
+        assert_eq!(contract.current_stnear_price, 115 * NEAR / 10);
+        report(&mut contract, OPERATOR, 9 * NEAR, t);
+        assert_eq!(contract.current_stnear_price, 11 * NEAR);
+    }
+
+    #[test]
+    fn stale_reports_are_left_out() {
+        let mut contract = contract_with_reporters();
+        report(&mut contract, ALICE, 11 * NEAR, 10 * NANOS_PER_SECOND);
+        // ALICE's report is 90 seconds old
+        report(&mut contract, BOB, 12 * NEAR, 100 * NANOS_PER_SECOND);
+        assert_eq!(contract.current_stnear_price, INITIAL_PRICE);
+        assert_eq!(contract.fresh_reported_prices(), vec![12 * NEAR]);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a price reporter")]
+    fn only_whitelisted_accounts_report() {
+        let mut contract = contract_with_reporters();
+        report(&mut contract, CAROL, 11 * NEAR, 10 * NANOS_PER_SECOND);
+    }
+
+    #[test]
+    #[should_panic(expected = "stNEAR price is stale")]
+    fn stale_price_blocks_borrowing() {
+        let mut contract = contract_with_reporters();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, 100 * NANOS_PER_SECOND);
+    }
+
+    #[test]
+    fn the_operator_can_not_bypass_the_median() {
+        let mut contract = contract_with_reporters();
+        let t = 10 * NANOS_PER_SECOND;
+        report(&mut contract, ALICE, 11 * NEAR, t);
+        report(&mut contract, BOB, 11 * NEAR, t);
+        set_context(OPERATOR, 0, t);
+        contract.set_stnear_price_usd((13 * NEAR).into());
+        assert_eq!(contract.current_stnear_price, 11 * NEAR);
+        assert_eq!(contract.price_reports.get(&OPERATOR.into()).unwrap().price, 13 * NEAR);
+    }
+}
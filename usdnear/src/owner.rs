@@ -23,19 +23,121 @@ impl UsdNearStableCoin {
         Promise::new(env::current_account_id()).add_full_access_key(new_public_key)
     }
 
+    /// Parameter manager's method.
+    /// Sets USDNEAR NEP-148 metadata (name, icon, reference). symbol and decimals can not be changed
+    pub fn set_ft_metadata(&mut self, metadata: FungibleTokenMetadata) {
+        self.assert_role(Role::ParameterManager);
+        crate::funtoken::assert_valid_ft_metadata(&metadata);
+        assert_eq!(metadata.symbol, crate::funtoken::USDNEAR, "symbol can not be changed");
+        assert_eq!(metadata.decimals, crate::funtoken::USDNEAR_DECIMALS, "decimals can not be changed");
+        self.usdnear_metadata = metadata;
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "ft_metadata", value: self.usdnear_metadata.name.clone() }.emit();
+    }
+
+    /// Owner's method.
+    /// First step of the ownership transfer, `new_owner_account_id` must call `accept_ownership`
+    pub fn propose_owner(&mut self, new_owner_account_id: AccountId) {
+        self.assert_owner_calling();
+        assert!(env::is_valid_account_id(new_owner_account_id.as_bytes()),"invalid account id {}",new_owner_account_id);
+        assert!(new_owner_account_id != self.owner_account_id,"{} is already the owner",new_owner_account_id);
+        events::AccountChange {
+            updated_by: &env::predecessor_account_id(),
+            change: "owner_proposed",
+            old_account_id: &self.owner_account_id,
+            new_account_id: &new_owner_account_id,
+        }.emit();
+        self.pending_owner_account_id = Some(new_owner_account_id);
+    }
+
+    /// Owner's method.
+    /// Cancels a proposed ownership transfer
+    pub fn cancel_owner_proposal(&mut self) {
+        self.assert_owner_calling();
+        assert!(self.pending_owner_account_id.is_some(),"there's no pending owner");
+        let pending_owner = self.pending_owner_account_id.take().unwrap();
+        events::AccountChange {
+            updated_by: &env::predecessor_account_id(),
+            change: "owner_proposal_cancelled",
+            old_account_id: &self.owner_account_id,
+            new_account_id: &pending_owner,
+        }.emit();
+    }
+
+    /// Pending owner's method.
+    /// Second step of the ownership transfer, the proposed owner accepts and becomes the owner
+    pub fn accept_ownership(&mut self) {
+        let predecessor = env::predecessor_account_id();
+        assert!(self.pending_owner_account_id.as_ref() == Some(&predecessor),"Can only be called by the pending owner");
+        events::AccountChange {
+            updated_by: &predecessor,
+            change: "owner_accepted",
+            old_account_id: &self.owner_account_id,
+            new_account_id: &predecessor,
+        }.emit();
+        self.owner_account_id = predecessor;
+        self.pending_owner_account_id = None;
+    }
+
+    /// Owner's method.
+    /// Sets the operator account. The operator role and price reporter are moved to the new account. The account must be registered
+    pub fn set_operator_account_id(&mut self, account_id: AccountId) {
+        self.assert_owner_calling();
+        assert!(env::is_valid_account_id(account_id.as_bytes()),"invalid account id {}",account_id);
+        let old_operator = self.operator_account_id.clone();
+        assert!(account_id != old_operator,"{} is already the operator",account_id);
+        //the operator is credited with fees
+        self.assert_registered(&account_id);
+        let mut operators = self.role_members(Role::Operator);
+        operators.retain(|m| m != &old_operator);
+        self.role_members.insert(&Role::Operator, &operators);
+        self.internal_grant_role(Role::Operator, &account_id);
+        if self.price_reports.remove(&old_operator).is_some() && self.price_reports.get(&account_id).is_none() {
+            self.price_reports.insert(&account_id, &PriceReport::default());
+        }
+        events::AccountChange {
+            updated_by: &env::predecessor_account_id(),
+            change: "operator",
+            old_account_id: &old_operator,
+            new_account_id: &account_id,
+        }.emit();
+        self.operator_account_id = account_id;
+    }
+
     /// Owner's method.
-    /// Pauses borrowing
-    pub fn pause_borrowing(&mut self) {
+    /// Sets the treasury account, where interest is paid from now on. The account must be registered
+    pub fn set_treasury_account_id(&mut self, account_id: AccountId) {
         self.assert_owner_calling();
-        assert!(!self.borrowing_paused, "borrowing is already paused");
-        self.borrowing_paused = true;
+        assert!(env::is_valid_account_id(account_id.as_bytes()),"invalid account id {}",account_id);
+        self.assert_registered(&account_id);
+        events::AccountChange {
+            updated_by: &env::predecessor_account_id(),
+            change: "treasury",
+            old_account_id: &self.treasury_account_id,
+            new_account_id: &account_id,
+        }.emit();
+        self.treasury_account_id = account_id;
     }
 
     /// Returns the account ID of the owner.
+    pub fn get_owner_account_id(&self) -> AccountId {
+        return self.owner_account_id.clone();
+    }
+
+    /// Returns the account ID of the proposed owner, if any
+    pub fn get_pending_owner_account_id(&self) -> Option<AccountId> {
+        return self.pending_owner_account_id.clone();
+    }
+
+    /// Returns the account ID of the operator.
     pub fn get_operator_account_id(&self) -> AccountId {
         return self.operator_account_id.clone();
     }
 
+    /// Returns the account ID of the treasury.
+    pub fn get_treasury_account_id(&self) -> AccountId {
+        return self.treasury_account_id.clone();
+    }
+
     /// The amount of usdnear tokens in circulation
     pub fn get_total_usdnear(&self) -> U128String {
         return self.total_usdnear.into();
@@ -46,12 +148,13 @@ impl UsdNearStableCoin {
     pub fn get_account_info(&self, account_id: AccountId) -> GetAccountInfoResult {
         let usdnear = self.get_usdnear_balance(&account_id); 
         let acc = self.internal_get_account(&account_id);
+        let stbl = self.get_stbl_balance(&account_id);
         return GetAccountInfoResult {
             account_id,
             usdnear: usdnear.into(),
             stnear: acc.free_stnear(&self).into(),
             stnear_price_usd: self.current_stnear_price.into(),
-            stbl: acc.stbl.into(),
+            stbl: stbl.into(),
             usdnear_credit_limit: acc.get_current_credit_limit(self).into(),
             locked_stnear: acc.locked_stnear(&self).into(),
             valued_collateral_usd: acc.valued_collateral_usd(&self).into(),
@@ -82,17 +185,20 @@ impl UsdNearStableCoin {
             name: CONTRACT_NAME.into(),
             version:CONTRACT_VERSION.into(),
             source:"https://github.com/Narwallets/usdnear-stable".into(), 
-            standards:vec!("NEP-129".into(),"NEP-138".into()),  
+            standards:vec!("NEP-129".into(),"NEP-138".into(),"NEP-141".into(),"NEP-145".into(),"NEP-148".into()),  
             webAppUrl:self.web_app_url.clone(),
             developersAccountId:DEVELOPERS_ACCOUNT_ID.into(),
             auditorAccountId: self.auditor_account_id.clone()
         }
     }
 
+    /// Parameter manager's method.
     /// sets configurable contract info [NEP-129](https://github.com/nearprotocol/NEPs/pull/129)
     // Note: params are not Option<String> so the user can not inadvertely set null to data by not including the argument
     pub fn set_contract_info(&mut self, web_app_url:String, auditor_account_id:String) {
-        self.assert_owner_calling();
+        self.assert_role(Role::ParameterManager);
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "web_app_url", value: web_app_url.clone() }.emit();
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "auditor_account_id", value: auditor_account_id.clone() }.emit();
         self.web_app_url = if web_app_url.len()>0 { Some(web_app_url) } else { None };
         self.auditor_account_id = if auditor_account_id.len()>0 { Some(auditor_account_id) } else { None };
     }
@@ -111,6 +217,9 @@ impl UsdNearStableCoin {
             b_accounts_count: self.b_accounts.len().into(),
             total_collateral_shares: self.total_collateral_shares.into(),
             usdnear_apr_basis_points: self.usdnear_apr_basis_points,
+            rate_model: self.rate_model.clone(),
+            current_apr_basis_points: self.current_apr_basis_points(),
+            utilization_bp: self.utilization_bp(),
         };
     }
 
@@ -119,7 +228,7 @@ impl UsdNearStableCoin {
         return ContractParamsJSON {
             collateral_basis_points: self.collateral_basis_points,
             min_collateral_basis_points: self.min_collateral_basis_points,
-            borrowing_paused: self.borrowing_paused,
+            borrowing_paused: self.paused.borrowing,
             min_account_balance: self.min_account_balance.into(),
             usdnear_apr_basis_points: self.usdnear_apr_basis_points,
             epochs_per_year: self.epochs_per_year,
@@ -128,39 +237,11 @@ impl UsdNearStableCoin {
             };
     }
 
-    /// Sets contract parameters 
-    pub fn set_contract_params(&mut self, params:ContractParamsJSON) {
-
-        self.assert_owner_calling();
-
-        assert!(params.collateral_basis_points>120*PERCENT_BP);
-        self.collateral_basis_points = params.collateral_basis_points;
-
-        assert!(params.min_collateral_basis_points>110*PERCENT_BP);
-        self.min_collateral_basis_points = params.min_collateral_basis_points;
-
-        // collateral_basis_points should be > 100%collat+liquidation_fee 
-        assert!(self.collateral_basis_points > 10000+self.liquidaton_fee_basis_points as u32);
-
-        self.borrowing_paused = params.borrowing_paused;
-
-        self.min_account_balance = params.min_account_balance.0;
-
-        self.usdnear_apr_basis_points = params.usdnear_apr_basis_points;
-        self.epochs_per_year = params.epochs_per_year;
-
-        assert!(params.operator_fee_basis_points+params.treasury_fee_basis_points==10000,"fee split must add 100%");
-        self.operator_fee_basis_points = params.operator_fee_basis_points;
-        self.treasury_fee_basis_points = params.treasury_fee_basis_points;
-    }
-
 
-    /// Sets contract parameters 
+    /// Reporter's method (the operator is a price reporter).
+    /// Same as report_stnear_price, kept for existing clients: the price is a report, it goes through the median and min_fresh_reports (see oracle.rs)
     pub fn set_stnear_price_usd(&mut self, stnear_price_usd:U128String) {
-        self.assert_owner_calling();
-        //allow 25% variation max
-        assert!(stnear_price_usd.0 > self.current_stnear_price* 75/100 && stnear_price_usd.0 < self.current_stnear_price * 125/100 );
-        self.current_stnear_price = stnear_price_usd.0;
+        self.internal_report_price(&env::predecessor_account_id(), stnear_price_usd.0);
     }
 
     //DURING TESTING methods
@@ -168,8 +249,9 @@ impl UsdNearStableCoin {
         self.assert_owner_calling();
         self.busy= false;
     }
+    /// Treasury manager's method.
     pub fn forgive_loan(&mut self, account_id:AccountId) {
-        self.assert_owner_calling();
+        self.assert_role(Role::TreasuryManager);
         let mut acc = self.internal_get_account(&account_id);
         let owed_usdnear= self.amount_from_usdnear_shares(acc.shares_usdnear_owed);
         acc.remove_owed_usdnear_preserve_share_price(owed_usdnear,self);
@@ -177,6 +259,8 @@ impl UsdNearStableCoin {
         acc.remove_locked_amount_preserve_share_price(locked_stnear,self);
         acc.add_free_amount_preserve_share_price(locked_stnear,self);
         self.internal_update_account(&account_id,&acc);
+        self.update_mining_account(&account_id);
+        events::ForgiveLoan { account_id: &account_id, usdnear_amount: owed_usdnear.into() }.emit();
     }
 
     /// compute rewards and interest
@@ -248,14 +332,11 @@ impl UsdNearStableCoin {
         );
 
         if rewards > 0 {
-            // compute interest on loans as a % of rewards
+            // pay the interest accrued on loans (see interest.rs) with the staking rewards
             // initially loan interest is 2.5% APR. 
-            // NEAR staking rewards are about 10% APY (feb-2021), so a 25% of rewards approximates 2.5% APR
-            // The 25% has to be computed ONLY on 100% collateral, so is 2.5% APR over USDNEAR owed
-
-            let interest_year_usdnear = apply_pct(self.usdnear_apr_basis_points, self.total_usdnear);
-            let interest_epoch_usdnear = interest_year_usdnear / self.epochs_per_year as u128; //default 365*2, 2 epochs per day
-            let interest_epoch_stnear = self.usdnear_to_stnear(interest_epoch_usdnear);
+            // NEAR staking rewards are about 10% APY (feb-2021), so the rewards are more than enough to pay the interest
+            // if they're not, the unpaid interest remains as debt and is paid next epoch
+            let interest_epoch_stnear = self.pay_interest_with_stnear(rewards);
 
             //compute rewards to distribute between locked-stnear-pool and free-stnear-pool
             let rewards_to_distribute = rewards - interest_epoch_stnear;
@@ -263,20 +344,155 @@ impl UsdNearStableCoin {
             let rewards_for_free_stnear = proportional(rewards_to_distribute, self.total_free_stnear, total_stnear_in_the_contract);
             let rewards_for_locked_stnear = proportional(rewards_to_distribute, self.total_collateral_stnear, total_stnear_in_the_contract);
 
-            //add interest stNEAR to treasury (computed by difference)
+            //interest stNEAR (computed by difference)
             assert!(rewards>=rewards_for_free_stnear+rewards_for_locked_stnear);
-            let amount_for_treasury = rewards - rewards_for_free_stnear - rewards_for_locked_stnear;
-            &self.add_amount_and_free_shares_preserve_share_price(self.treasury_account_id.clone(), amount_for_treasury);
-            log!("treasury got {} as epoch interest payment",amount_for_treasury);
+            let interest_stnear = rewards - rewards_for_free_stnear - rewards_for_locked_stnear;
+            //share interest with STBL stakers
+            let amount_for_stakers = self.distribute_staking_rewards(interest_stnear);
+            log!("STBL stakers got {} as epoch interest payment",amount_for_stakers);
+            //reserve share, overflowing to the treasury above the reserve target
+            let amount_for_reserve = self.pay_reserve_interest_share(interest_stnear - amount_for_stakers);
+            log!("reserve got {} as epoch interest payment",amount_for_reserve);
+            //split the rest of the interest stNEAR between operator and treasury
+            let (amount_for_operator, amount_for_treasury) = self.pay_operator_and_treasury_fees(interest_stnear - amount_for_stakers - amount_for_reserve);
+            log!("operator got {} and treasury got {} as epoch interest payment",amount_for_operator,amount_for_treasury);
             
             // rest of staking rewards go into free and collateral pools, increasing share value -> stNEAR amounts for everyone
+            // (STBL liquidity mining emits at the share prices before the rewards)
+            self.update_mining_accumulators();
             self.total_collateral_stnear += rewards_for_free_stnear;
             self.total_collateral_stnear += rewards_for_locked_stnear;
 
             self.last_rewards_epoch_height = env::epoch_height();
 
+            //peg controller step
+            self.update_rate_model_on_epoch();
+
+            self.record_epoch(&EpochRecord {
+                epoch_height: env::epoch_height(),
+                timestamp: env::block_timestamp(),
+                rewards_stnear: rewards,
+                interest_stnear,
+                stakers_stnear: amount_for_stakers,
+                operator_stnear: amount_for_operator,
+                treasury_stnear: amount_for_treasury,
+                reserve_stnear: amount_for_reserve,
+            });
+
+            events::EpochRewards {
+                epoch_height: env::epoch_height().into(),
+                rewards_stnear: rewards.into(),
+                interest_stnear: interest_stnear.into(),
+                stakers_stnear: amount_for_stakers.into(),
+                operator_stnear: amount_for_operator.into(),
+                treasury_stnear: amount_for_treasury.into(),
+                reserve_stnear: amount_for_reserve.into(),
+            }.emit();
+
         }
     }
     
 
 }
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn assert_valid_contract_params(&self, params: &ContractParamsJSON) {
+        assert!(params.collateral_basis_points>120*PERCENT_BP);
+        assert!(params.min_collateral_basis_points>110*PERCENT_BP);
+        // collateral_basis_points should be > 100%collat+liquidation_fee 
+        assert!(params.collateral_basis_points > 10000+self.liquidaton_fee_basis_points as u32);
+        assert!(params.operator_fee_basis_points+params.treasury_fee_basis_points==10000,"fee split must add 100%");
+    }
+
+    /// Sets contract parameters. Called when a params proposal is executed (see timelock.rs)
+    pub(crate) fn internal_set_contract_params(&mut self, params: &ContractParamsJSON) {
+
+        self.assert_valid_contract_params(params);
+
+        // accrue interest at the previous APR
+        self.accrue_borrow_interest();
+
+        self.collateral_basis_points = params.collateral_basis_points;
+        self.min_collateral_basis_points = params.min_collateral_basis_points;
+
+        // a params proposal can pause borrowing but never unpause it, only the owner can unpause (see pause.rs)
+        if params.borrowing_paused {
+            self.internal_set_paused("borrowing", true);
+        }
+
+        self.min_account_balance = params.min_account_balance.0;
+
+        self.usdnear_apr_basis_points = params.usdnear_apr_basis_points;
+        self.epochs_per_year = params.epochs_per_year;
+
+        self.operator_fee_basis_points = params.operator_fee_basis_points;
+        self.treasury_fee_basis_points = params.treasury_fee_basis_points;
+
+        events::ParamsUpdate { updated_by: &env::predecessor_account_id(), params }.emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn ownership_transfer_takes_two_steps() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.propose_owner(ALICE.into());
+        assert_eq!(contract.get_owner_account_id(), OWNER);
+        assert_eq!(contract.get_pending_owner_account_id(), Some(ALICE.into()));
+
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.accept_ownership();
+        assert_eq!(contract.get_owner_account_id(), ALICE);
+        assert_eq!(contract.get_pending_owner_account_id(), None);
+        assert!(contract.has_role(Role::Guardian, ALICE.into()));
+        assert!(!contract.has_role(Role::Guardian, OWNER.into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the pending owner")]
+    fn only_the_pending_owner_accepts() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.propose_owner(ALICE.into());
+        set_context(BOB, 0, NANOS_PER_SECOND);
+        contract.accept_ownership();
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the pending owner")]
+    fn cancelled_proposals_can_not_be_accepted() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.propose_owner(ALICE.into());
+        contract.cancel_owner_proposal();
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.accept_ownership();
+    }
+
+    #[test]
+    fn the_new_operator_takes_the_role_and_the_price_reports() {
+        let mut contract = new_contract();
+        register(&mut contract, CAROL, NANOS_PER_SECOND);
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_operator_account_id(CAROL.into());
+        assert_eq!(contract.get_operator_account_id(), CAROL);
+        assert!(contract.has_role(Role::Operator, CAROL.into()));
+        assert!(!contract.has_role(Role::Operator, OPERATOR.into()));
+        assert!(contract.price_reports.get(&CAROL.into()).is_some());
+        assert!(contract.price_reports.get(&OPERATOR.into()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account carol.near is not registered")]
+    fn the_new_treasury_must_be_registered() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_treasury_account_id(CAROL.into());
+    }
+}
@@ -0,0 +1,186 @@
+//
+// PAUSE CONTROLS
+// each operation can be paused independently.
+// The guardian (or the owner) can pause, only the owner can unpause
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+pub const PAUSABLE_OPERATIONS: &[&str] = &[
+    "borrowing",
+    "repaying",
+    "deposits",
+    "withdrawals",
+    "conversions",
+    "liquidations",
+    "transfers",
+];
+
+/// true => the operation is paused
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseFlags {
+    /// take_loan (minting USDNEAR)
+    pub borrowing: bool,
+    /// repay_loan
+    pub repaying: bool,
+    /// stNEAR deposits (ft_on_transfer)
+    pub deposits: bool,
+    /// withdraw_stnear
+    pub withdrawals: bool,
+    /// convert_usdnear (conversion window)
+    pub conversions: bool,
+    /// liquidate
+    pub liquidations: bool,
+    /// ft_transfer, ft_transfer_call & stbl_transfer
+    pub transfers: bool,
+}
+
+impl PauseFlags {
+    fn flag_mut(&mut self, operation: &str) -> &mut bool {
+        return match operation {
+            "borrowing" => &mut self.borrowing,
+            "repaying" => &mut self.repaying,
+            "deposits" => &mut self.deposits,
+            "withdrawals" => &mut self.withdrawals,
+            "conversions" => &mut self.conversions,
+            "liquidations" => &mut self.liquidations,
+            "transfers" => &mut self.transfers,
+            _ => panic!("unknown operation {}. Valid: all, {}", operation, PAUSABLE_OPERATIONS.join(", ")),
+        };
+    }
+
+    pub fn is_paused(&self, operation: &str) -> bool {
+        return match operation {
+            "borrowing" => self.borrowing,
+            "repaying" => self.repaying,
+            "deposits" => self.deposits,
+            "withdrawals" => self.withdrawals,
+            "conversions" => self.conversions,
+            "liquidations" => self.liquidations,
+            "transfers" => self.transfers,
+            _ => panic!("unknown operation {}", operation),
+        };
+    }
+}
+
+impl UsdNearStableCoin {
+
+    /// Asserts the operation is not paused
+    pub(crate) fn assert_not_paused(&self, operation: &str) {
+        assert!(!self.paused.is_paused(operation), "{} is paused", operation);
+    }
+
+    pub(crate) fn internal_set_paused(&mut self, operation: &str, paused: bool) {
+        let operations: Vec<&str> = if operation == "all" { PAUSABLE_OPERATIONS.to_vec() } else { vec![operation] };
+        for op in operations {
+            let flag = self.paused.flag_mut(op);
+            if *flag != paused {
+                *flag = paused;
+                events::PauseUpdate { updated_by: &env::predecessor_account_id(), operation: op, paused }.emit();
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Guardian's method (or owner).
+    /// Pauses an operation: borrowing, repaying, deposits, withdrawals, conversions, liquidations, transfers or all
+    pub fn pause(&mut self, operation: String) {
+        self.assert_role(Role::Guardian);
+        self.internal_set_paused(&operation, true);
+    }
+
+    /// Owner's method.
+    /// Unpauses an operation: borrowing, repaying, deposits, withdrawals, conversions, liquidations, transfers or all
+    pub fn unpause(&mut self, operation: String) {
+        self.assert_owner_calling();
+        self.internal_set_paused(&operation, false);
+    }
+
+    /// Returns which operations are paused
+    pub fn get_pause_status(&self) -> PauseFlags {
+        return self.paused.clone();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    const GUARDIAN: &str = "guardian.near";
+
+    fn contract_with_guardian() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.grant_role(Role::Guardian, GUARDIAN.into());
+        return contract;
+    }
+
+    #[test]
+    fn operations_pause_independently() {
+        let mut contract = contract_with_guardian();
+        set_context(GUARDIAN, 0, NANOS_PER_SECOND);
+        contract.pause("transfers".into());
+        let status = contract.get_pause_status();
+        assert!(status.transfers);
+        assert!(!status.borrowing && !status.deposits && !status.withdrawals);
+
+        // borrowing still works
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.unpause("transfers".into());
+        assert!(!contract.get_pause_status().transfers);
+    }
+
+    #[test]
+    fn pause_all_and_unpause_all() {
+        let mut contract = contract_with_guardian();
+        set_context(GUARDIAN, 0, NANOS_PER_SECOND);
+        contract.pause("all".into());
+        for operation in PAUSABLE_OPERATIONS {
+            assert!(contract.paused.is_paused(operation));
+        }
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.unpause("all".into());
+        for operation in PAUSABLE_OPERATIONS {
+            assert!(!contract.paused.is_paused(operation));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "transfers is paused")]
+    fn paused_operations_fail() {
+        let mut contract = contract_with_guardian();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        register(&mut contract, BOB, NANOS_PER_SECOND);
+        set_context(GUARDIAN, 0, NANOS_PER_SECOND);
+        contract.pause("transfers".into());
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        contract.ft_transfer(BOB.into(), NEAR.into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn the_guardian_can_not_unpause() {
+        let mut contract = contract_with_guardian();
+        set_context(GUARDIAN, 0, NANOS_PER_SECOND);
+        contract.pause("borrowing".into());
+        contract.unpause("borrowing".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown operation")]
+    fn unknown_operations_are_rejected() {
+        let mut contract = contract_with_guardian();
+        set_context(GUARDIAN, 0, NANOS_PER_SECOND);
+        contract.pause("minting".into());
+    }
+}
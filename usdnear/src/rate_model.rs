@@ -0,0 +1,389 @@
+//
+// INTEREST RATE MODELS
+// the loan APR (see interest.rs) is computed by a pluggable model, selected and parameterized by governance:
+// - fixed: `usdnear_apr_basis_points` from the contract params
+// - kinked_utilization: grows with utilization (debt / borrowing capacity of the collateral), steeper after the kink
+// - peg_controller: every epoch the APR steps up if USDNEAR trades or converts below $1, and down if it trades above $1
+// the APR is computed from the state at the last interest accrual, and every rate-changing operation accrues first
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+/// max APR any model can return: 100%
+pub const MAX_APR_BP: u32 = 100 * PERCENT_BP;
+/// USDNEAR market price precision (24 decimals), 1 USD
+pub const ONE_USD: u128 = NEAR;
+
+/// what the models see
+pub struct RateInputs {
+    /// `usdnear_apr_basis_points` from the contract params
+    pub base_apr_bp: u32,
+    /// debt / borrowing capacity of the collateral at collateral_basis_points, in basis points (can be > 100%)
+    pub utilization_bp: u32,
+    /// APR set by the peg controller on the last epoch
+    pub peg_controller_apr_bp: u32,
+}
+
+/// peg signal measured every epoch
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PegSignal {
+    BelowPeg,
+    AtPeg,
+    AbovePeg,
+}
+
+pub trait InterestRateModel {
+    /// loan APR in basis points
+    fn apr_basis_points(&self, inputs: &RateInputs) -> u32;
+
+    /// called every epoch, returns the new peg controller APR. Stateless models keep it unchanged
+    fn on_epoch(&self, current_apr_bp: u32, _signal: PegSignal) -> u32 {
+        return current_apr_bp;
+    }
+
+    fn assert_valid(&self) {}
+}
+
+pub struct FixedRate;
+
+impl InterestRateModel for FixedRate {
+    fn apr_basis_points(&self, inputs: &RateInputs) -> u32 {
+        return inputs.base_apr_bp;
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KinkedUtilizationRate {
+    /// APR at 0% utilization
+    pub base_apr_bp: u32,
+    /// APR added from 0% to kink utilization
+    pub slope1_bp: u32,
+    pub kink_utilization_bp: u32,
+    /// APR added from kink to 100% utilization (keeps growing above 100%)
+    pub slope2_bp: u32,
+}
+
+impl InterestRateModel for KinkedUtilizationRate {
+    fn apr_basis_points(&self, inputs: &RateInputs) -> u32 {
+        let utilization = inputs.utilization_bp as u64;
+        let kink = self.kink_utilization_bp as u64;
+        let mut apr = self.base_apr_bp as u64 + self.slope1_bp as u64 * std::cmp::min(utilization, kink) / kink;
+        if utilization > kink {
+            apr += self.slope2_bp as u64 * (utilization - kink) / (10000 - kink);
+        }
+        return std::cmp::min(apr, MAX_APR_BP as u64) as u32;
+    }
+
+    fn assert_valid(&self) {
+        assert!(self.kink_utilization_bp > 0 && self.kink_utilization_bp < 10000, "kink_utilization_bp must be between 0 and 10000");
+        let apr_at_kink = self.base_apr_bp as u64 + self.slope1_bp as u64;
+        assert!(apr_at_kink <= MAX_APR_BP as u64, "APR at the kink must be <= {}", MAX_APR_BP);
+        assert!(apr_at_kink + self.slope2_bp as u64 <= MAX_APR_BP as u64, "APR at 100% utilization must be <= {}", MAX_APR_BP);
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PegControllerRate {
+    pub min_apr_bp: u32,
+    pub max_apr_bp: u32,
+    /// APR change per epoch when off-peg
+    pub step_bp: u32,
+    /// USDNEAR market price deviation from $1 considered on-peg
+    pub deadband_bp: u32,
+    /// USDNEAR converted in an epoch, basis points of total_usdnear, considered below-peg. 0 => conversions are not a signal
+    pub conversion_trigger_bp: u32,
+}
+
+impl PegControllerRate {
+    fn clamp(&self, apr_bp: u32) -> u32 {
+        return std::cmp::min(std::cmp::max(apr_bp, self.min_apr_bp), self.max_apr_bp);
+    }
+}
+
+impl InterestRateModel for PegControllerRate {
+    fn apr_basis_points(&self, inputs: &RateInputs) -> u32 {
+        return self.clamp(inputs.peg_controller_apr_bp);
+    }
+
+    fn on_epoch(&self, current_apr_bp: u32, signal: PegSignal) -> u32 {
+        let current_apr_bp = self.clamp(current_apr_bp);
+        return match signal {
+            // higher rate => borrowers repay, USDNEAR supply shrinks
+            PegSignal::BelowPeg => self.clamp(current_apr_bp + self.step_bp),
+            PegSignal::AbovePeg => self.clamp(current_apr_bp.saturating_sub(self.step_bp)),
+            PegSignal::AtPeg => current_apr_bp,
+        };
+    }
+
+    fn assert_valid(&self) {
+        assert!(self.min_apr_bp <= self.max_apr_bp && self.max_apr_bp <= MAX_APR_BP, "invalid APR range");
+        assert!(self.step_bp > 0, "step_bp must be positive");
+        assert!(self.deadband_bp < 10000 && self.conversion_trigger_bp <= 10000, "invalid deadband or conversion trigger");
+    }
+}
+
+/// the selected model and its params
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum RateModel {
+    Fixed,
+    KinkedUtilization(KinkedUtilizationRate),
+    PegController(PegControllerRate),
+}
+
+impl RateModel {
+    pub fn as_model(&self) -> &dyn InterestRateModel {
+        return match self {
+            RateModel::Fixed => &FixedRate,
+            RateModel::KinkedUtilization(model) => model,
+            RateModel::PegController(model) => model,
+        };
+    }
+}
+
+impl UsdNearStableCoin {
+
+    /// debt / borrowing capacity of all the collateral, at the last interest accrual
+    pub(crate) fn utilization_bp(&self) -> u32 {
+        let collateral_usd = (U256::from(self.total_collateral_stnear) * U256::from(self.current_stnear_price) / U256::from(NEAR)).as_u128();
+        let capacity = proportional(collateral_usd, 10000, self.collateral_basis_points as u128);
+        let debt = proportional(self.total_usdnear_shares, self.borrow_index, BORROW_INDEX_ONE);
+        if capacity == 0 {
+            return if debt == 0 { 0 } else { u32::MAX };
+        }
+        return std::cmp::min(proportional(debt, 10000, capacity), u32::MAX as u128) as u32;
+    }
+
+    /// loan APR from the selected model
+    pub(crate) fn current_apr_basis_points(&self) -> u32 {
+        return self.rate_model.as_model().apr_basis_points(&RateInputs {
+            base_apr_bp: self.usdnear_apr_basis_points,
+            utilization_bp: self.utilization_bp(),
+            peg_controller_apr_bp: self.peg_controller_apr_bp,
+        });
+    }
+
+    fn is_usdnear_market_price_fresh(&self) -> bool {
+        return self.usdnear_market_price_timestamp > 0
+            && env::block_timestamp().saturating_sub(self.usdnear_market_price_timestamp) <= self.price_max_age;
+    }
+
+    fn peg_signal(&self, model: &PegControllerRate) -> PegSignal {
+        let converted_bp = if self.total_usdnear == 0 { 0 } else { proportional(self.epoch_converted_usdnear, 10000, self.total_usdnear) };
+        if model.conversion_trigger_bp > 0 && converted_bp >= model.conversion_trigger_bp as u128 {
+            return PegSignal::BelowPeg;
+        }
+        if self.is_usdnear_market_price_fresh() {
+            if self.usdnear_market_price < apply_pct(10000 - model.deadband_bp, ONE_USD) {
+                return PegSignal::BelowPeg;
+            }
+            if self.usdnear_market_price > apply_pct(10000 + model.deadband_bp, ONE_USD) {
+                return PegSignal::AbovePeg;
+            }
+        }
+        return PegSignal::AtPeg;
+    }
+
+    /// runs the model's epoch step (peg controller) and resets the epoch conversions. Must not panic
+    pub(crate) fn update_rate_model_on_epoch(&mut self) {
+        let signal = match &self.rate_model {
+            RateModel::PegController(model) => self.peg_signal(model),
+            _ => PegSignal::AtPeg,
+        };
+        let new_apr_bp = self.rate_model.as_model().on_epoch(self.peg_controller_apr_bp, signal);
+        if new_apr_bp != self.peg_controller_apr_bp {
+            // accrue at the previous APR
+            self.accrue_borrow_interest();
+            events::InterestRateUpdate {
+                signal: &format!("{:?}", signal),
+                old_apr_bp: self.peg_controller_apr_bp,
+                new_apr_bp,
+            }.emit();
+            self.peg_controller_apr_bp = new_apr_bp;
+        }
+        self.epoch_converted_usdnear = 0;
+    }
+
+    /// selects the interest rate model (governance)
+    pub(crate) fn internal_set_rate_model(&mut self, rate_model: &RateModel) {
+        rate_model.as_model().assert_valid();
+        // accrue at the previous APR
+        self.accrue_borrow_interest();
+        // the peg controller starts from the current APR
+        self.peg_controller_apr_bp = self.current_apr_basis_points();
+        self.rate_model = rate_model.clone();
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "rate_model",
+            value: near_sdk::serde_json::to_string(rate_model).unwrap(),
+        }.emit();
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Operator's method.
+    /// Reports the USDNEAR market price in USD (24 decimals), the peg controller signal
+    pub fn report_usdnear_market_price(&mut self, usdnear_price_usd: U128String) {
+        self.assert_role(Role::Operator);
+        assert!(usdnear_price_usd.0 > 0, "price must be positive");
+        self.usdnear_market_price = usdnear_price_usd.0;
+        self.usdnear_market_price_timestamp = env::block_timestamp();
+    }
+
+    /// Returns the interest rate model, the current APR and the model inputs
+    pub fn get_interest_rate_info(&self) -> InterestRateInfoJSON {
+        return InterestRateInfoJSON {
+            rate_model: self.rate_model.clone(),
+            current_apr_basis_points: self.current_apr_basis_points(),
+            usdnear_apr_basis_points: self.usdnear_apr_basis_points,
+            utilization_bp: self.utilization_bp(),
+            peg_controller_apr_bp: self.peg_controller_apr_bp,
+            usdnear_market_price: self.usdnear_market_price.into(),
+            usdnear_market_price_timestamp: self.usdnear_market_price_timestamp.into(),
+            epoch_converted_usdnear: self.epoch_converted_usdnear.into(),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    fn kinked(base_apr_bp: u32, slope1_bp: u32, slope2_bp: u32) -> KinkedUtilizationRate {
+        return KinkedUtilizationRate { base_apr_bp, slope1_bp, kink_utilization_bp: 8000, slope2_bp };
+    }
+
+    #[test]
+    fn kinked_model_follows_both_slopes() {
+        let model = kinked(200, 800, 4000);
+        model.assert_valid();
+        let apr_at = |utilization_bp: u32| model.apr_basis_points(&RateInputs { base_apr_bp: 0, utilization_bp, peg_controller_apr_bp: 0 });
+        assert_eq!(apr_at(0), 200);
+        assert_eq!(apr_at(4000), 600);
+        assert_eq!(apr_at(8000), 1000);
+        assert_eq!(apr_at(9000), 3000);
+        assert_eq!(apr_at(10000), 5000);
+    }
+
+    #[test]
+    #[should_panic(expected = "APR at 100% utilization must be <=")]
+    fn kinked_model_bounds_slope2() {
+        kinked(200, 800, u32::MAX).assert_valid();
+    }
+
+    #[test]
+    #[should_panic(expected = "APR at the kink must be <=")]
+    fn kinked_model_bounds_the_kink_apr_without_overflow() {
+        kinked(u32::MAX, u32::MAX, 0).assert_valid();
+    }
+
+    #[test]
+    fn kinked_model_charges_by_utilization() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        // only the collateral needed at 200% is locked, so a single loan is at 100% utilization,
+        // above the kink: 2% + 8% + 40% * (99.99% - 80%) / 20%
+        open_loan(&mut contract, ALICE, 100 * NEAR, 250 * NEAR, t0);
+        contract.internal_set_rate_model(&RateModel::KinkedUtilization(kinked(200, 800, 4000)));
+        let info = contract.get_interest_rate_info();
+        assert_eq!(info.utilization_bp, 9999);
+        assert_eq!(info.current_apr_basis_points, 4998);
+
+        // one year at 49.98% APR
+        set_context(ALICE, 0, t0 + NANOS_PER_YEAR as Timestamp);
+        assert_close(contract.get_account_interest(ALICE.into()).outstanding_loans_usdnear.0, 37495 * NEAR / 100, NEAR / 100);
+    }
+
+    /// runs an epoch with 1 stNEAR of rewards at `block_timestamp`
+    fn rewarded_epoch(contract: &mut UsdNearStableCoin, block_timestamp: Timestamp) {
+        set_price(contract, INITIAL_PRICE, block_timestamp);
+        let total_balance = contract.total_collateral_stnear + NEAR;
+        run_epoch(contract, total_balance, block_timestamp);
+    }
+
+    fn report_usdnear_price(contract: &mut UsdNearStableCoin, usdnear_price_usd: u128, block_timestamp: Timestamp) {
+        set_context(OPERATOR, 0, block_timestamp);
+        contract.report_usdnear_market_price(usdnear_price_usd.into());
+    }
+
+    #[test]
+    fn peg_controller_steps_the_apr_every_epoch() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 100 * NEAR, t0);
+        contract.internal_set_rate_model(&RateModel::PegController(PegControllerRate {
+            min_apr_bp: 100,
+            max_apr_bp: 400,
+            step_bp: 100,
+            deadband_bp: 50,
+            conversion_trigger_bp: 0,
+        }));
+        // starts from the fixed 2.5% APR
+        assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, 250);
+
+        // below peg: the APR steps up, up to max_apr_bp
+        let mut now = t0;
+        for expected_apr_bp in [350, 400, 400] {
+            now += EPOCH_DURATION;
+            report_usdnear_price(&mut contract, 98 * ONE_USD / 100, now);
+            rewarded_epoch(&mut contract, now);
+            assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, expected_apr_bp);
+        }
+
+        // inside the deadband the APR doesn't move
+        now += EPOCH_DURATION;
+        report_usdnear_price(&mut contract, 10040 * ONE_USD / 10000, now);
+        rewarded_epoch(&mut contract, now);
+        assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, 400);
+
+        // above peg: the APR steps down, down to min_apr_bp
+        for expected_apr_bp in [300, 200, 100, 100] {
+            now += EPOCH_DURATION;
+            report_usdnear_price(&mut contract, 102 * ONE_USD / 100, now);
+            rewarded_epoch(&mut contract, now);
+            assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, expected_apr_bp);
+        }
+
+        // a stale market price is not a signal
+        now += EPOCH_DURATION;
+        rewarded_epoch(&mut contract, now);
+        assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, 100);
+    }
+
+    #[test]
+    fn conversions_push_the_peg_controller_up() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 100 * NEAR, 100 * NEAR, t0);
+        contract.internal_set_rate_model(&RateModel::PegController(PegControllerRate {
+            min_apr_bp: 100,
+            max_apr_bp: 1000,
+            step_bp: 100,
+            deadband_bp: 50,
+            conversion_trigger_bp: 1000,
+        }));
+
+        // 10% of the USDNEAR converted in the epoch, with the market price on peg
+        let t1 = t0 + EPOCH_DURATION;
+        report_usdnear_price(&mut contract, ONE_USD, t1);
+        contract.epoch_converted_usdnear = contract.total_usdnear / 10;
+        rewarded_epoch(&mut contract, t1);
+        assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, 350);
+        assert_eq!(contract.epoch_converted_usdnear, 0);
+
+        // no conversions in the next epoch
+        let t2 = t1 + EPOCH_DURATION;
+        report_usdnear_price(&mut contract, ONE_USD, t2);
+        rewarded_epoch(&mut contract, t2);
+        assert_eq!(contract.get_interest_rate_info().current_apr_basis_points, 350);
+    }
+}
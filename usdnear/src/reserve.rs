@@ -0,0 +1,311 @@
+//
+// PROTOCOL RESERVE - surplus buffer / insurance reserve
+// stNEAR held as free shares of an internal account (so it keeps earning staking rewards).
+// Inflows: `interest_share_bp` of the epoch interest (after the STBL stakers share), `liquidation_penalty_share_bp` of the
+// liquidation fee, the collateral seized from underwater positions and anyone's `fund_reserve`.
+// It's used first to cover bad debt (see bad_debt.rs) and conversions when the collateral pool is short.
+// When the reserve is worth more than `target_usd`, the excess overflows to the treasury as free stNEAR.
+// The last MAX_RESERVE_RECORDS movements are kept on-chain in a ring buffer
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+pub const RESERVE_INTERNAL_ACCOUNT: &str = "..RESERVE..";
+/// ring buffer capacity
+pub const MAX_RESERVE_RECORDS: u64 = 100;
+/// default share of the epoch interest for the reserve: 10%
+pub const DEFAULT_RESERVE_INTEREST_SHARE_BP: u32 = 10 * PERCENT_BP;
+/// default share of the liquidation fee for the reserve: 20%
+pub const DEFAULT_RESERVE_LIQUIDATION_PENALTY_SHARE_BP: u32 = 20 * PERCENT_BP;
+/// default target size: USD 1M
+pub const DEFAULT_RESERVE_TARGET_USD: u128 = 1_000_000 * NEAR;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReserveConfig {
+    /// share of the epoch interest (after the STBL stakers share)
+    pub interest_share_bp: u32,
+    /// share of the liquidation fee, taken from the liquidator's stNEAR
+    pub liquidation_penalty_share_bp: u32,
+    /// above this value (at the current stNEAR price) the reserve overflows to the treasury
+    pub target_usd: U128String,
+}
+
+impl Default for ReserveConfig {
+    fn default() -> Self {
+        Self {
+            interest_share_bp: DEFAULT_RESERVE_INTEREST_SHARE_BP,
+            liquidation_penalty_share_bp: DEFAULT_RESERVE_LIQUIDATION_PENALTY_SHARE_BP,
+            target_usd: DEFAULT_RESERVE_TARGET_USD.into(),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ReserveRecord {
+    pub timestamp: Timestamp,
+    /// fund, interest, liquidation_penalty, seized_collateral, bad_debt_cover, conversion_cover, overflow
+    pub action: String,
+    /// true => inflow
+    pub inflow: bool,
+    pub stnear_amount: u128,
+    pub reserve_stnear_after: u128,
+}
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn reserve_stnear(&self) -> u128 {
+        let reserve_acc = self.internal_get_account(&String::from(RESERVE_INTERNAL_ACCOUNT));
+        return reserve_acc.free_stnear(self);
+    }
+
+    /// adds a record to the reserve ring buffer and emits the event
+    fn record_reserve_movement(&mut self, account_id: &str, action: &str, inflow: bool, stnear: u128) {
+        let record = ReserveRecord {
+            timestamp: env::block_timestamp(),
+            action: action.into(),
+            inflow,
+            stnear_amount: stnear,
+            reserve_stnear_after: self.reserve_stnear(),
+        };
+        if self.reserve_records.len() < MAX_RESERVE_RECORDS {
+            self.reserve_records.push(&record);
+        }
+        else {
+            self.reserve_records.replace(self.reserve_records_next, &record);
+        }
+        self.reserve_records_next = (self.reserve_records_next + 1) % MAX_RESERVE_RECORDS;
+        events::ReserveUpdate { account_id, action, stnear_amount: stnear.into() }.emit();
+    }
+
+    /// adds stNEAR to the reserve. The stNEAR must have been removed from its pool before. Must not panic
+    pub(crate) fn add_to_reserve(&mut self, account_id: &str, action: &str, stnear: u128) {
+        if stnear == 0 {
+            return;
+        }
+        let reserve_id = String::from(RESERVE_INTERNAL_ACCOUNT);
+        let mut reserve_acc = self.internal_get_account(&reserve_id);
+        reserve_acc.add_free_amount_preserve_share_price(stnear, self);
+        self.internal_update_account(&reserve_id, &reserve_acc);
+        self.total_reserve_inflows_stnear += stnear;
+        self.record_reserve_movement(account_id, action, true, stnear);
+    }
+
+    /// moves reserve stNEAR to the collateral pool, increasing every borrower's locked stNEAR. Must not panic
+    pub(crate) fn reserve_to_collateral_pool(&mut self, action: &str, stnear: u128) {
+        if stnear == 0 {
+            return;
+        }
+        let reserve_id = String::from(RESERVE_INTERNAL_ACCOUNT);
+        let mut reserve_acc = self.internal_get_account(&reserve_id);
+        reserve_acc.remove_free_amount_preserve_share_price(stnear, self);
+        self.internal_update_account(&reserve_id, &reserve_acc);
+        // utilization changes, accrue at the previous APR
+        self.accrue_borrow_interest();
+        // STBL liquidity mining emits at the share prices before the cover
+        self.update_mining_accumulators();
+        // collateral shares remain the same, so the stNEAR is distributed proportionally to all locked collateral
+        self.total_collateral_stnear += stnear;
+        self.record_reserve_movement(&env::current_account_id(), action, false, stnear);
+    }
+
+    /// the reserve share of the epoch interest, overflowing to the treasury above the target. Returns the stNEAR amount. Must not panic
+    pub(crate) fn pay_reserve_interest_share(&mut self, interest_stnear: u128) -> u128 {
+        let amount = apply_pct(self.reserve_config.interest_share_bp, interest_stnear);
+        self.add_to_reserve(&env::current_account_id(), "interest", amount);
+        self.reserve_overflow_to_treasury();
+        return amount;
+    }
+
+    /// covers a conversion when the collateral pool is short `shortfall_stnear`. Must not panic
+    pub(crate) fn reserve_cover_conversion(&mut self, shortfall_stnear: u128) {
+        let covered = std::cmp::min(shortfall_stnear, self.reserve_stnear());
+        self.reserve_to_collateral_pool("conversion_cover", covered);
+    }
+
+    /// moves the reserve value above target_usd to the treasury as free stNEAR. Must not panic
+    pub(crate) fn reserve_overflow_to_treasury(&mut self) {
+        let treasury_id = self.treasury_account_id.clone();
+        // the treasury is always registered (see fees.rs), never credit an account not paying its storage
+        if !self.storage_deposits.contains_key(&treasury_id) {
+            log!("reserve overflow skipped, the treasury {} is not registered", treasury_id);
+            return;
+        }
+        let target_stnear = (U256::from(self.reserve_config.target_usd.0) * U256::from(NEAR) / U256::from(self.current_stnear_price)).as_u128();
+        let reserve_stnear = self.reserve_stnear();
+        if reserve_stnear <= target_stnear {
+            return;
+        }
+        let excess = reserve_stnear - target_stnear;
+        let reserve_id = String::from(RESERVE_INTERNAL_ACCOUNT);
+        let mut reserve_acc = self.internal_get_account(&reserve_id);
+        let num_shares = std::cmp::min(self.free_shares_from_amount(excess), reserve_acc.free_shares);
+        reserve_acc.free_shares -= num_shares;
+        self.internal_update_account(&reserve_id, &reserve_acc);
+        let mut treasury_acc = self.internal_get_account(&treasury_id);
+        treasury_acc.free_shares += num_shares;
+        self.internal_update_account(&treasury_id, &treasury_acc);
+        self.update_mining_account(&treasury_id);
+        self.total_reserve_overflow_stnear += excess;
+        self.record_reserve_movement(&treasury_id, "overflow", false, excess);
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Moves `amount` of the caller's free stNEAR to the protocol reserve
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn fund_reserve(&mut self, amount: U128String) {
+        self.assert_one_yocto_for("fund_reserve");
+        assert!(amount.0 > 0, "amount must be positive");
+        let account_id = env::predecessor_account_id();
+        let mut acc = self.internal_get_account(&account_id);
+        let free_stnear = acc.free_stnear(self);
+        assert!(free_stnear >= amount.0, "Not enough free stNEAR. You have only stNEAR {} free", free_stnear);
+        acc.remove_free_amount_preserve_share_price(amount.0, self);
+        self.internal_update_account(&account_id, &acc);
+        self.update_mining_account(&account_id);
+        self.add_to_reserve(&account_id, "fund", amount.0);
+    }
+
+    /// Owner's method.
+    /// Sets the reserve shares of interest & liquidation fees and the target size
+    pub fn set_reserve_config(&mut self, config: ReserveConfig) {
+        self.assert_owner_calling();
+        assert!(config.interest_share_bp <= 10000 && config.liquidation_penalty_share_bp <= 10000, "shares must be <= 10000");
+        self.reserve_config = config;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "reserve_config",
+            value: format!("interest_share_bp:{} liquidation_penalty_share_bp:{} target_usd:{}",
+                self.reserve_config.interest_share_bp, self.reserve_config.liquidation_penalty_share_bp, self.reserve_config.target_usd.0),
+        }.emit();
+    }
+
+    /// Returns the stNEAR in the protocol reserve
+    pub fn get_reserve_stnear(&self) -> U128String {
+        return self.reserve_stnear().into();
+    }
+
+    /// Returns the reserve size, config and totals
+    pub fn get_reserve_info(&self) -> ReserveInfoJSON {
+        let reserve_stnear = self.reserve_stnear();
+        return ReserveInfoJSON {
+            reserve_stnear: reserve_stnear.into(),
+            reserve_usd: (U256::from(reserve_stnear) * U256::from(self.current_stnear_price) / U256::from(NEAR)).as_u128().into(),
+            config: self.reserve_config.clone(),
+            total_inflows_stnear: self.total_reserve_inflows_stnear.into(),
+            total_overflow_stnear: self.total_reserve_overflow_stnear.into(),
+        };
+    }
+
+    /// Returns the last reserve movements, oldest first
+    pub fn get_reserve_records(&self) -> Vec<ReserveRecordJSON> {
+        let mut records = self.reserve_records.to_vec();
+        if records.len() as u64 == MAX_RESERVE_RECORDS {
+            //the ring buffer is full, reserve_records_next is the oldest
+            records.rotate_left(self.reserve_records_next as usize);
+        }
+        return records.iter()
+            .map(|r| ReserveRecordJSON {
+                timestamp: r.timestamp.into(),
+                action: r.action.clone(),
+                inflow: r.inflow,
+                stnear_amount: r.stnear_amount.into(),
+                reserve_stnear_after: r.reserve_stnear_after.into(),
+            })
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn reserve_above_target_overflows_to_the_treasury() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        deposit_stnear(&mut contract, ALICE, 100 * NEAR, t0);
+        set_context(ALICE, 1, t0);
+        contract.fund_reserve((100 * NEAR).into());
+        assert_eq!(contract.get_reserve_stnear().0, 100 * NEAR);
+
+        // target USD 500 => stNEAR 50 at USD 10
+        set_context(OWNER, 0, t0);
+        contract.set_reserve_config(ReserveConfig { target_usd: (500 * NEAR).into(), ..ReserveConfig::default() });
+
+        let t1 = t0 + 30 * EPOCH_DURATION;
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear + 10 * NEAR;
+        run_epoch(&mut contract, total_balance, t1);
+
+        assert_close(contract.get_reserve_stnear().0, 50 * NEAR, 1000);
+        let info = contract.get_reserve_info();
+        assert!(info.total_overflow_stnear.0 > 50 * NEAR);
+        let treasury_stnear = contract.get_account_info(TREASURY.into()).stnear.0;
+        assert_close(treasury_stnear, info.total_overflow_stnear.0 + contract.total_treasury_fees_stnear, 1000);
+        // the treasury mining stake follows its stNEAR shares
+        assert_eq!(contract.mining_accounts.get(&TREASURY.into()).unwrap().free_stake, contract.internal_get_account(&TREASURY.into()).free_shares);
+
+        let records = contract.get_reserve_records();
+        let last = records.last().unwrap();
+        assert_eq!(last.action, "overflow");
+        assert!(!last.inflow);
+    }
+
+    #[test]
+    fn reserve_below_target_keeps_its_interest_share() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        deposit_stnear(&mut contract, ALICE, 10 * NEAR, t0);
+        set_context(ALICE, 1, t0);
+        contract.fund_reserve((10 * NEAR).into());
+        set_context(OWNER, 0, t0);
+        contract.set_reserve_config(ReserveConfig { target_usd: (500 * NEAR).into(), ..ReserveConfig::default() });
+
+        let t1 = t0 + 30 * EPOCH_DURATION;
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear + 10 * NEAR;
+        run_epoch(&mut contract, total_balance, t1);
+
+        let reserve_share = contract.get_epoch_records()[0].reserve_stnear.0;
+        assert!(reserve_share > 0);
+        assert_close(contract.get_reserve_stnear().0, 10 * NEAR + reserve_share, 1000);
+        assert_eq!(contract.get_reserve_info().total_overflow_stnear.0, 0);
+        assert_eq!(contract.get_reserve_records().last().unwrap().action, "interest");
+    }
+
+    #[test]
+    fn reserve_cover_accrues_interest_at_the_previous_utilization() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        deposit_stnear(&mut contract, ALICE, 500 * NEAR, t0);
+        set_context(ALICE, 1, t0);
+        contract.fund_reserve((500 * NEAR).into());
+
+        set_context(OWNER, 0, t0);
+        contract.internal_set_rate_model(&RateModel::KinkedUtilization(KinkedUtilizationRate {
+            base_apr_bp: 0, slope1_bp: 1000, kink_utilization_bp: 8000, slope2_bp: 0,
+        }));
+        let apr_before = contract.current_apr_basis_points();
+
+        let t1 = t0 + 365 * 24 * ONE_HOUR;
+        set_context(CONTRACT, 0, t1);
+        let expected_index = contract.current_borrow_index();
+        contract.reserve_to_collateral_pool("test", 500 * NEAR);
+        // the elapsed year accrued at the APR before the cover lowered utilization
+        assert_eq!(contract.borrow_index_updated_at, t1);
+        assert_eq!(contract.borrow_index, expected_index);
+        assert!(contract.current_apr_basis_points() < apr_before);
+    }
+}
@@ -0,0 +1,162 @@
+//
+// ROLE-BASED ACCESS CONTROL
+// the owner holds every role implicitly and grants/revokes the others
+// - Operator: credited with the operator fees, and a price reporter (see oracle.rs)
+// - Guardian: emergency actions (pause, confirm/reject prices held by the circuit breaker, veto params proposals)
+// - TreasuryManager: protocol funds decisions (forgive_loan, mint_stbl)
+// - ParameterManager: risk & config parameters (propose_contract_params, price feed, TWAP, circuit breaker limits, metadata)
+// Owner only: ownership transfer, params timelock, STBL mint schedule & governance config, operator & treasury accounts, grant/revoke roles, unpause, security policy, price reporters & oracle, add_full_access_key
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Operator,
+    Guardian,
+    TreasuryManager,
+    ParameterManager,
+}
+
+pub const GRANTABLE_ROLES: &[Role] = &[Role::Operator, Role::Guardian, Role::TreasuryManager, Role::ParameterManager];
+
+/// max accounts per role
+pub const MAX_ROLE_MEMBERS: usize = 10;
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Role::Owner => "owner",
+            Role::Operator => "operator",
+            Role::Guardian => "guardian",
+            Role::TreasuryManager => "treasury_manager",
+            Role::ParameterManager => "parameter_manager",
+        };
+    }
+}
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn role_members(&self, role: Role) -> Vec<AccountId> {
+        if role == Role::Owner {
+            return vec![self.owner_account_id.clone()];
+        }
+        return self.role_members.get(&role).unwrap_or_default();
+    }
+
+    /// the owner has every role
+    pub(crate) fn internal_has_role(&self, role: Role, account_id: &AccountId) -> bool {
+        return account_id == &self.owner_account_id || self.role_members(role).contains(account_id);
+    }
+
+    /// Asserts that the method was called by an account with `role` (or the owner)
+    pub(crate) fn assert_role(&self, role: Role) {
+        assert!(self.internal_has_role(role, &env::predecessor_account_id()), "Can only be called by the {} role", role.name());
+    }
+
+    pub(crate) fn internal_grant_role(&mut self, role: Role, account_id: &AccountId) {
+        let mut members = self.role_members(role);
+        if !members.contains(account_id) {
+            assert!(members.len() < MAX_ROLE_MEMBERS, "max {} accounts per role", MAX_ROLE_MEMBERS);
+            members.push(account_id.clone());
+            self.role_members.insert(&role, &members);
+            events::RoleUpdate { updated_by: &env::predecessor_account_id(), role: role.name(), account_id, granted: true }.emit();
+        }
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Owner's method.
+    /// Grants `role` to `account_id`. The owner role can not be granted
+    pub fn grant_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_owner_calling();
+        assert!(GRANTABLE_ROLES.contains(&role), "the {} role can not be granted", role.name());
+        assert!(env::is_valid_account_id(account_id.as_bytes()), "invalid account id {}", account_id);
+        self.internal_grant_role(role, &account_id);
+    }
+
+    /// Owner's method.
+    /// Revokes `role` from `account_id`
+    pub fn revoke_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_owner_calling();
+        assert!(GRANTABLE_ROLES.contains(&role), "the {} role can not be revoked", role.name());
+        let mut members = self.role_members(role);
+        assert!(members.contains(&account_id), "{} does not have the {} role", account_id, role.name());
+        members.retain(|m| m != &account_id);
+        self.role_members.insert(&role, &members);
+        events::RoleUpdate { updated_by: &env::predecessor_account_id(), role: role.name(), account_id: &account_id, granted: false }.emit();
+    }
+
+    /// Returns true if `account_id` has `role` (the owner has every role)
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        return self.internal_has_role(role, &account_id);
+    }
+
+    /// Returns every role and its holders
+    pub fn get_roles(&self) -> Vec<RoleMembersJSON> {
+        let mut roles = vec![Role::Owner];
+        roles.extend_from_slice(GRANTABLE_ROLES);
+        return roles.iter()
+            .map(|role| RoleMembersJSON { role: *role, members: self.role_members(*role) })
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn the_owner_has_every_role() {
+        let contract = new_contract();
+        for role in [Role::Owner, Role::Operator, Role::Guardian, Role::TreasuryManager, Role::ParameterManager].iter() {
+            assert!(contract.has_role(*role, OWNER.into()));
+            assert!(!contract.has_role(*role, ALICE.into()));
+        }
+        assert!(contract.has_role(Role::Operator, OPERATOR.into()));
+        assert!(!contract.has_role(Role::Guardian, OPERATOR.into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the parameter_manager role")]
+    fn revoked_roles_lose_access() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.grant_role(Role::ParameterManager, ALICE.into());
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.set_twap_window(3600.into());
+        assert_eq!(contract.twap_window, ONE_HOUR);
+
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.revoke_role(Role::ParameterManager, ALICE.into());
+        let roles = contract.get_roles();
+        assert!(roles.iter().all(|r| !r.members.contains(&ALICE.into())));
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.set_twap_window(7200.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "the owner role can not be granted")]
+    fn the_owner_role_can_not_be_granted() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.grant_role(Role::Owner, ALICE.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn only_the_owner_grants_roles() {
+        let mut contract = new_contract();
+        set_context(OPERATOR, 0, NANOS_PER_SECOND);
+        contract.grant_role(Role::Guardian, OPERATOR.into());
+    }
+}
@@ -0,0 +1,158 @@
+//
+// SECURITY POLICY: 1 yoctoNEAR deposit on value-moving methods
+//
+// Function-call access keys can not attach a deposit, so requiring 1 yoctoNEAR
+// forces the user to sign with a full-access key (i.e. confirm in the wallet).
+// A leaked function-call key can not move USDNEAR or stNEAR.
+//
+// Every value-moving method must be listed in ONE_YOCTO_METHODS and call `assert_one_yocto_for`
+// The owner can exempt contract-specific methods (e.g. if a wallet can't attach 1 yocto),
+// but NEP-141/NEP-145 methods, and the methods moving stNEAR out or minting USDNEAR, can never be exempted.
+// Every change is logged as an event
+//
+
+use crate::*;
+use crate::funtoken::assert_one_yocto;
+use near_sdk::near_bindgen;
+
+/// methods requiring a 1 yoctoNEAR attached deposit (unless exempted by the owner)
+pub const ONE_YOCTO_METHODS: &[&str] = &[
+    "ft_transfer",
+    "ft_transfer_call",
+    "storage_withdraw",
+    "storage_unregister",
+    "stbl_transfer",
+    "stake_stbl",
+    "unstake_stbl",
+    "withdraw_stnear",
+    "take_loan",
+    "repay_loan",
+    "liquidate",
+    "convert_usdnear",
+    "deposit_to_stability_pool",
+    "withdraw_from_stability_pool",
+    "claim_stability_pool_gains",
+    "claim_staking_rewards",
+    "claim_stbl",
+    "withdraw_unstaked_stbl",
+    "start_auction",
+    "bid_auction",
+    "fund_reserve",
+];
+
+/// methods where 1 yoctoNEAR is required by the standard, or that move stNEAR or mint USDNEAR: can not be exempted
+pub const ONE_YOCTO_MANDATORY_METHODS: &[&str] = &[
+    "ft_transfer",
+    "ft_transfer_call",
+    "storage_withdraw",
+    "storage_unregister",
+    "stbl_transfer",
+    "withdraw_stnear",
+    "take_loan",
+    "liquidate",
+    "convert_usdnear",
+];
+
+impl UsdNearStableCoin {
+    /// Asserts 1 yoctoNEAR was attached, unless the owner exempted `method`
+    pub(crate) fn assert_one_yocto_for(&self, method: &str) {
+        if !self.one_yocto_exempt_methods.iter().any(|m| m == method) {
+            assert_one_yocto();
+        }
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Returns the per-method 1 yoctoNEAR security policy
+    pub fn get_security_policy(&self) -> Vec<MethodSecurityPolicyJSON> {
+        return ONE_YOCTO_METHODS
+            .iter()
+            .map(|method| MethodSecurityPolicyJSON {
+                method: method.to_string(),
+                requires_one_yocto: !self.one_yocto_exempt_methods.iter().any(|m| m == method),
+                mandatory: ONE_YOCTO_MANDATORY_METHODS.contains(method),
+            })
+            .collect();
+    }
+
+    /// Owner's method.
+    /// Requires or exempts 1 yoctoNEAR deposit for a contract-specific method
+    pub fn set_one_yocto_required(&mut self, method: String, required: bool) {
+        self.assert_owner_calling();
+        assert!(ONE_YOCTO_METHODS.contains(&method.as_str()), "{} is not a value-moving method", method);
+        assert!(required || !ONE_YOCTO_MANDATORY_METHODS.contains(&method.as_str()), "1 yoctoNEAR is mandatory for {}", method);
+        self.one_yocto_exempt_methods.retain(|m| m != &method);
+        if !required {
+            self.one_yocto_exempt_methods.push(method.clone());
+        }
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: &format!("one_yocto_required.{}", method),
+            value: required.to_string(),
+        }.emit();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    #[should_panic(expected = "fn requires to attach 1 yoctoNEAR")]
+    fn ft_transfer_requires_one_yocto() {
+        let mut contract = new_contract();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        register(&mut contract, BOB, NANOS_PER_SECOND);
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.ft_transfer(BOB.into(), NEAR.into(), None);
+    }
+
+    #[test]
+    fn exempted_methods_work_with_function_call_keys() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_one_yocto_required("repay_loan".into(), false);
+        let policy = contract.get_security_policy();
+        let repay_loan = policy.iter().find(|p| p.method == "repay_loan").unwrap();
+        assert!(!repay_loan.requires_one_yocto && !repay_loan.mandatory);
+
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.repay_loan((100 * NEAR).into());
+        assert_eq!(contract.ft_balance_of(ALICE.into()).0, 350 * NEAR);
+
+        // required again
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_one_yocto_required("repay_loan".into(), true);
+        assert!(contract.get_security_policy().iter().find(|p| p.method == "repay_loan").unwrap().requires_one_yocto);
+    }
+
+    #[test]
+    #[should_panic(expected = "fn requires to attach 1 yoctoNEAR")]
+    fn repay_loan_requires_one_yocto() {
+        let mut contract = new_contract();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        set_context(ALICE, 0, NANOS_PER_SECOND);
+        contract.repay_loan((100 * NEAR).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "1 yoctoNEAR is mandatory for ft_transfer")]
+    fn standard_methods_can_not_be_exempted() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_one_yocto_required("ft_transfer".into(), false);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 yoctoNEAR is mandatory for withdraw_stnear")]
+    fn stnear_withdrawals_can_not_be_exempted() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_one_yocto_required("withdraw_stnear".into(), false);
+    }
+}
@@ -0,0 +1,370 @@
+//
+// STABILITY POOL (Liquity-style)
+// USDNEAR holders deposit USDNEAR in the pool. `liquidate_with_stability_pool` (open to anyone) repays the debt of an
+// undercollateralized account burning pool USDNEAR, and the seized stNEAR collateral is distributed pro-rata to depositors.
+// Every deposit shrinks by the same fraction and earns the same stNEAR per USDNEAR, tracked with the product/sum accumulators:
+// - P: product of (1 - debt burned / pool deposits) of every liquidation. compounded deposit = initial * P / P_snapshot
+// - S[epoch][scale]: sum of (free shares gained / pool deposits) * P. gains = initial * (S - S_snapshot) / P_snapshot
+// when P gets too small it's multiplied by SCALE_FACTOR and `scale` increases. When the pool is emptied `epoch` increases
+// and every deposit from the previous epoch is 0.
+// The seized stNEAR is held as free shares of an internal account (so it keeps earning staking rewards) until claimed
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+pub const STABILITY_POOL_INTERNAL_ACCOUNT: &str = "..STABILITY_POOL..";
+/// P & S precision
+pub const SP_DECIMAL_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// P is rescaled when it drops below this
+pub const SP_SCALE_FACTOR: u128 = 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct StabilityDeposit {
+    /// USDNEAR deposited at the last snapshot
+    pub initial_value: u128,
+    pub snapshot_p: u128,
+    pub snapshot_s: u128,
+    pub snapshot_scale: u64,
+    pub snapshot_epoch: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StabilityPool {
+    /// USDNEAR in the pool
+    pub total_deposits: u128,
+    pub product_p: u128,
+    pub current_scale: u64,
+    pub current_epoch: u64,
+    /// totals since deployment
+    pub total_usdnear_burned: u128,
+    pub total_stnear_seized: u128,
+}
+
+impl Default for StabilityPool {
+    fn default() -> Self {
+        Self {
+            total_deposits: 0,
+            product_p: SP_DECIMAL_PRECISION,
+            current_scale: 0,
+            current_epoch: 0,
+            total_usdnear_burned: 0,
+            total_stnear_seized: 0,
+        }
+    }
+}
+
+impl UsdNearStableCoin {
+
+    fn sp_sum(&self, epoch: u64, scale: u64) -> u128 {
+        return self.stability_pool_sums.get(&(epoch, scale)).unwrap_or_default();
+    }
+
+    /// current value of a deposit, after the liquidations since its snapshot
+    pub(crate) fn compounded_stability_deposit(&self, deposit: &StabilityDeposit) -> u128 {
+        let pool = &self.stability_pool;
+        if deposit.initial_value == 0 || deposit.snapshot_epoch < pool.current_epoch {
+            return 0;
+        }
+        let compounded = match pool.current_scale - deposit.snapshot_scale {
+            0 => proportional(deposit.initial_value, pool.product_p, deposit.snapshot_p),
+            1 => proportional(deposit.initial_value, pool.product_p, deposit.snapshot_p) / SP_SCALE_FACTOR,
+            _ => 0,
+        };
+        // ignore dust
+        if compounded < deposit.initial_value / SP_SCALE_FACTOR {
+            return 0;
+        }
+        return compounded;
+    }
+
+    /// free shares earned by a deposit since its snapshot
+    pub(crate) fn stability_deposit_gain_shares(&self, deposit: &StabilityDeposit) -> u128 {
+        if deposit.initial_value == 0 {
+            return 0;
+        }
+        // gains after the snapshot scale+1 are negligible
+        let first_portion = self.sp_sum(deposit.snapshot_epoch, deposit.snapshot_scale) - deposit.snapshot_s;
+        let second_portion = self.sp_sum(deposit.snapshot_epoch, deposit.snapshot_scale + 1) / SP_SCALE_FACTOR;
+        return (U256::from(deposit.initial_value) * U256::from(first_portion + second_portion)
+            / U256::from(deposit.snapshot_p) / U256::from(SP_DECIMAL_PRECISION)).as_u128();
+    }
+
+    /// moves the deposit gains to the depositor's free stNEAR and takes a new snapshot with `new_value`.
+    /// Returns the stNEAR amount of the gains
+    fn update_stability_deposit(&mut self, account_id: &AccountId, new_value: u128) -> u128 {
+        let deposit = self.stability_deposits.get(account_id).unwrap_or_default();
+        let pool_id = String::from(STABILITY_POOL_INTERNAL_ACCOUNT);
+        let mut pool_acc = self.internal_get_account(&pool_id);
+        let gain_shares = std::cmp::min(self.stability_deposit_gain_shares(&deposit), pool_acc.free_shares);
+        let gain_stnear = self.amount_from_free_shares(gain_shares);
+        if gain_shares > 0 {
+            pool_acc.free_shares -= gain_shares;
+            self.internal_update_account(&pool_id, &pool_acc);
+            let mut acc = self.internal_get_account(account_id);
+            acc.free_shares += gain_shares;
+            self.internal_update_account(account_id, &acc);
+            self.update_mining_account(account_id);
+        }
+        if new_value == 0 {
+            self.stability_deposits.remove(account_id);
+        }
+        else {
+            let pool = &self.stability_pool;
+            self.stability_deposits.insert(account_id, &StabilityDeposit {
+                initial_value: new_value,
+                snapshot_p: pool.product_p,
+                snapshot_s: self.sp_sum(pool.current_epoch, pool.current_scale),
+                snapshot_scale: pool.current_scale,
+                snapshot_epoch: pool.current_epoch,
+            });
+        }
+        return gain_stnear;
+    }
+
+    /// burns `debt` pool USDNEAR and distributes `gain_shares` free shares to depositors, updating P and S
+    fn offset_with_stability_pool(&mut self, debt: u128, gain_shares: u128) {
+        let total_deposits = self.stability_pool.total_deposits;
+        assert!(debt > 0 && debt <= total_deposits, "invalid stability pool offset");
+        let epoch = self.stability_pool.current_epoch;
+        let scale = self.stability_pool.current_scale;
+        let p = self.stability_pool.product_p;
+
+        // shares per unit deposited rounded down, so gains are never over-distributed
+        let gain_per_unit = proportional(gain_shares, SP_DECIMAL_PRECISION, total_deposits);
+        // fits in u128: the stNEAR seized per USDNEAR burned is bounded by min_collateral_basis_points / stNEAR price
+        let marginal_sum = (U256::from(gain_per_unit) * U256::from(p)).as_u128();
+        self.stability_pool_sums.insert(&(epoch, scale), &(self.sp_sum(epoch, scale) + marginal_sum));
+
+        // loss per unit deposited rounded up, so compounded deposits never exceed the pool
+        let loss_per_unit = proportional(debt, SP_DECIMAL_PRECISION, total_deposits) + 1;
+        if debt == total_deposits || loss_per_unit >= SP_DECIMAL_PRECISION {
+            // pool emptied, start a new epoch
+            self.stability_pool.current_epoch += 1;
+            self.stability_pool.current_scale = 0;
+            self.stability_pool.product_p = SP_DECIMAL_PRECISION;
+        }
+        else {
+            let product_factor = SP_DECIMAL_PRECISION - loss_per_unit;
+            let mut new_p = proportional(p, product_factor, SP_DECIMAL_PRECISION);
+            if new_p < SP_SCALE_FACTOR {
+                new_p = proportional(p, product_factor * SP_SCALE_FACTOR, SP_DECIMAL_PRECISION);
+                self.stability_pool.current_scale += 1;
+            }
+            assert!(new_p > 0, "stability pool P became 0");
+            self.stability_pool.product_p = new_p;
+        }
+        self.stability_pool.total_deposits -= debt;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Deposits `amount` USDNEAR from the caller's balance in the stability pool. Pays the pending stNEAR gains
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn deposit_to_stability_pool(&mut self, amount: U128String) {
+        self.assert_one_yocto_for("deposit_to_stability_pool");
+        self.assert_not_paused("deposits");
+        assert!(amount.0 > 0, "amount must be positive");
+        let account_id = env::predecessor_account_id();
+        self.assert_registered(&account_id);
+        let usdnear_balance = self.get_usdnear_balance(&account_id);
+        assert!(usdnear_balance >= amount.0, "Not enough balance, you only have USDNEAR {}", usdnear_balance);
+        self.set_usdnear_balance(&account_id, usdnear_balance - amount.0);
+
+        let deposit = self.stability_deposits.get(&account_id).unwrap_or_default();
+        let compounded = self.compounded_stability_deposit(&deposit);
+        let gain_stnear = self.update_stability_deposit(&account_id, compounded + amount.0);
+        self.stability_pool.total_deposits += amount.0;
+        events::StabilityPoolUpdate { account_id: &account_id, action: "deposit", usdnear_amount: amount, stnear_gain: gain_stnear.into() }.emit();
+    }
+
+    /// Withdraws up to `amount` USDNEAR from the caller's stability pool deposit. Pays the pending stNEAR gains.
+    /// Returns the USDNEAR withdrawn
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn withdraw_from_stability_pool(&mut self, amount: U128String) -> U128String {
+        self.assert_one_yocto_for("withdraw_from_stability_pool");
+        self.assert_not_paused("withdrawals");
+        let account_id = env::predecessor_account_id();
+        let deposit = self.stability_deposits.get(&account_id).expect("no stability pool deposit");
+        let compounded = self.compounded_stability_deposit(&deposit);
+        let to_withdraw = std::cmp::min(amount.0, std::cmp::min(compounded, self.stability_pool.total_deposits));
+        let gain_stnear = self.update_stability_deposit(&account_id, compounded - to_withdraw);
+        self.stability_pool.total_deposits -= to_withdraw;
+        let usdnear_balance = self.get_usdnear_balance(&account_id);
+        self.set_usdnear_balance(&account_id, usdnear_balance + to_withdraw);
+        events::StabilityPoolUpdate { account_id: &account_id, action: "withdraw", usdnear_amount: to_withdraw.into(), stnear_gain: gain_stnear.into() }.emit();
+        return to_withdraw.into();
+    }
+
+    /// Moves the caller's stNEAR gains from liquidations to the caller's free stNEAR. Returns the stNEAR amount
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn claim_stability_pool_gains(&mut self) -> U128String {
+        self.assert_one_yocto_for("claim_stability_pool_gains");
+        let account_id = env::predecessor_account_id();
+        let deposit = self.stability_deposits.get(&account_id).expect("no stability pool deposit");
+        let compounded = self.compounded_stability_deposit(&deposit);
+        let gain_stnear = self.update_stability_deposit(&account_id, compounded);
+        assert!(gain_stnear > 0, "no stability pool gains to claim");
+        events::StabilityPoolUpdate { account_id: &account_id, action: "claim", usdnear_amount: 0.into(), stnear_gain: gain_stnear.into() }.emit();
+        return gain_stnear.into();
+    }
+
+    /// liquidates an account below min_collateral_basis_points repaying its debt with stability pool USDNEAR,
+    /// the seized stNEAR goes to the pool depositors. Same amounts as `liquidate` (see liquidation.rs). Open to anyone
+    pub fn liquidate_with_stability_pool(&mut self, loan_account_id: AccountId) {
+        self.assert_liquidation_mode(LiquidationMode::FixedDiscount);
+        self.assert_not_paused("liquidations");
+        self.assert_not_busy();
+        self.use_price_for(PriceUseCase::Liquidate);
+
+        let mut loan_acc = self.internal_get_account(&loan_account_id);
+        assert!(loan_acc.shares_usdnear_owed>0,"no USDNEAR owed");
+        let quote = self.liquidation_quote(&loan_acc, self.stnear_valuation_price(), self.stability_pool.total_deposits);
+        assert!(self.is_liquidatable(&quote), "coll.rate.BP is {}. Can't liquidate", quote.collateralization_ratio);
+        assert!(quote.usdnear_repay > 0, "the stability pool is empty");
+
+        // repay the loan with pool usdnear (burn it, remove from circulation)
+        loan_acc.remove_owed_usdnear_preserve_share_price(quote.usdnear_repay, self);
+        // move the seized collateral to the pool free shares
+        let pool_id = String::from(STABILITY_POOL_INTERNAL_ACCOUNT);
+        let mut pool_acc = self.internal_get_account(&pool_id);
+        let shares_before = pool_acc.free_shares;
+        loan_acc.remove_locked_amount_preserve_share_price(quote.stnear_to_receive + quote.stnear_to_reserve, self);
+        pool_acc.add_free_amount_preserve_share_price(quote.stnear_to_receive, self);
+        let gain_shares = pool_acc.free_shares - shares_before;
+        self.internal_update_account(&loan_account_id, &loan_acc);
+        self.internal_update_account(&pool_id, &pool_acc);
+        self.update_mining_account(&loan_account_id);
+        self.add_to_reserve(&loan_account_id, "liquidation_penalty", quote.stnear_to_reserve);
+
+        self.offset_with_stability_pool(quote.usdnear_repay, gain_shares);
+        self.stability_pool.total_usdnear_burned += quote.usdnear_repay;
+        self.stability_pool.total_stnear_seized += quote.stnear_to_receive;
+
+        events::FtBurn { owner_id: STABILITY_POOL_INTERNAL_ACCOUNT, amount: quote.usdnear_repay.into(), memo: Some("liquidate_with_stability_pool") }.emit();
+        events::Liquidate {
+            loan_account_id: &loan_account_id,
+            liquidator_id: STABILITY_POOL_INTERNAL_ACCOUNT,
+            usdnear_repaid: quote.usdnear_repay.into(),
+            stnear_seized: quote.stnear_to_receive.into(),
+            collateralization_ratio: quote.collateralization_ratio,
+        }.emit();
+    }
+
+    /// Returns stability pool totals and accumulators
+    pub fn get_stability_pool_info(&self) -> StabilityPoolInfoJSON {
+        let pool_acc = self.internal_get_account(&String::from(STABILITY_POOL_INTERNAL_ACCOUNT));
+        return StabilityPoolInfoJSON {
+            total_deposits_usdnear: self.stability_pool.total_deposits.into(),
+            unclaimed_gains_stnear: pool_acc.free_stnear(self).into(),
+            product_p: self.stability_pool.product_p.into(),
+            current_scale: self.stability_pool.current_scale.into(),
+            current_epoch: self.stability_pool.current_epoch.into(),
+            total_usdnear_burned: self.stability_pool.total_usdnear_burned.into(),
+            total_stnear_seized: self.stability_pool.total_stnear_seized.into(),
+        };
+    }
+
+    /// Returns the current stability pool deposit of `account_id` and its stNEAR gains
+    pub fn get_stability_deposit(&self, account_id: AccountId) -> StabilityDepositJSON {
+        let deposit = self.stability_deposits.get(&account_id).unwrap_or_default();
+        return StabilityDepositJSON {
+            deposit_usdnear: self.compounded_stability_deposit(&deposit).into(),
+            gains_stnear: self.amount_from_free_shares(self.stability_deposit_gain_shares(&deposit)).into(),
+            account_id,
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// rounding of the P & S accumulators (SP_DECIMAL_PRECISION)
+    const DUST: u128 = 1_000_000_000;
+
+    /// ALICE borrows at 200%, BOB & CAROL borrow USDNEAR to fund the pool. Returns the timestamp
+    fn setup(contract: &mut UsdNearStableCoin, bob_deposit: u128, carol_deposit: u128) -> Timestamp {
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(contract, ALICE, 100 * NEAR, 450 * NEAR, t0);
+        open_loan(contract, BOB, 1_000 * NEAR, 300 * NEAR, t0);
+        open_loan(contract, CAROL, 1_000 * NEAR, 100 * NEAR, t0);
+        for (account_id, amount) in [(BOB, bob_deposit), (CAROL, carol_deposit)] {
+            if amount > 0 {
+                set_context(account_id, 1, t0);
+                contract.deposit_to_stability_pool(amount.into());
+            }
+        }
+        // USD 10 => 7, ALICE is at 140%
+        set_price(contract, 8 * NEAR, t0 + ONE_HOUR);
+        set_price(contract, 7 * NEAR, t0 + 2 * ONE_HOUR);
+        return t0 + 2 * ONE_HOUR;
+    }
+
+    #[test]
+    fn deposits_shrink_and_earn_pro_rata() {
+        let mut contract = new_contract();
+        let t1 = setup(&mut contract, 300 * NEAR, 100 * NEAR);
+        set_context(CAROL, 0, t1);
+        contract.liquidate_with_stability_pool(ALICE.into());
+
+        let info = contract.get_stability_pool_info();
+        // restoring 200% repays ~USDNEAR 300 of the 400 deposited
+        assert_close(info.total_usdnear_burned.0, 300 * NEAR, NEAR / 100);
+        assert_eq!(info.total_deposits_usdnear.0, 400 * NEAR - info.total_usdnear_burned.0);
+        let bob = contract.get_stability_deposit(BOB.into());
+        let carol = contract.get_stability_deposit(CAROL.into());
+        assert_close(bob.deposit_usdnear.0 + carol.deposit_usdnear.0, info.total_deposits_usdnear.0, DUST);
+        assert!(bob.deposit_usdnear.0 + carol.deposit_usdnear.0 <= info.total_deposits_usdnear.0);
+        assert_close(bob.deposit_usdnear.0, 3 * carol.deposit_usdnear.0, DUST);
+        // the seized stNEAR is shared 3:1, never over-distributed
+        assert_close(bob.gains_stnear.0, 3 * carol.gains_stnear.0, DUST);
+        assert!(bob.gains_stnear.0 + carol.gains_stnear.0 <= info.total_stnear_seized.0);
+        assert_close(bob.gains_stnear.0 + carol.gains_stnear.0, info.total_stnear_seized.0, DUST);
+
+        // withdrawing pays the compounded deposit and the gains
+        let bob_stnear = contract.get_account_info(BOB.into()).stnear.0;
+        let bob_usdnear = contract.ft_balance_of(BOB.into()).0;
+        set_context(BOB, 1, t1);
+        let withdrawn = contract.withdraw_from_stability_pool((1_000 * NEAR).into()).0;
+        assert_eq!(withdrawn, bob.deposit_usdnear.0);
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, bob_usdnear + withdrawn);
+        assert_close(contract.get_account_info(BOB.into()).stnear.0, bob_stnear + bob.gains_stnear.0, DUST);
+        assert!(contract.stability_deposits.get(&BOB.into()).is_none());
+        assert_close(contract.get_stability_pool_info().total_deposits_usdnear.0, carol.deposit_usdnear.0, DUST);
+    }
+
+    #[test]
+    fn emptied_pool_starts_a_new_epoch() {
+        let mut contract = new_contract();
+        let t1 = setup(&mut contract, 0, 100 * NEAR);
+        set_context(BOB, 0, t1);
+        contract.liquidate_with_stability_pool(ALICE.into());
+
+        let info = contract.get_stability_pool_info();
+        assert_eq!(info.total_usdnear_burned.0, 100 * NEAR);
+        assert_eq!(info.total_deposits_usdnear.0, 0);
+        assert_eq!(info.current_epoch.0, 1);
+        let carol = contract.get_stability_deposit(CAROL.into());
+        assert_eq!(carol.deposit_usdnear.0, 0);
+        assert_close(carol.gains_stnear.0, info.total_stnear_seized.0, DUST);
+
+        // the gains of the previous epoch are still claimed, and the emptied deposit removed
+        set_context(CAROL, 1, t1);
+        assert_eq!(contract.claim_stability_pool_gains().0, carol.gains_stnear.0);
+        assert!(contract.stability_deposits.get(&CAROL.into()).is_none());
+
+        // new deposits start at P = 1
+        set_context(BOB, 1, t1);
+        contract.deposit_to_stability_pool((50 * NEAR).into());
+        assert_eq!(contract.get_stability_deposit(BOB.into()).deposit_usdnear.0, 50 * NEAR);
+        assert_eq!(contract.get_stability_deposit(BOB.into()).gains_stnear.0, 0);
+    }
+}
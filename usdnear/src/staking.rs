@@ -0,0 +1,320 @@
+//
+// STBL STAKING - protocol fee sharing
+// STBL holders stake STBL and receive `fee_share_bp` of the epoch interest, in stNEAR.
+// The stakers' stNEAR is kept as free shares of this contract's own borrowing account (so it keeps earning staking rewards)
+// and distributed with a free-shares-per-staked-STBL accumulator. `claim_staking_rewards` moves the earned free shares
+// to the staker's borrowing account (free stNEAR, withdrawable with withdraw_stnear).
+// Unstaked STBL can be withdrawn after `unstake_cooldown`
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+/// accumulator precision
+pub const STAKING_ACC_PRECISION: u128 = NEAR;
+/// interest is not shared if less STBL is staked (keeps the accumulator in range)
+pub const MIN_TOTAL_STAKED_STBL: u128 = NEAR;
+/// default share of the epoch interest for STBL stakers: 20%
+pub const DEFAULT_STAKING_FEE_SHARE_BP: u32 = 20 * PERCENT_BP;
+/// default unstake cooldown: 7 days
+pub const DEFAULT_UNSTAKE_COOLDOWN: Duration = 7 * 24 * ONE_HOUR;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct StakingAccount {
+    pub staked: u128,
+    pub acc_paid: u128,
+    /// free stNEAR shares earned and not claimed
+    pub pending_free_shares: u128,
+    /// STBL in cooldown
+    pub unstaking: u128,
+    pub unstaking_available_at: Timestamp,
+}
+
+impl StakingAccount {
+    fn is_empty(&self) -> bool {
+        return self.staked == 0 && self.pending_free_shares == 0 && self.unstaking == 0;
+    }
+
+    fn settle(&mut self, acc: u128) {
+        self.pending_free_shares += proportional(self.staked, acc - self.acc_paid, STAKING_ACC_PRECISION);
+        self.acc_paid = acc;
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StblStaking {
+    pub total_staked: u128,
+    /// free shares per staked STBL (x STAKING_ACC_PRECISION)
+    pub acc_free_shares_per_stbl: u128,
+    /// share of the epoch interest for STBL stakers
+    pub fee_share_bp: u32,
+    pub unstake_cooldown: Duration,
+    /// stNEAR distributed to stakers since deployment
+    pub total_distributed_stnear: u128,
+}
+
+impl Default for StblStaking {
+    fn default() -> Self {
+        Self {
+            total_staked: 0,
+            acc_free_shares_per_stbl: 0,
+            fee_share_bp: DEFAULT_STAKING_FEE_SHARE_BP,
+            unstake_cooldown: DEFAULT_UNSTAKE_COOLDOWN,
+            total_distributed_stnear: 0,
+        }
+    }
+}
+
+impl UsdNearStableCoin {
+
+    fn internal_get_staking_account(&self, account_id: &AccountId) -> StakingAccount {
+        let mut staking_acc = self.staking_accounts.get(account_id).unwrap_or_default();
+        staking_acc.settle(self.stbl_staking.acc_free_shares_per_stbl);
+        return staking_acc;
+    }
+
+    fn internal_update_staking_account(&mut self, account_id: &AccountId, staking_acc: &StakingAccount) {
+        if staking_acc.is_empty() {
+            self.staking_accounts.remove(account_id);
+        } else {
+            self.staking_accounts.insert(account_id, staking_acc);
+        }
+    }
+
+    /// distributes the stakers' share of the epoch interest. Returns the stNEAR amount for the stakers. Must not panic
+    pub(crate) fn distribute_staking_rewards(&mut self, interest_stnear: u128) -> u128 {
+        if self.stbl_staking.total_staked < MIN_TOTAL_STAKED_STBL {
+            return 0;
+        }
+        let amount = apply_pct(self.stbl_staking.fee_share_bp, interest_stnear);
+        let num_shares = self.free_shares_from_amount(amount);
+        if num_shares == 0 {
+            return 0;
+        }
+        self.add_amount_and_free_shares_preserve_share_price(env::current_account_id(), amount);
+        self.stbl_staking.acc_free_shares_per_stbl += proportional(num_shares, STAKING_ACC_PRECISION, self.stbl_staking.total_staked);
+        self.stbl_staking.total_distributed_stnear += amount;
+        return amount;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Stakes `amount` STBL from the caller's STBL balance
+    #[payable]
+    pub fn stake_stbl(&mut self, amount: U128String) {
+        self.assert_one_yocto_for("stake_stbl");
+        assert!(amount.0 > 0, "amount must be positive");
+        let account_id = env::predecessor_account_id();
+        let balance = self.get_stbl_balance(&account_id);
+        assert!(balance >= amount.0, "Not enough STBL balance {}", balance);
+        self.set_stbl_balance(&account_id, balance - amount.0);
+
+        let mut staking_acc = self.internal_get_staking_account(&account_id);
+        staking_acc.staked += amount.0;
+        self.record_total_stake_checkpoint();
+        self.stbl_staking.total_staked += amount.0;
+        self.internal_update_staking_account(&account_id, &staking_acc);
+        self.record_stake_checkpoint(&account_id, staking_acc.staked);
+        events::StblStaking { account_id: &account_id, action: "stake", amount }.emit();
+    }
+
+    /// Starts the cooldown for `amount` staked STBL. Unstaking more STBL restarts the cooldown
+    #[payable]
+    pub fn unstake_stbl(&mut self, amount: U128String) {
+        self.assert_one_yocto_for("unstake_stbl");
+        assert!(amount.0 > 0, "amount must be positive");
+        let account_id = env::predecessor_account_id();
+        let mut staking_acc = self.internal_get_staking_account(&account_id);
+        assert!(staking_acc.staked >= amount.0, "You have only STBL {} staked", staking_acc.staked);
+        staking_acc.staked -= amount.0;
+        self.record_total_stake_checkpoint();
+        self.stbl_staking.total_staked -= amount.0;
+        staking_acc.unstaking += amount.0;
+        staking_acc.unstaking_available_at = env::block_timestamp() + self.stbl_staking.unstake_cooldown;
+        self.internal_update_staking_account(&account_id, &staking_acc);
+        self.record_stake_checkpoint(&account_id, staking_acc.staked);
+        events::StblStaking { account_id: &account_id, action: "unstake", amount }.emit();
+    }
+
+    /// Moves the unstaked STBL back to the caller's STBL balance, after the cooldown
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn withdraw_unstaked_stbl(&mut self) {
+        self.assert_one_yocto_for("withdraw_unstaked_stbl");
+        let account_id = env::predecessor_account_id();
+        let mut staking_acc = self.internal_get_staking_account(&account_id);
+        assert!(staking_acc.unstaking > 0, "no unstaked STBL");
+        assert!(env::block_timestamp() >= staking_acc.unstaking_available_at, "unstaked STBL is available at {}", staking_acc.unstaking_available_at);
+        let amount = staking_acc.unstaking;
+        staking_acc.unstaking = 0;
+        self.internal_update_staking_account(&account_id, &staking_acc);
+        let balance = self.get_stbl_balance(&account_id);
+        self.set_stbl_balance(&account_id, balance + amount);
+        events::StblStaking { account_id: &account_id, action: "withdraw", amount: amount.into() }.emit();
+    }
+
+    /// Moves the stNEAR earned by staking to the caller's free stNEAR. Returns the stNEAR amount
+    /// Requires 1 yoctoNEAR attached (see security.rs)
+    #[payable]
+    pub fn claim_staking_rewards(&mut self) -> U128String {
+        self.assert_one_yocto_for("claim_staking_rewards");
+        let account_id = env::predecessor_account_id();
+        //the stNEAR goes to the caller's borrowing account, its storage is paid by the registration (NEP-145)
+        self.assert_registered(&account_id);
+        let mut staking_acc = self.internal_get_staking_account(&account_id);
+        let num_shares = staking_acc.pending_free_shares;
+        assert!(num_shares > 0, "no staking rewards to claim");
+        staking_acc.pending_free_shares = 0;
+        self.internal_update_staking_account(&account_id, &staking_acc);
+
+        // move the free shares from this contract's account to the staker
+        let mut pool_acc = self.internal_get_account(&env::current_account_id());
+        let num_shares = std::cmp::min(num_shares, pool_acc.free_shares);
+        pool_acc.free_shares -= num_shares;
+        self.internal_update_account(&env::current_account_id(), &pool_acc);
+        let mut acc = self.internal_get_account(&account_id);
+        acc.free_shares += num_shares;
+        self.internal_update_account(&account_id, &acc);
+        self.update_mining_account(&account_id);
+
+        let amount = self.amount_from_free_shares(num_shares);
+        events::StblStaking { account_id: &account_id, action: "claim", amount: amount.into() }.emit();
+        return amount.into();
+    }
+
+    /// Owner's method.
+    /// Sets the share of the epoch interest for STBL stakers and the unstake cooldown
+    pub fn set_stbl_staking_config(&mut self, fee_share_bp: u32, unstake_cooldown_seconds: U64String) {
+        self.assert_owner_calling();
+        assert!(fee_share_bp <= 10000, "fee_share_bp must be <= 10000");
+        self.stbl_staking.fee_share_bp = fee_share_bp;
+        self.stbl_staking.unstake_cooldown = unstake_cooldown_seconds.0 * NANOS_PER_SECOND;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "stbl_staking_config",
+            value: format!("fee_share_bp:{} unstake_cooldown_seconds:{}", fee_share_bp, unstake_cooldown_seconds.0),
+        }.emit();
+    }
+
+    /// Returns STBL staking totals and config
+    pub fn get_stbl_staking_info(&self) -> StblStakingInfoJSON {
+        let pool_acc = self.internal_get_account(&env::current_account_id());
+        return StblStakingInfoJSON {
+            total_staked_stbl: self.stbl_staking.total_staked.into(),
+            fee_share_bp: self.stbl_staking.fee_share_bp,
+            unstake_cooldown_seconds: (self.stbl_staking.unstake_cooldown / NANOS_PER_SECOND).into(),
+            total_distributed_stnear: self.stbl_staking.total_distributed_stnear.into(),
+            unclaimed_stnear: self.amount_from_free_shares(pool_acc.free_shares).into(),
+        };
+    }
+
+    /// Returns the staked & unstaking STBL of `account_id` and the stNEAR to claim
+    pub fn get_stbl_staking_account(&self, account_id: AccountId) -> StblStakingAccountJSON {
+        let staking_acc = self.internal_get_staking_account(&account_id);
+        return StblStakingAccountJSON {
+            account_id,
+            staked_stbl: staking_acc.staked.into(),
+            unstaking_stbl: staking_acc.unstaking.into(),
+            unstaking_available_at: staking_acc.unstaking_available_at.into(),
+            rewards_stnear: self.amount_from_free_shares(staking_acc.pending_free_shares).into(),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn stakers_share_the_epoch_interest() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(1_000 * NEAR, 1_000_000 * NEAR);
+        open_loan(&mut contract, ALICE, 1_000 * NEAR, 1_000 * NEAR, t0);
+        register(&mut contract, BOB, t0);
+        set_context(OWNER, 0, t0);
+        contract.mint_stbl(BOB.into(), (100 * NEAR).into(), None);
+        set_context(BOB, 1, t0);
+        contract.stake_stbl((100 * NEAR).into());
+
+        let t1 = t0 + 30 * EPOCH_DURATION;
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear + 10 * NEAR;
+        run_epoch(&mut contract, total_balance, t1);
+        let stakers_stnear = contract.get_epoch_records()[0].stakers_stnear.0;
+        assert!(stakers_stnear > 0);
+
+        // BOB is the only staker and gets all of it as free stNEAR
+        set_context(BOB, 1, t1);
+        assert_close(contract.claim_staking_rewards().0, stakers_stnear, 1000);
+        assert_close(contract.get_account_info(BOB.into()).stnear.0, stakers_stnear, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "not registered")]
+    fn claim_requires_registration() {
+        let mut contract = new_contract();
+        set_context(CAROL, 1, 10 * NANOS_PER_SECOND);
+        contract.claim_staking_rewards();
+    }
+
+    #[test]
+    fn unstaked_stbl_is_withdrawn_after_the_cooldown() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 100 * NEAR, t0);
+        set_context(ALICE, 1, t0);
+        contract.unstake_stbl((40 * NEAR).into());
+        let staking_acc = contract.get_stbl_staking_account(ALICE.into());
+        assert_eq!(staking_acc.staked_stbl.0, 60 * NEAR);
+        assert_eq!(staking_acc.unstaking_stbl.0, 40 * NEAR);
+        assert_eq!(staking_acc.unstaking_available_at.0, t0 + DEFAULT_UNSTAKE_COOLDOWN);
+        assert_eq!(contract.get_stbl_staking_info().total_staked_stbl.0, 60 * NEAR);
+
+        set_context(ALICE, 1, t0 + DEFAULT_UNSTAKE_COOLDOWN);
+        contract.withdraw_unstaked_stbl();
+        assert_eq!(contract.get_stbl_balance(&ALICE.into()), 40 * NEAR);
+        assert_eq!(contract.get_stbl_staking_account(ALICE.into()).unstaking_stbl.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "unstaked STBL is available at")]
+    fn unstaking_more_restarts_the_cooldown() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        stake_stbl(&mut contract, ALICE, 100 * NEAR, t0);
+        set_context(ALICE, 1, t0);
+        contract.unstake_stbl((40 * NEAR).into());
+        set_context(ALICE, 1, t0 + ONE_HOUR);
+        contract.unstake_stbl((10 * NEAR).into());
+        set_context(ALICE, 1, t0 + DEFAULT_UNSTAKE_COOLDOWN);
+        contract.withdraw_unstaked_stbl();
+    }
+
+    #[test]
+    fn stakers_share_the_interest_by_stake() {
+        let mut contract = new_contract();
+        let t0 = 10 * NANOS_PER_SECOND;
+        open_loan(&mut contract, CAROL, 1_000 * NEAR, 1_000 * NEAR, t0);
+        stake_stbl(&mut contract, ALICE, 300 * NEAR, t0);
+        stake_stbl(&mut contract, BOB, 200 * NEAR, t0);
+        // STBL in cooldown earns nothing: ALICE has 3/4 of the stake
+        set_context(BOB, 1, t0);
+        contract.unstake_stbl((100 * NEAR).into());
+
+        let t1 = t0 + 30 * EPOCH_DURATION;
+        set_price(&mut contract, INITIAL_PRICE, t1);
+        let total_balance = contract.total_collateral_stnear + 10 * NEAR;
+        run_epoch(&mut contract, total_balance, t1);
+        let stakers_stnear = contract.get_epoch_records()[0].stakers_stnear.0;
+        assert!(stakers_stnear > 0);
+        assert_close(contract.get_stbl_staking_account(ALICE.into()).rewards_stnear.0, stakers_stnear * 3 / 4, 1000);
+        assert_close(contract.get_stbl_staking_account(BOB.into()).rewards_stnear.0, stakers_stnear / 4, 1000);
+        assert_close(contract.get_stbl_staking_info().unclaimed_stnear.0, stakers_stnear, 1000);
+    }
+}
@@ -0,0 +1,276 @@
+//
+// STBL governance token
+// a second fungible token surface in this contract, with its own balance map, total supply and metadata.
+// Methods are prefixed `stbl_` (the `ft_` methods belong to USDNEAR).
+// STBL holders must be registered (NEP-145 storage_deposit covers both tokens).
+// STBL can only be minted within the governance-controlled mint schedule:
+// `emission_per_second` accrues to the mintable amount, and total supply can never exceed `max_supply`.
+// The schedule is changed by a passed `stbl_mint_schedule` governance proposal, queued behind the params timelock
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+
+pub const STBL: &str = "STBL";
+pub const STBL_NAME: &str = "STBL Governance Token";
+pub const STBL_DECIMALS: u8 = 24;
+pub const STBL_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 32 32'%3E%3Ccircle cx='16' cy='16' r='16' fill='%23383d8c'/%3E%3Ctext x='16' y='21' font-size='10' font-family='sans-serif' text-anchor='middle' fill='white'%3ESTBL%3C/text%3E%3C/svg%3E";
+/// default hard cap: 100M STBL
+pub const DEFAULT_STBL_MAX_SUPPLY: u128 = 100_000_000 * NEAR;
+/// default emission of a new contract: 0.5 STBL per second (~15.8M STBL per year),
+/// so the treasury manager can mint the first STBL and bootstrap governance
+pub const DEFAULT_STBL_EMISSION_PER_SECOND: u128 = NEAR / 2;
+
+pub fn default_stbl_metadata() -> FungibleTokenMetadata {
+    return FungibleTokenMetadata {
+        spec: FT_METADATA_SPEC.into(),
+        name: STBL_NAME.into(),
+        symbol: STBL.into(),
+        icon: Some(STBL_ICON.into()),
+        reference: None,
+        reference_hash: None,
+        decimals: STBL_DECIMALS,
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StblMintSchedule {
+    /// STBL emitted per second, added to `mintable`
+    pub emission_per_second: u128,
+    /// hard cap on total STBL supply
+    pub max_supply: u128,
+    /// emitted and not yet minted
+    pub mintable: u128,
+    /// last time `mintable` was updated
+    pub last_accrual: Timestamp,
+}
+
+impl StblMintSchedule {
+    pub fn new(emission_per_second: u128, max_supply: u128) -> Self {
+        Self {
+            emission_per_second,
+            max_supply,
+            mintable: 0,
+            last_accrual: env::block_timestamp(),
+        }
+    }
+
+    /// mintable amount at `now`, capped so total supply can not exceed max_supply
+    pub fn mintable_at(&self, now: Timestamp, total_supply: u128) -> u128 {
+        let elapsed = now.saturating_sub(self.last_accrual) as u128;
+        let emitted = proportional(self.emission_per_second, elapsed, NANOS_PER_SECOND as u128);
+        let cap = self.max_supply.saturating_sub(total_supply);
+        return std::cmp::min(self.mintable.saturating_add(emitted), cap);
+    }
+}
+
+impl UsdNearStableCoin {
+
+    pub(crate) fn get_stbl_balance(&self, account_id: &String) -> u128 {
+        return self.stbl_balances.get(account_id).unwrap_or_default();
+    }
+
+    pub(crate) fn set_stbl_balance(&mut self, account_id: &String, balance: u128) {
+        if balance==0 {
+            self.stbl_balances.remove(account_id);
+        }
+        else {
+            self.stbl_balances.insert(account_id,&balance);
+        }
+    }
+
+    pub(crate) fn stbl_transfer_internal(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let sender_balance = self.get_stbl_balance(sender_id);
+        assert!(sender_balance>=amount,"Not enough STBL balance {}",sender_balance);
+        self.set_stbl_balance(sender_id, sender_balance - amount);
+        let receiver_balance = self.get_stbl_balance(receiver_id);
+        self.set_stbl_balance(receiver_id, receiver_balance + amount);
+    }
+
+    /// updates the mintable amount according to the mint schedule
+    pub(crate) fn accrue_stbl_emission(&mut self) {
+        let now = env::block_timestamp();
+        self.stbl_mint_schedule.mintable = self.stbl_mint_schedule.mintable_at(now, self.total_stbl);
+        self.stbl_mint_schedule.last_accrual = now;
+    }
+
+    /// mints STBL to `receiver_id`, within the mint schedule
+    pub(crate) fn internal_mint_stbl(&mut self, receiver_id: &AccountId, amount: u128, memo: Option<&str>) {
+        self.accrue_stbl_emission();
+        assert!(amount <= self.stbl_mint_schedule.mintable, "only {} STBL can be minted now", self.stbl_mint_schedule.mintable);
+        self.stbl_mint_schedule.mintable -= amount;
+        let balance = self.get_stbl_balance(receiver_id);
+        self.set_stbl_balance(receiver_id, balance + amount);
+        self.total_stbl += amount;
+        events::StblMint { owner_id: receiver_id, amount: amount.into(), memo }.emit();
+    }
+
+    /// sets the STBL mint schedule (governance). What was emitted at the previous rate remains mintable
+    pub(crate) fn internal_set_stbl_mint_schedule(&mut self, emission_per_second: u128, max_supply: u128) {
+        self.assert_valid_stbl_max_supply(max_supply);
+        self.accrue_stbl_emission();
+        self.stbl_mint_schedule.emission_per_second = emission_per_second;
+        self.stbl_mint_schedule.max_supply = max_supply;
+        self.stbl_mint_schedule.mintable = std::cmp::min(self.stbl_mint_schedule.mintable, max_supply - self.total_stbl);
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "stbl_mint_schedule",
+            value: format!("emission_per_second:{} max_supply:{}", emission_per_second, max_supply),
+        }.emit();
+    }
+
+    pub(crate) fn assert_valid_stbl_max_supply(&self, max_supply: u128) {
+        assert!(max_supply >= self.total_stbl, "max supply can not be lower than the total supply {}", self.total_stbl);
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Returns the total supply of STBL
+    pub fn stbl_total_supply(&self) -> U128String {
+        return self.total_stbl.into();
+    }
+
+    /// Returns the STBL balance of `account_id`
+    pub fn stbl_balance_of(&self, account_id: AccountId) -> U128String {
+        return self.get_stbl_balance(&account_id).into();
+    }
+
+    /// Returns the STBL token metadata
+    pub fn stbl_metadata(&self) -> FungibleTokenMetadata {
+        return self.stbl_metadata.clone();
+    }
+
+    /// Transfer `amount` of STBL from the caller to `receiver_id`.
+    /// Requirements:
+    /// * 1 yoctoNEAR attached (see security.rs)
+    /// * receiver_id must be registered (NEP-145)
+    #[payable]
+    pub fn stbl_transfer(&mut self, receiver_id: AccountId, amount: U128String, memo: Option<String>) {
+        self.assert_one_yocto_for("stbl_transfer");
+        self.assert_not_paused("transfers");
+        self.assert_registered(&receiver_id);
+        self.stbl_transfer_internal(&env::predecessor_account_id(), &receiver_id, amount.0);
+        events::StblTransfer { old_owner_id: &env::predecessor_account_id(), new_owner_id: &receiver_id, amount, memo: memo.as_deref() }.emit();
+    }
+
+    /// Parameter manager's method.
+    /// Sets the STBL token metadata. Symbol and decimals can not be changed
+    pub fn set_stbl_metadata(&mut self, metadata: FungibleTokenMetadata) {
+        self.assert_role(Role::ParameterManager);
+        crate::funtoken::assert_valid_ft_metadata(&metadata);
+        assert_eq!(metadata.symbol, STBL, "symbol can not be changed");
+        assert_eq!(metadata.decimals, STBL_DECIMALS, "decimals can not be changed");
+        self.stbl_metadata = metadata;
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "stbl_metadata", value: self.stbl_metadata.name.clone() }.emit();
+    }
+
+    /// Treasury manager's method.
+    /// Mints STBL to `receiver_id`, up to what the mint schedule made mintable
+    pub fn mint_stbl(&mut self, receiver_id: AccountId, amount: U128String, memo: Option<String>) {
+        self.assert_role(Role::TreasuryManager);
+        self.assert_registered(&receiver_id);
+        self.internal_mint_stbl(&receiver_id, amount.0, memo.as_deref());
+    }
+
+    /// Returns the STBL mint schedule and the amount mintable now
+    pub fn get_stbl_mint_schedule(&self) -> StblMintScheduleJSON {
+        return StblMintScheduleJSON {
+            emission_per_second: self.stbl_mint_schedule.emission_per_second.into(),
+            max_supply: self.stbl_mint_schedule.max_supply.into(),
+            total_supply: self.total_stbl.into(),
+            mintable: self.stbl_mint_schedule.mintable_at(env::block_timestamp(), self.total_stbl).into(),
+        };
+    }
+
+    /// Owner's method.
+    /// Moves the legacy `BorrowingAccount.stbl` field into the STBL balances map, after `migrate` (see migrate.rs)
+    /// for the borrowing accounts in [from_index, from_index+limit). Returns how many accounts were migrated.
+    /// Emptied accounts are removed (changing the indexes), so repeat until every call returns 0
+    pub fn migrate_stbl_balances(&mut self, from_index: u64, limit: u32) -> u32 {
+        self.assert_owner_calling();
+        let keys = self.b_accounts.keys_as_vector();
+        let account_ids: Vec<AccountId> = (from_index..std::cmp::min(from_index + limit as u64, keys.len()))
+            .map(|index| keys.get(index).unwrap())
+            .collect();
+        let mut migrated = 0;
+        for account_id in account_ids {
+            let mut acc = self.internal_get_account(&account_id);
+            if acc.stbl > 0 {
+                // total_stbl already includes the legacy balances
+                let balance = self.get_stbl_balance(&account_id);
+                self.set_stbl_balance(&account_id, balance + acc.stbl);
+                acc.stbl = 0;
+                self.internal_update_account(&account_id, &acc);
+                migrated += 1;
+            }
+        }
+        return migrated;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// STBL 1 per second up to 100 from 1 second, ALICE and BOB registered
+    fn contract_with_mint_schedule() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        register(&mut contract, ALICE, NANOS_PER_SECOND);
+        register(&mut contract, BOB, NANOS_PER_SECOND);
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(NEAR, 100 * NEAR);
+        return contract;
+    }
+
+    fn mint(contract: &mut UsdNearStableCoin, receiver: &str, amount: u128, block_timestamp: Timestamp) {
+        set_context(OWNER, 0, block_timestamp);
+        contract.mint_stbl(receiver.into(), amount.into(), None);
+    }
+
+    #[test]
+    fn minting_follows_the_schedule() {
+        let mut contract = contract_with_mint_schedule();
+        set_context(ALICE, 0, 11 * NANOS_PER_SECOND);
+        assert_eq!(contract.get_stbl_mint_schedule().mintable.0, 10 * NEAR);
+        mint(&mut contract, ALICE, 10 * NEAR, 11 * NANOS_PER_SECOND);
+        assert_eq!(contract.stbl_balance_of(ALICE.into()).0, 10 * NEAR);
+        assert_eq!(contract.stbl_total_supply().0, 10 * NEAR);
+        assert_eq!(contract.get_stbl_mint_schedule().mintable.0, 0);
+
+        // capped by max_supply
+        set_context(ALICE, 0, 1000 * NANOS_PER_SECOND);
+        assert_eq!(contract.get_stbl_mint_schedule().mintable.0, 90 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "STBL can be minted now")]
+    fn minting_more_than_emitted_fails() {
+        let mut contract = contract_with_mint_schedule();
+        mint(&mut contract, ALICE, 11 * NEAR, 11 * NANOS_PER_SECOND);
+    }
+
+    #[test]
+    fn stbl_transfer_moves_the_balance() {
+        let mut contract = contract_with_mint_schedule();
+        mint(&mut contract, ALICE, 10 * NEAR, 11 * NANOS_PER_SECOND);
+        set_context(ALICE, 1, 11 * NANOS_PER_SECOND);
+        contract.stbl_transfer(BOB.into(), (4 * NEAR).into(), None);
+        assert_eq!(contract.stbl_balance_of(ALICE.into()).0, 6 * NEAR);
+        assert_eq!(contract.stbl_balance_of(BOB.into()).0, 4 * NEAR);
+        // USDNEAR balances are separate
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "max supply can not be lower than the total supply")]
+    fn max_supply_can_not_drop_below_the_total_supply() {
+        let mut contract = contract_with_mint_schedule();
+        mint(&mut contract, ALICE, 10 * NEAR, 11 * NANOS_PER_SECOND);
+        set_context(OWNER, 0, 11 * NANOS_PER_SECOND);
+        contract.internal_set_stbl_mint_schedule(NEAR, 5 * NEAR);
+    }
+}
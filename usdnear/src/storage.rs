@@ -0,0 +1,247 @@
+//
+// NEP-145 Storage Management Standard implementation
+// https://github.com/near/NEPs/blob/master/specs/Standards/StorageManagement.md
+//
+
+use crate::*;
+use near_sdk::{near_bindgen, Promise};
+
+impl UsdNearStableCoin {
+
+    /// Registers an account whose storage is paid from the contract balance (protocol accounts, legacy accounts, see migrate.rs).
+    /// The deposit is recorded as 0, so `storage_unregister` never refunds NEAR the account did not deposit.
+    /// Returns false if the account was already registered
+    pub(crate) fn register_contract_paid_account(&mut self, account_id: &AccountId) -> bool {
+        if self.storage_deposits.contains_key(account_id) {
+            return false;
+        }
+        let min_balance = self.storage_balance_bounds().min.0;
+        assert!(env::account_balance() >= min_balance, "not enough balance to pay the storage of {}", account_id);
+        self.storage_deposits.insert(account_id, &0);
+        log!("{} registered, storage paid by the contract", account_id);
+        return true;
+    }
+
+    /// Registers a protocol account (operator, treasury) credited with fees, the storage is paid by the contract
+    pub(crate) fn register_protocol_account(&mut self, account_id: &AccountId) {
+        self.register_contract_paid_account(account_id);
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Registers an account (the predecessor if `account_id` is omitted) paying for its storage.
+    /// Every account uses the same fixed amount of storage (balances, borrowing account and every other per-account record),
+    /// so the deposit is always `storage_balance_bounds().min` and any excess is refunded.
+    /// If the account is already registered the full attached deposit is refunded
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>, #[allow(unused_variables)] registration_only: Option<bool>) -> StorageBalance {
+
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        assert!(env::is_valid_account_id(account_id.as_bytes()),"invalid account id {}",account_id);
+
+        if self.storage_deposits.contains_key(&account_id) {
+            log!("The account {} is already registered, refunding the deposit",account_id);
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+        }
+        else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            assert!(amount >= min_balance,"The attached deposit is less than the minimum storage balance {}",min_balance);
+            self.storage_deposits.insert(&account_id, &min_balance);
+            let refund = amount - min_balance;
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        }
+        return self.storage_balance_of(account_id).unwrap();
+    }
+
+    /// Withdraws NEAR not used for storage. Since storage per account is fixed,
+    /// there's never available balance, so only `amount` 0 or None is accepted
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128String>) -> StorageBalance {
+        self.assert_one_yocto_for("storage_withdraw");
+        let account_id = env::predecessor_account_id();
+        let storage_balance = self.storage_balance_of(account_id.clone());
+        assert!(storage_balance.is_some(),"The account {} is not registered",account_id);
+        let amount = amount.unwrap_or(0.into()).0;
+        assert!(amount == 0,"The amount is greater than the available storage balance");
+        return storage_balance.unwrap();
+    }
+
+    /// Unregisters the predecessor account and returns the storage NEAR deposit (none if the contract paid for the storage).
+    /// The account can not have free stNEAR, collateral or outstanding loans, nor be the operator or the treasury.
+    /// If the account has USDNEAR, `force` must be true and the USDNEAR balance is burned.
+    /// Returns false if the account was not registered
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        self.assert_one_yocto_for("storage_unregister");
+        let account_id = env::predecessor_account_id();
+        let force = force.unwrap_or(false);
+
+        if let Some(deposit) = self.storage_deposits.get(&account_id) {
+            assert!(account_id != self.operator_account_id && account_id != self.treasury_account_id,"The operator and treasury accounts can't unregister, they're credited with fees");
+            let acc = self.internal_get_account(&account_id);
+            assert!(acc.is_empty(),"The account has stNEAR or outstanding loans. Repay and withdraw first");
+            let usdnear_balance = self.get_usdnear_balance(&account_id);
+            if usdnear_balance > 0 {
+                assert!(force,"Can't unregister the account with a positive USDNEAR balance without force");
+                // burn usdnear tokens (owed_usdnear_shares remain the same), so all users with outstanding loans now owe a little less
+                self.set_usdnear_balance(&account_id, 0);
+                self.burn_usdnear_from_circulation(usdnear_balance);
+                events::FtBurn { owner_id: &account_id, amount: usdnear_balance.into(), memo: Some("storage_unregister") }.emit();
+            }
+            assert!(!self.stability_deposits.contains_key(&account_id),"The account has a stability pool deposit. Withdraw first");
+            assert!(self.staking_accounts.get(&account_id).is_none(),"The account has staked STBL or staking rewards. Unstake, withdraw and claim first");
+            self.stake_checkpoints.remove(&account_id);
+            self.governance_votes.remove(&account_id);
+            if let Some(mining_acc) = self.mining_accounts.remove(&account_id) {
+                // the account is empty, so its stakes are 0
+                assert!(force || mining_acc.pending_stbl == 0,"Can't unregister the account with STBL to claim without force. Call claim_stbl first");
+            }
+            let stbl_balance = self.get_stbl_balance(&account_id);
+            if stbl_balance > 0 {
+                assert!(force,"Can't unregister the account with a positive STBL balance without force");
+                self.set_stbl_balance(&account_id, 0);
+                self.total_stbl -= stbl_balance;
+                events::StblBurn { owner_id: &account_id, amount: stbl_balance.into(), memo: Some("storage_unregister") }.emit();
+            }
+            self.storage_deposits.remove(&account_id);
+            Promise::new(account_id).transfer(deposit + 1);
+            return true;
+        }
+        else {
+            log!("The account {} is not registered",account_id);
+            return false;
+        }
+    }
+
+    /// Returns the storage balance of `account_id` or None if the account is not registered.
+    /// Accounts whose storage is paid by the contract report the min bound, their refundable deposit is 0
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        let min_balance = self.storage_balance_bounds().min.0;
+        return self.storage_deposits.get(&account_id).map(|deposit| StorageBalance {
+            total: (if deposit == 0 { min_balance } else { deposit }).into(),
+            available: 0.into(),
+        });
+    }
+
+    /// min & max storage deposit. Both are the same since every account uses the same storage
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let required_storage_balance = self.account_storage_usage as u128 * STORAGE_PRICE_PER_BYTE;
+        return StorageBalanceBounds {
+            min: required_storage_balance.into(),
+            max: Some(required_storage_balance.into()),
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// ALICE borrows USDNEAR 450 and sends 100 to BOB
+    fn contract_with_bob_holding_usdnear() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        open_loan(&mut contract, ALICE, 100 * NEAR, 450 * NEAR, NANOS_PER_SECOND);
+        register(&mut contract, BOB, NANOS_PER_SECOND);
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        contract.ft_transfer(BOB.into(), (100 * NEAR).into(), None);
+        return contract;
+    }
+
+    #[test]
+    fn storage_deposit_takes_the_fixed_bound() {
+        let mut contract = new_contract();
+        let bounds = contract.storage_balance_bounds();
+        assert!(bounds.min.0 > 0);
+        assert_eq!(Some(bounds.min), bounds.max);
+
+        assert!(contract.storage_balance_of(ALICE.into()).is_none());
+        register(&mut contract, ALICE, NANOS_PER_SECOND);
+        assert_eq!(contract.storage_balance_of(ALICE.into()).unwrap().total, bounds.min);
+        // registering again does not change the deposit
+        register(&mut contract, ALICE, NANOS_PER_SECOND);
+        let balance = contract.storage_balance_of(ALICE.into()).unwrap();
+        assert_eq!(balance.total, bounds.min);
+        assert_eq!(balance.available.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn storage_deposit_below_the_bound_fails() {
+        let mut contract = new_contract();
+        let min = contract.storage_balance_bounds().min.0;
+        set_context(ALICE, min - 1, NANOS_PER_SECOND);
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account has stNEAR or outstanding loans")]
+    fn borrowers_can_not_unregister() {
+        let mut contract = contract_with_bob_holding_usdnear();
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with a positive USDNEAR balance without force")]
+    fn unregistering_with_usdnear_requires_force() {
+        let mut contract = contract_with_bob_holding_usdnear();
+        set_context(BOB, 1, NANOS_PER_SECOND);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn forced_unregister_burns_the_usdnear() {
+        let mut contract = contract_with_bob_holding_usdnear();
+        let total_supply = contract.ft_total_supply().0;
+        set_context(BOB, 1, NANOS_PER_SECOND);
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.storage_balance_of(BOB.into()).is_none());
+        assert_eq!(contract.ft_balance_of(BOB.into()).0, 0);
+        assert_eq!(contract.ft_total_supply().0, total_supply - 100 * NEAR);
+        // a second call finds nothing to unregister
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "The operator and treasury accounts can't unregister")]
+    fn the_operator_can_not_unregister() {
+        let mut contract = new_contract();
+        set_context(OPERATOR, 1, NANOS_PER_SECOND);
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn unregister_refunds_the_deposit() {
+        let mut contract = new_contract();
+        let min = contract.storage_balance_bounds().min.0;
+        register(&mut contract, ALICE, NANOS_PER_SECOND);
+        set_context(ALICE, 1, NANOS_PER_SECOND);
+        assert!(contract.storage_unregister(None));
+        assert_eq!(near_transferred_to(ALICE), min + 1);
+    }
+
+    #[test]
+    fn contract_paid_storage_is_not_refunded() {
+        let mut contract = new_contract();
+        let min = contract.storage_balance_bounds().min.0;
+        // the operator's storage was paid by the contract
+        assert_eq!(contract.storage_balance_of(OPERATOR.into()).unwrap().total.0, min);
+        register(&mut contract, CAROL, NANOS_PER_SECOND);
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_operator_account_id(CAROL.into());
+
+        // the former operator only gets the attached yoctoNEAR back
+        set_context(OPERATOR, 1, NANOS_PER_SECOND);
+        assert!(contract.storage_unregister(None));
+        assert_eq!(near_transferred_to(OPERATOR), 1);
+    }
+}
@@ -0,0 +1,127 @@
+//
+// UNIT TESTS helpers
+// a mocked blockchain where every epoch lasts EPOCH_DURATION, and a contract with registered accounts
+//
+
+use crate::*;
+use near_sdk::{testing_env, MockedBlockchain, PromiseResult, VMConfig, VMContext, RuntimeFeesConfig};
+
+pub const CONTRACT: &str = "usdnear.near";
+pub const OWNER: &str = "owner.near";
+pub const TREASURY: &str = "treasury.near";
+pub const OPERATOR: &str = "operator.near";
+pub const ALICE: &str = "alice.near";
+pub const BOB: &str = "bob.near";
+pub const CAROL: &str = "carol.near";
+
+pub const EPOCH_DURATION: Timestamp = 12 * ONE_HOUR;
+/// stNEAR price at init: USD 10
+pub const INITIAL_PRICE: u128 = 10 * NEAR;
+
+/// sets the blockchain context: `predecessor` calls with `attached_deposit` at `block_timestamp`
+pub fn set_context(predecessor: &str, attached_deposit: u128, block_timestamp: Timestamp) {
+    testing_env!(vm_context(predecessor, attached_deposit, block_timestamp), VMConfig::free(), RuntimeFeesConfig::free());
+}
+
+/// sets the blockchain context of a callback (the contract calls itself) receiving `promise_result` at `block_timestamp`
+pub fn set_callback_context(promise_result: PromiseResult, block_timestamp: Timestamp) {
+    let storage = match env::take_blockchain_interface() {
+        Some(mut bi) => bi.as_mut_mocked_blockchain().unwrap().take_storage(),
+        None => Default::default(),
+    };
+    env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+        vm_context(CONTRACT, 0, block_timestamp),
+        VMConfig::free(),
+        RuntimeFeesConfig::free(),
+        vec![promise_result],
+        storage,
+        Default::default(),
+    )));
+}
+
+fn vm_context(predecessor: &str, attached_deposit: u128, block_timestamp: Timestamp) -> VMContext {
+    return VMContext {
+        current_account_id: CONTRACT.into(),
+        signer_account_id: predecessor.into(),
+        signer_account_pk: vec![0, 1, 2],
+        predecessor_account_id: predecessor.into(),
+        input: vec![],
+        block_index: block_timestamp / NANOS_PER_SECOND,
+        block_timestamp,
+        epoch_height: block_timestamp / EPOCH_DURATION,
+        account_balance: 1_000 * NEAR,
+        account_locked_balance: 0,
+        storage_usage: 10u64.pow(6),
+        attached_deposit,
+        prepaid_gas: 10u64.pow(18),
+        random_seed: vec![0, 1, 2],
+        is_view: false,
+        output_data_receivers: vec![],
+    };
+}
+
+/// a new contract on empty storage, created at timestamp 1 second
+pub fn new_contract() -> UsdNearStableCoin {
+    env::take_blockchain_interface();
+    set_context(OWNER, 0, NANOS_PER_SECOND);
+    return UsdNearStableCoin::new(OWNER.into(), TREASURY.into(), OPERATOR.into(), INITIAL_PRICE.into());
+}
+
+/// registers `account_id` (NEP-145) at `block_timestamp`
+pub fn register(contract: &mut UsdNearStableCoin, account_id: &str, block_timestamp: Timestamp) {
+    set_context(account_id, NEAR, block_timestamp);
+    contract.storage_deposit(None, None);
+}
+
+/// `account_id` deposits `amount` stNEAR at `block_timestamp`
+pub fn deposit_stnear(contract: &mut UsdNearStableCoin, account_id: &str, amount: u128, block_timestamp: Timestamp) {
+    set_context(META_POOL_STNEAR_CONTRACT, 0, block_timestamp);
+    contract.ft_on_transfer(account_id.into(), amount.into(), String::new());
+}
+
+/// registers `account_id`, deposits `stnear` and takes a loan of `usdnear` at `block_timestamp`
+pub fn open_loan(contract: &mut UsdNearStableCoin, account_id: &str, stnear: u128, usdnear: u128, block_timestamp: Timestamp) {
+    register(contract, account_id, block_timestamp);
+    deposit_stnear(contract, account_id, stnear, block_timestamp);
+    set_context(account_id, 1, block_timestamp);
+    contract.take_loan(usdnear.into());
+}
+
+/// registers `account_id`, gives it `amount` STBL and stakes it at `block_timestamp`
+pub fn stake_stbl(contract: &mut UsdNearStableCoin, account_id: &str, amount: u128, block_timestamp: Timestamp) {
+    register(contract, account_id, block_timestamp);
+    contract.set_stbl_balance(&account_id.into(), amount);
+    contract.total_stbl += amount;
+    set_context(account_id, 1, block_timestamp);
+    contract.stake_stbl(amount.into());
+}
+
+/// the operator sets the stNEAR price at `block_timestamp`
+pub fn set_price(contract: &mut UsdNearStableCoin, price: u128, block_timestamp: Timestamp) {
+    set_context(OPERATOR, 0, block_timestamp);
+    contract.set_stnear_price_usd(price.into());
+}
+
+/// runs the epoch callback at `block_timestamp`, the stNEAR contract reporting `total_balance`
+pub fn run_epoch(contract: &mut UsdNearStableCoin, total_balance: u128, block_timestamp: Timestamp) {
+    set_context(CONTRACT, 0, block_timestamp);
+    contract.after_get_meta_contract_stnear_total_balance(total_balance.into());
+}
+
+/// asserts `a` and `b` differ at most `max_diff`
+pub fn assert_close(a: u128, b: u128, max_diff: u128) {
+    assert!(a.abs_diff(b) <= max_diff, "{} and {} differ more than {}", a, b, max_diff);
+}
+
+/// NEAR sent to `receiver_id` by the Transfer actions created in the current context
+pub fn near_transferred_to(receiver_id: &str) -> u128 {
+    let mut blockchain = env::take_blockchain_interface().unwrap();
+    let receipts = format!("{:?}", blockchain.as_mut_mocked_blockchain().unwrap().created_receipts());
+    env::set_blockchain_interface(blockchain);
+    let receiver = format!("receiver_id: \"{}\"", receiver_id);
+    return receipts.split("Receipt {")
+        .filter(|receipt| receipt.contains(&receiver))
+        .flat_map(|receipt| receipt.split("TransferAction { deposit: ").skip(1))
+        .map(|transfer| transfer.split(' ').next().unwrap().parse::<u128>().unwrap())
+        .sum();
+}
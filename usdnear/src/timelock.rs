@@ -0,0 +1,264 @@
+//
+// TIMELOCKED CONTRACT PARAMS
+// risk parameters (collateral %, APR, fee split...) are not applied immediately:
+// the parameter manager queues a proposal, and anyone can execute it after `params_timelock`,
+// so borrowers get notice before the parameters move. The guardian can veto a queued proposal.
+// Passed governance `contract_params`, `fee_split`, `rate_model` and `stbl_mint_schedule` proposals are queued here too (see governance.rs).
+// A proposal not executed within PARAMS_PROPOSAL_GRACE_PERIOD after its eta expires
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+/// default delay between queueing and executing a params proposal: 2 days
+pub const DEFAULT_PARAMS_TIMELOCK: Duration = 2 * 24 * ONE_HOUR;
+/// the owner can not set a shorter delay
+pub const MIN_PARAMS_TIMELOCK: Duration = 24 * ONE_HOUR;
+/// after its eta a proposal can be executed during this period, then it expires
+pub const PARAMS_PROPOSAL_GRACE_PERIOD: Duration = 14 * 24 * ONE_HOUR;
+/// max queued proposals
+pub const MAX_PARAMS_PROPOSALS: u64 = 10;
+
+/// a queued change. Only the fields of the change are applied, so executing a proposal
+/// doesn't revert params changed after it was queued
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ParamsChange {
+    ContractParams(ContractParamsJSON),
+    /// interest split between operator & treasury, and the STBL stakers share of the epoch interest
+    FeeSplit { operator_fee_basis_points: u16, treasury_fee_basis_points: u16, stbl_stakers_fee_share_bp: u32 },
+    /// interest rate model (see rate_model.rs)
+    RateModel(RateModel),
+    /// STBL emission and hard cap (see stbl.rs)
+    StblMintSchedule { emission_per_second: U128String, max_supply: U128String },
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ParamsProposal {
+    pub change: ParamsChange,
+    pub proposed_by: AccountId,
+    pub proposed_at: Timestamp,
+    /// earliest execution timestamp
+    pub eta: Timestamp,
+}
+
+impl ParamsProposal {
+    pub fn is_expired(&self) -> bool {
+        return env::block_timestamp() > self.eta + PARAMS_PROPOSAL_GRACE_PERIOD;
+    }
+}
+
+impl UsdNearStableCoin {
+
+    fn take_params_proposal(&mut self, proposal_id: u64) -> ParamsProposal {
+        return match self.params_proposals.remove(&proposal_id) {
+            Some(proposal) => proposal,
+            None => panic!("params proposal {} not found", proposal_id),
+        };
+    }
+
+    fn resolve_params_proposal(&self, proposal_id: u64, proposal: &ParamsProposal, action: &str) {
+        events::ParamsProposalUpdate {
+            proposal_id: proposal_id.into(),
+            updated_by: &env::predecessor_account_id(),
+            action,
+            eta: proposal.eta.into(),
+            change: &proposal.change,
+        }.emit();
+    }
+
+    fn apply_params_change(&mut self, change: &ParamsChange) {
+        match change {
+            ParamsChange::ContractParams(params) => self.internal_set_contract_params(params),
+            ParamsChange::FeeSplit { operator_fee_basis_points, treasury_fee_basis_points, stbl_stakers_fee_share_bp } => {
+                let mut params = self.get_contract_params();
+                params.operator_fee_basis_points = *operator_fee_basis_points;
+                params.treasury_fee_basis_points = *treasury_fee_basis_points;
+                self.internal_set_contract_params(&params);
+                self.stbl_staking.fee_share_bp = *stbl_stakers_fee_share_bp;
+                events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "stbl_stakers_fee_share_bp", value: stbl_stakers_fee_share_bp.to_string() }.emit();
+            }
+            ParamsChange::RateModel(rate_model) => self.internal_set_rate_model(rate_model),
+            ParamsChange::StblMintSchedule { emission_per_second, max_supply } => {
+                self.internal_set_stbl_mint_schedule(emission_per_second.0, max_supply.0)
+            }
+        }
+    }
+
+    /// queues a validated change, executable by anyone after `params_timelock`. Returns the proposal id
+    pub(crate) fn internal_queue_params_proposal(&mut self, change: ParamsChange) -> u64 {
+        assert!(self.params_proposals.len() < MAX_PARAMS_PROPOSALS, "max {} queued params proposals", MAX_PARAMS_PROPOSALS);
+        let proposal_id = self.next_params_proposal_id;
+        self.next_params_proposal_id += 1;
+        let proposal = ParamsProposal {
+            change,
+            proposed_by: env::predecessor_account_id(),
+            proposed_at: env::block_timestamp(),
+            eta: env::block_timestamp() + self.params_timelock,
+        };
+        self.resolve_params_proposal(proposal_id, &proposal, "queued");
+        self.params_proposals.insert(&proposal_id, &proposal);
+        return proposal_id;
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Parameter manager's method.
+    /// Queues a contract params change, executable by anyone after `params_timelock`. Returns the proposal id
+    pub fn propose_contract_params(&mut self, params: ContractParamsJSON) -> U64String {
+        self.assert_role(Role::ParameterManager);
+        self.assert_valid_contract_params(&params);
+        return self.internal_queue_params_proposal(ParamsChange::ContractParams(params)).into();
+    }
+
+    /// Executes a queued params proposal after its eta. Anyone can call it
+    pub fn execute_params_proposal(&mut self, proposal_id: U64String) {
+        let proposal = self.take_params_proposal(proposal_id.0);
+        assert!(env::block_timestamp() >= proposal.eta, "params proposal {} can not be executed before {}", proposal_id.0, proposal.eta);
+        assert!(!proposal.is_expired(), "params proposal {} expired", proposal_id.0);
+        self.resolve_params_proposal(proposal_id.0, &proposal, "executed");
+        self.apply_params_change(&proposal.change);
+    }
+
+    /// Guardian's method.
+    /// Vetoes a queued params proposal
+    pub fn veto_params_proposal(&mut self, proposal_id: U64String) {
+        self.assert_role(Role::Guardian);
+        let proposal = self.take_params_proposal(proposal_id.0);
+        self.resolve_params_proposal(proposal_id.0, &proposal, "vetoed");
+    }
+
+    /// Removes an expired params proposal. Anyone can call it
+    pub fn remove_expired_params_proposal(&mut self, proposal_id: U64String) {
+        let proposal = self.take_params_proposal(proposal_id.0);
+        assert!(proposal.is_expired(), "params proposal {} has not expired", proposal_id.0);
+        self.resolve_params_proposal(proposal_id.0, &proposal, "expired");
+    }
+
+    /// Owner's method.
+    /// Sets the delay between queueing and executing params proposals. Applies to new proposals
+    pub fn set_params_timelock(&mut self, params_timelock_seconds: U64String) {
+        self.assert_owner_calling();
+        let params_timelock = params_timelock_seconds.0 * NANOS_PER_SECOND;
+        assert!(params_timelock >= MIN_PARAMS_TIMELOCK, "min params timelock is {} seconds", MIN_PARAMS_TIMELOCK / NANOS_PER_SECOND);
+        self.params_timelock = params_timelock;
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "params_timelock_seconds", value: params_timelock_seconds.0.to_string() }.emit();
+    }
+
+    pub fn get_params_timelock_seconds(&self) -> U64String {
+        return (self.params_timelock / NANOS_PER_SECOND).into();
+    }
+
+    /// Returns queued params proposals, including expired ones not yet removed
+    pub fn get_params_proposals(&self) -> Vec<ParamsProposalJSON> {
+        return self.params_proposals.iter()
+            .map(|(proposal_id, proposal)| ParamsProposalJSON {
+                proposal_id: proposal_id.into(),
+                proposed_by: proposal.proposed_by.clone(),
+                proposed_at: proposal.proposed_at.into(),
+                eta: proposal.eta.into(),
+                executable: env::block_timestamp() >= proposal.eta && !proposal.is_expired(),
+                expired: proposal.is_expired(),
+                change: proposal.change,
+            })
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// the owner proposes a 5% APR at 1 second
+    fn contract_with_proposal(borrowing_paused: bool) -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        let mut params = contract.get_contract_params();
+        params.usdnear_apr_basis_points = 500;
+        params.borrowing_paused = borrowing_paused;
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        assert_eq!(contract.propose_contract_params(params).0, 0);
+        return contract;
+    }
+
+    fn eta() -> Timestamp {
+        return NANOS_PER_SECOND + DEFAULT_PARAMS_TIMELOCK;
+    }
+
+    #[test]
+    fn params_apply_after_the_timelock() {
+        let mut contract = contract_with_proposal(false);
+        assert!(!contract.get_params_proposals()[0].executable);
+        set_context(ALICE, 0, eta());
+        assert!(contract.get_params_proposals()[0].executable);
+        contract.execute_params_proposal(0.into());
+        assert_eq!(contract.get_contract_params().usdnear_apr_basis_points, 500);
+        assert!(contract.get_params_proposals().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "params proposal 0 can not be executed before")]
+    fn params_can_not_apply_before_the_timelock() {
+        let mut contract = contract_with_proposal(false);
+        set_context(ALICE, 0, eta() - 1);
+        contract.execute_params_proposal(0.into());
+    }
+
+    #[test]
+    fn params_never_unpause_borrowing() {
+        let mut contract = contract_with_proposal(false);
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.pause("borrowing".into());
+        set_context(ALICE, 0, eta());
+        contract.execute_params_proposal(0.into());
+        assert!(contract.paused.borrowing);
+    }
+
+    #[test]
+    fn params_can_pause_borrowing() {
+        let mut contract = contract_with_proposal(true);
+        set_context(ALICE, 0, eta());
+        contract.execute_params_proposal(0.into());
+        assert!(contract.paused.borrowing);
+    }
+
+    #[test]
+    #[should_panic(expected = "params proposal 0 not found")]
+    fn vetoed_params_can_not_apply() {
+        let mut contract = contract_with_proposal(false);
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.veto_params_proposal(0.into());
+        set_context(ALICE, 0, eta());
+        contract.execute_params_proposal(0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "params proposal 0 expired")]
+    fn expired_params_can_not_apply() {
+        let mut contract = contract_with_proposal(false);
+        set_context(ALICE, 0, eta() + PARAMS_PROPOSAL_GRACE_PERIOD + 1);
+        contract.execute_params_proposal(0.into());
+    }
+
+    #[test]
+    fn expired_proposals_are_removed_by_anyone() {
+        let mut contract = contract_with_proposal(false);
+        set_context(ALICE, 0, eta() + PARAMS_PROPOSAL_GRACE_PERIOD + 1);
+        assert!(contract.get_params_proposals()[0].expired);
+        contract.remove_expired_params_proposal(0.into());
+        assert!(contract.get_params_proposals().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "min params timelock is 86400 seconds")]
+    fn timelock_has_a_minimum() {
+        let mut contract = new_contract();
+        set_context(OWNER, 0, NANOS_PER_SECOND);
+        contract.set_params_timelock(3600.into());
+    }
+}
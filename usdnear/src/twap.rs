@@ -0,0 +1,242 @@
+//
+// TWAP - Time-weighted average price
+// every price update is recorded in an on-chain ring buffer of observations,
+// the TWAP is computed over the last `twap_window` nanoseconds
+// the owner chooses per use-case (take_loan, liquidate, convert_usdnear) to use spot, TWAP or the most conservative of both
+//
+
+use crate::*;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+/// ring buffer capacity, 2 days of hourly updates
+pub const MAX_PRICE_OBSERVATIONS: u64 = 48;
+/// default TWAP window: 6 hours
+pub const DEFAULT_TWAP_WINDOW: Duration = 6 * 60 * 60 * NANOS_PER_SECOND;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PriceObservation {
+    pub price: u128,
+    pub timestamp: Timestamp,
+}
+
+/// Which price to use to value collateral
+/// Conservative: the one that protects the collateral pool, see PriceModes
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum PriceMode {
+    Spot,
+    Twap,
+    Conservative,
+}
+
+/// Price mode per use-case
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceModes {
+    /// conservative => min(spot,twap), the collateral is valued lower
+    pub take_loan: PriceMode,
+    /// conservative => max(spot,twap), a short price dip can't trigger a liquidation and the liquidator gets less stNEAR
+    pub liquidate: PriceMode,
+    /// conservative => max(spot,twap), the converter gets less stNEAR per USDNEAR
+    pub convert_usdnear: PriceMode,
+}
+
+impl Default for PriceModes {
+    fn default() -> Self {
+        Self {
+            take_loan: PriceMode::Spot,
+            liquidate: PriceMode::Spot,
+            convert_usdnear: PriceMode::Spot,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum PriceUseCase {
+    TakeLoan,
+    Liquidate,
+    ConvertUsdnear,
+}
+
+impl UsdNearStableCoin {
+
+    /// adds a price observation to the ring buffer
+    pub(crate) fn record_price_observation(&mut self, price: u128) {
+        let observation = PriceObservation { price, timestamp: env::block_timestamp() };
+        if self.price_observations.len() < MAX_PRICE_OBSERVATIONS {
+            self.price_observations.push(&observation);
+        }
+        else {
+            self.price_observations.replace(self.price_observations_next, &observation);
+        }
+        self.price_observations_next = (self.price_observations_next + 1) % MAX_PRICE_OBSERVATIONS;
+    }
+
+    /// time-weighted average of the observations in the last `twap_window`
+    /// each observed price holds until the next observation (the last one until now)
+    pub(crate) fn compute_twap(&self) -> u128 {
+        let now = env::block_timestamp();
+        let window_start = now.saturating_sub(self.twap_window);
+        let mut observations = self.price_observations.to_vec();
+        observations.sort_by_key(|o| o.timestamp);
+
+        let mut weighted_sum = U256::zero();
+        let mut total_time: u64 = 0;
+        let mut period_end = now;
+        for observation in observations.iter().rev() {
+            let period_start = std::cmp::max(observation.timestamp, window_start);
+            if period_end > period_start {
+                weighted_sum += U256::from(observation.price) * U256::from(period_end - period_start);
+                total_time += period_end - period_start;
+            }
+            if observation.timestamp <= window_start {
+                break;
+            }
+            period_end = observation.timestamp;
+        }
+
+        if total_time == 0 {
+            return self.current_stnear_price;
+        }
+        return (weighted_sum / U256::from(total_time)).as_u128();
+    }
+
+    /// price to use for a use-case according to the configured PriceMode
+    pub(crate) fn price_for(&self, use_case: PriceUseCase) -> u128 {
+        let (mode, conservative_is_min) = match use_case {
+            PriceUseCase::TakeLoan => (self.price_modes.take_loan, true),
+            PriceUseCase::Liquidate => (self.price_modes.liquidate, false),
+            PriceUseCase::ConvertUsdnear => (self.price_modes.convert_usdnear, false),
+        };
+        return match mode {
+            PriceMode::Spot => self.current_stnear_price,
+            PriceMode::Twap => self.compute_twap(),
+            PriceMode::Conservative => {
+                let twap = self.compute_twap();
+                if conservative_is_min {
+                    std::cmp::min(self.current_stnear_price, twap)
+                } else {
+                    std::cmp::max(self.current_stnear_price, twap)
+                }
+            }
+        };
+    }
+
+    /// sets the price used by stnear_to_usd & usdnear_to_stnear for the rest of this call
+    pub(crate) fn use_price_for(&mut self, use_case: PriceUseCase) {
+        self.valuation_price = Some(self.price_for(use_case));
+    }
+
+    /// price used to value stNEAR: the use-case price if set by use_price_for, else spot
+    pub(crate) fn stnear_valuation_price(&self) -> u128 {
+        return self.valuation_price.unwrap_or(self.current_stnear_price);
+    }
+}
+
+#[near_bindgen]
+impl UsdNearStableCoin {
+
+    /// Parameter manager's method.
+    /// Sets which price (spot, twap, conservative) to use for take_loan, liquidate and convert_usdnear
+    pub fn set_price_modes(&mut self, price_modes: PriceModes) {
+        self.assert_role(Role::ParameterManager);
+        self.price_modes = price_modes;
+        events::ConfigUpdate {
+            updated_by: &env::predecessor_account_id(),
+            key: "price_modes",
+            value: format!("{:?} {:?} {:?}", self.price_modes.take_loan, self.price_modes.liquidate, self.price_modes.convert_usdnear),
+        }.emit();
+    }
+
+    /// Parameter manager's method.
+    /// Sets the TWAP window
+    pub fn set_twap_window(&mut self, twap_window_seconds: U64String) {
+        self.assert_role(Role::ParameterManager);
+        assert!(twap_window_seconds.0 > 0, "twap window must be positive");
+        self.twap_window = twap_window_seconds.0 * NANOS_PER_SECOND;
+        events::ConfigUpdate { updated_by: &env::predecessor_account_id(), key: "twap_window_seconds", value: twap_window_seconds.0.to_string() }.emit();
+    }
+
+    /// Returns spot & TWAP prices, and the price used for each use-case
+    pub fn get_twap_info(&self) -> TwapInfoJSON {
+        return TwapInfoJSON {
+            spot_price: self.current_stnear_price.into(),
+            twap_price: self.compute_twap().into(),
+            twap_window_seconds: (self.twap_window / NANOS_PER_SECOND).into(),
+            observations_count: self.price_observations.len(),
+            price_modes: self.price_modes.clone(),
+            take_loan_price: self.price_for(PriceUseCase::TakeLoan).into(),
+            liquidate_price: self.price_for(PriceUseCase::Liquidate).into(),
+            convert_usdnear_price: self.price_for(PriceUseCase::ConvertUsdnear).into(),
+        };
+    }
+
+    /// Returns the price observations ring buffer, oldest first
+    pub fn get_price_observations(&self) -> Vec<PriceObservationJSON> {
+        let mut observations = self.price_observations.to_vec();
+        observations.sort_by_key(|o| o.timestamp);
+        return observations.iter()
+            .map(|o| PriceObservationJSON { price: o.price.into(), timestamp: o.timestamp.into() })
+            .collect();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_utils::*;
+
+    /// price 10 at init, 8 three hours later
+    fn contract_with_price_drop() -> UsdNearStableCoin {
+        let mut contract = new_contract();
+        set_price(&mut contract, 8 * NEAR, NANOS_PER_SECOND + 3 * ONE_HOUR);
+        return contract;
+    }
+
+    #[test]
+    fn twap_weights_prices_by_time() {
+        let contract = contract_with_price_drop();
+        // 3 hours at 10 and 3 hours at 8
+        set_context(ALICE, 0, NANOS_PER_SECOND + 6 * ONE_HOUR);
+        assert_eq!(contract.compute_twap(), 9 * NEAR);
+        // the price at 10 is out of the window
+        set_context(ALICE, 0, NANOS_PER_SECOND + 9 * ONE_HOUR);
+        assert_eq!(contract.compute_twap(), 8 * NEAR);
+    }
+
+    #[test]
+    fn conservative_mode_protects_the_collateral_pool() {
+        let mut contract = contract_with_price_drop();
+        let t = NANOS_PER_SECOND + 6 * ONE_HOUR;
+        set_context(OWNER, 0, t);
+        contract.set_price_modes(PriceModes {
+            take_loan: PriceMode::Conservative,
+            liquidate: PriceMode::Conservative,
+            convert_usdnear: PriceMode::Twap,
+        });
+        let info = contract.get_twap_info();
+        assert_eq!(info.spot_price.0, 8 * NEAR);
+        assert_eq!(info.twap_price.0, 9 * NEAR);
+        // collateral is valued lower when borrowing, higher when liquidating
+        assert_eq!(info.take_loan_price.0, 8 * NEAR);
+        assert_eq!(info.liquidate_price.0, 9 * NEAR);
+        assert_eq!(info.convert_usdnear_price.0, 9 * NEAR);
+    }
+
+    #[test]
+    fn observations_are_a_ring_buffer() {
+        let mut contract = new_contract();
+        for n in 1..=60 {
+            set_context(CONTRACT, 0, NANOS_PER_SECOND + n * ONE_HOUR);
+            contract.record_price_observation(INITIAL_PRICE + n as u128);
+        }
+        let observations = contract.get_price_observations();
+        assert_eq!(observations.len() as u64, MAX_PRICE_OBSERVATIONS);
+        // oldest first, the first 13 were overwritten
+        assert_eq!(observations[0].price.0, INITIAL_PRICE + 13);
+        assert_eq!(observations.last().unwrap().price.0, INITIAL_PRICE + 60);
+    }
+}
@@ -1,7 +1,16 @@
-use near_sdk::json_types::{U128, U64};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{AccountId};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use uint::construct_uint;
+use crate::twap::PriceModes;
+use crate::roles::Role;
+use crate::governance::{GovernanceAction, ProposalStatus};
+use crate::rate_model::RateModel;
+use crate::timelock::ParamsChange;
+use crate::bad_debt::BadDebtConfig;
+use crate::reserve::ReserveConfig;
+use crate::auction::LiquidationMode;
 
 pub const META_POOL_STNEAR_CONTRACT: &str = "meta.pool.testnet";
 
@@ -14,6 +23,9 @@ pub const FIVE_NEAR: u128 = 5 * NEAR;
 pub const TEN_NEAR: u128 = 10 * NEAR;
 pub const NEAR_1K: u128 = 1_000 * NEAR;
 
+/// NEAR protocol storage staking cost: 10^19 yoctoNEAR per byte (100kb per NEAR)
+pub const STORAGE_PRICE_PER_BYTE: u128 = 10_000_000_000_000_000_000;
+
 
 ///To be a liquidator you need to have a borrowing account with at least MIN_STNEAR_BALANCE_FOR_LIQUIDATORS
 pub const MIN_STNEAR_BALANCE_FOR_LIQUIDATORS:u128 = 100*NEAR;
@@ -94,16 +106,21 @@ pub struct GetContractStateResult {
     pub b_accounts_count: U64,
     pub total_collateral_shares: U128,
     pub usdnear_apr_basis_points: u32,
+    /// interest rate model and the APR it sets now (see rate_model.rs)
+    pub rate_model: RateModel,
+    pub current_apr_basis_points: u32,
+    pub utilization_bp: u32,
 }
 
 /// Struct returned from get_contract_params
 /// div-pool parameters info
 /// Represents contact parameters as JSON compatible struct
-#[derive(Serialize, Deserialize)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct ContractParamsJSON {
     pub collateral_basis_points: u32,
     pub min_collateral_basis_points: u32,
+    /// only `true` is applied when params are set, unpausing is the owner's `unpause`
     pub borrowing_paused: bool, 
     pub min_account_balance: U128String,
     pub usdnear_apr_basis_points: u32, //2.5% default
@@ -113,3 +130,404 @@ pub struct ContractParamsJSON {
     /// treasury_cut_basis_points. 
     pub treasury_fee_basis_points: u16,
 }
+
+/// NEP-145 storage balance of an account
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+/// NEP-145 min & max storage deposit for an account
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+pub const FT_METADATA_SPEC: &str = "ft-1.0.0";
+
+/// NEP-148 Fungible Token Metadata
+/// stored in contract state, returned by ft_metadata
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FungibleTokenMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    /// data-url or url of the token icon
+    pub icon: Option<String>,
+    /// link to a json file with more info
+    pub reference: Option<String>,
+    /// sha256 hash of the json file pointed at by the reference field
+    pub reference_hash: Option<Base64VecU8>,
+    pub decimals: u8,
+}
+
+/// Struct returned from get_security_policy
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MethodSecurityPolicyJSON {
+    pub method: String,
+    pub requires_one_yocto: bool,
+    /// required by NEP-141/NEP-145, can not be exempted
+    pub mandatory: bool,
+}
+
+/// Struct returned from get_price_reporters
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceReportJSON {
+    pub reporter_id: AccountId,
+    pub price: U128,
+    /// nanoseconds, 0 => never reported
+    pub timestamp: U64,
+    /// reported within price_max_age
+    pub fresh: bool,
+}
+
+/// Struct returned from get_price_feed_status
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceFeedStatusJSON {
+    pub current_stnear_price: U128,
+    /// nanoseconds
+    pub last_price_timestamp: U64,
+    /// if true, borrowing and stNEAR withdrawals are blocked
+    pub is_stale: bool,
+    pub price_max_age_seconds: U64,
+    pub min_fresh_reports: u16,
+    pub fresh_reports: u16,
+    /// price-oracle contract prices are pulled from, see pull_oracle_price
+    pub oracle_contract_id: Option<AccountId>,
+    pub oracle_asset_id: String,
+    pub oracle_price_decimals: u8,
+}
+
+/// Struct returned from get_twap_info
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TwapInfoJSON {
+    pub spot_price: U128,
+    pub twap_price: U128,
+    pub twap_window_seconds: U64,
+    pub observations_count: u64,
+    pub price_modes: PriceModes,
+    /// price used by take_loan according to price_modes
+    pub take_loan_price: U128,
+    /// price used by liquidate according to price_modes
+    pub liquidate_price: U128,
+    /// price used by convert_usdnear according to price_modes
+    pub convert_usdnear_price: U128,
+}
+
+/// Struct returned from get_price_observations
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceObservationJSON {
+    pub price: U128,
+    /// nanoseconds
+    pub timestamp: U64,
+}
+
+/// Struct returned from get_circuit_breaker_status
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreakerStatusJSON {
+    pub current_stnear_price: U128,
+    pub price_one_hour_ago: U128,
+    pub max_price_change_bp_per_update: u32,
+    pub max_price_change_bp_per_hour: u32,
+    /// price held by the circuit breaker, waiting for the guardian
+    pub pending_price: Option<U128>,
+    pub pending_price_timestamp: Option<U64>,
+    pub pending_price_reported_by: Option<AccountId>,
+    pub borrowing_paused: bool,
+}
+
+/// Struct returned from get_roles
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleMembersJSON {
+    pub role: Role,
+    pub members: Vec<AccountId>,
+}
+
+/// Struct returned from get_params_proposals
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParamsProposalJSON {
+    pub proposal_id: U64,
+    pub proposed_by: AccountId,
+    pub proposed_at: U64,
+    /// earliest execution timestamp
+    pub eta: U64,
+    pub executable: bool,
+    pub expired: bool,
+    pub change: ParamsChange,
+}
+
+/// Struct returned from get_stbl_mint_schedule
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblMintScheduleJSON {
+    pub emission_per_second: U128,
+    pub max_supply: U128,
+    pub total_supply: U128,
+    /// emitted and not yet minted, mintable now
+    pub mintable: U128,
+}
+
+/// Struct returned from get_stbl_mining_info
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblMiningInfoJSON {
+    pub stbl_per_second: U128,
+    /// share of the emission for borrowers, the rest goes to stNEAR depositors
+    pub debt_pool_bp: u32,
+    /// total USDNEAR debt shares
+    pub total_debt_stake: U128,
+    /// total free stNEAR shares
+    pub total_free_stake: U128,
+    /// total locked collateral shares
+    pub total_locked_stake: U128,
+    /// stNEAR value of the free and locked stakes, splits the deposit pool emission
+    pub total_deposit_stnear: U128,
+    pub debt_acc: U128,
+    pub free_acc: U128,
+    pub locked_acc: U128,
+    /// STBL emitted and not claimed yet
+    pub unclaimed_stbl: U128,
+}
+
+/// Struct returned from get_stbl_staking_info
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblStakingInfoJSON {
+    pub total_staked_stbl: U128,
+    /// share of the epoch interest for STBL stakers
+    pub fee_share_bp: u32,
+    pub unstake_cooldown_seconds: U64,
+    pub total_distributed_stnear: U128,
+    /// stNEAR distributed and not claimed yet
+    pub unclaimed_stnear: U128,
+}
+
+/// Struct returned from get_stbl_staking_account
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StblStakingAccountJSON {
+    pub account_id: AccountId,
+    pub staked_stbl: U128,
+    pub unstaking_stbl: U128,
+    pub unstaking_available_at: U64,
+    /// stNEAR to claim
+    pub rewards_stnear: U128,
+}
+
+/// Struct returned from get_governance_proposals
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceProposalJSON {
+    pub proposal_id: U64,
+    pub status: ProposalStatus,
+    pub action: GovernanceAction,
+    pub description: String,
+    pub proposer: AccountId,
+    pub created_at: U64,
+    pub voting_ends_at: U64,
+    /// total staked STBL before creation
+    pub total_voting_power: U128,
+    pub yes_votes: U128,
+    pub no_votes: U128,
+}
+
+/// Struct returned from get_governance_vote
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceVoteJSON {
+    pub voting_power: U128,
+    /// true: yes, false: no, None: not voted (votes are kept while the proposal is active)
+    pub vote: Option<bool>,
+}
+
+/// Struct returned from get_epoch_records
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EpochRecordJSON {
+    pub epoch_height: U64,
+    pub timestamp: U64,
+    pub rewards_stnear: U128,
+    pub interest_stnear: U128,
+    pub stakers_stnear: U128,
+    pub operator_stnear: U128,
+    pub treasury_stnear: U128,
+    pub reserve_stnear: U128,
+}
+
+/// Struct returned from get_fee_totals
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTotalsJSON {
+    pub operator_account_id: AccountId,
+    pub operator_fee_basis_points: u16,
+    pub total_operator_fees_stnear: U128,
+    pub treasury_account_id: AccountId,
+    pub treasury_fee_basis_points: u16,
+    pub total_treasury_fees_stnear: U128,
+    pub total_stakers_fees_stnear: U128,
+}
+
+/// Struct returned from get_account_interest
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountInterestJSON {
+    pub account_id: AccountId,
+    pub normalized_debt: U128,
+    pub borrow_index: U128,
+    /// normalized_debt * borrow_index
+    pub outstanding_loans_usdnear: U128,
+    /// part of outstanding_loans_usdnear that is accrued interest not paid yet
+    pub accrued_interest_usdnear: U128,
+}
+
+/// Struct returned from get_borrow_index_info
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BorrowIndexInfoJSON {
+    pub borrow_index: U128,
+    pub current_apr_basis_points: u32,
+    pub total_normalized_debt: U128,
+    /// total_normalized_debt * borrow_index
+    pub total_debt_usdnear: U128,
+    /// USDNEAR in circulation
+    pub total_usdnear: U128,
+    /// accrued interest not yet paid, paid every epoch with collateral staking rewards
+    pub unpaid_interest_usdnear: U128,
+    pub total_interest_paid_usdnear: U128,
+}
+
+/// Struct returned from get_interest_rate_info
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InterestRateInfoJSON {
+    pub rate_model: RateModel,
+    pub current_apr_basis_points: u32,
+    /// fixed rate model APR (contract params)
+    pub usdnear_apr_basis_points: u32,
+    pub utilization_bp: u32,
+    pub peg_controller_apr_bp: u32,
+    pub usdnear_market_price: U128,
+    pub usdnear_market_price_timestamp: U64,
+    pub epoch_converted_usdnear: U128,
+}
+
+/// Struct returned from get_liquidatable_accounts & preview_liquidation
+/// same amounts liquidate would use now
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidationPreviewJSON {
+    pub loan_account_id: AccountId,
+    /// collateralization_ratio < min_collateral_basis_points
+    pub liquidatable: bool,
+    /// liquidation price (price_modes.liquidate)
+    pub stnear_price_usd: U128,
+    pub collateralization_ratio: u32,
+    pub outstanding_loans_usdnear: U128,
+    pub locked_stnear: U128,
+    pub valued_collateral_usd: U128,
+    /// valued collateral < outstanding loans
+    pub underwater: bool,
+    /// USDNEAR to repay to restore collateral_basis_points (auction: the USDNEAR the auction still raises)
+    pub max_usdnear_repay: U128,
+    pub usdnear_repay: U128,
+    /// stNEAR the liquidator receives for usdnear_repay
+    pub stnear_to_receive: U128,
+    /// reserve share of the liquidation fee
+    pub stnear_to_reserve: U128,
+    /// auction: the amounts above are a bid on `auction` at its current price (0 if there's no running auction)
+    pub liquidation_mode: LiquidationMode,
+    /// running auction (auction mode), None if it must be started or restarted
+    pub auction: Option<AuctionJSON>,
+}
+
+/// Struct returned from get_stability_pool_info
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StabilityPoolInfoJSON {
+    pub total_deposits_usdnear: U128,
+    /// stNEAR seized in liquidations and not claimed by depositors
+    pub unclaimed_gains_stnear: U128,
+    pub product_p: U128,
+    pub current_scale: U64,
+    pub current_epoch: U64,
+    pub total_usdnear_burned: U128,
+    pub total_stnear_seized: U128,
+}
+
+/// Struct returned from get_stability_deposit
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StabilityDepositJSON {
+    pub account_id: AccountId,
+    /// deposit after liquidations
+    pub deposit_usdnear: U128,
+    pub gains_stnear: U128,
+}
+
+/// Struct returned from get_auction & get_auctions
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionJSON {
+    pub loan_account_id: AccountId,
+    pub started_at: U64,
+    /// USD per stNEAR (24 decimals)
+    pub start_price: U128,
+    pub floor_price: U128,
+    pub current_price: U128,
+    /// older than the auction duration, can be restarted
+    pub expired: bool,
+    pub usdnear_to_raise: U128,
+    pub stnear_for_sale: U128,
+}
+
+/// Struct returned from get_bad_debt_info
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BadDebtInfoJSON {
+    /// shortfall of the positions written off since deployment
+    pub total_bad_debt: U128,
+    /// USDNEAR in circulation not backed by debt yet
+    pub uncovered_bad_debt: U128,
+    /// covered with reserve stNEAR (including the seized collateral)
+    pub total_bad_debt_covered_by_reserve: U128,
+    pub total_bad_debt_socialized: U128,
+    pub reserve_stnear: U128,
+    pub config: BadDebtConfig,
+}
+
+/// Struct returned from get_reserve_info
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReserveInfoJSON {
+    pub reserve_stnear: U128,
+    /// at the current stNEAR price
+    pub reserve_usd: U128,
+    pub config: ReserveConfig,
+    pub total_inflows_stnear: U128,
+    /// moved to the treasury above target_usd
+    pub total_overflow_stnear: U128,
+}
+
+/// Struct returned from get_reserve_records
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReserveRecordJSON {
+    pub timestamp: U64,
+    pub action: String,
+    pub inflow: bool,
+    pub stnear_amount: U128,
+    pub reserve_stnear_after: U128,
+}